@@ -0,0 +1,692 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    arg_bytes, arg_int, check_arity, clamp_range, is_keyword, parse_int, CommandError,
+    CommandResult, Database, RedisValueRef, SetObject, StoredValue, MAX_STRING_SIZE,
+};
+
+/// Bits in the largest string a bit command may build
+const MAX_BITS: u64 = MAX_STRING_SIZE as u64 * 8;
+
+/// SETBIT key offset value, returns the previous bit
+pub fn handle_setbit(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("setbit", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let offset = parse_bit_offset(&args[1])?;
+    let bit = match arg_bytes(&args[2])?.as_ref() {
+        b"0" => false,
+        b"1" => true,
+        _ => return Err(CommandError::BitValue),
+    };
+
+    let value = extended(db, key, offset + 1)?;
+    let previous = get_bit(value, offset);
+    set_bit(value, offset, bit);
+    db.signal_modified(key);
+    Ok(RedisValueRef::Int(previous as i64))
+}
+
+/// GETBIT key offset, bits past the end of the string are 0
+pub fn handle_getbit(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("getbit", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let offset = parse_bit_offset(&args[1])?;
+    let bit = db
+        .get_string_bytes(key)?
+        .is_some_and(|value| get_bit(value, offset));
+    Ok(RedisValueRef::Int(bit as i64))
+}
+
+/// BITCOUNT key [start end [BYTE | BIT]]
+pub fn handle_bitcount(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("bitcount", args, 1, Some(4))?;
+    let key = arg_bytes(&args[0])?;
+    let range = match &args[1..] {
+        [] => None,
+        [start, end] => Some((arg_int(start)?, arg_int(end)?, false)),
+        [start, end, unit] => Some((arg_int(start)?, arg_int(end)?, parse_bit_unit(unit)?)),
+        _ => return Err(CommandError::Syntax),
+    };
+
+    let Some(value) = db.get_string_bytes(key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let count = match bit_range(value, range) {
+        Some((start, end)) => count_ones(value, start, end),
+        None => 0,
+    };
+    Ok(RedisValueRef::Int(count as i64))
+}
+
+/// BITPOS key bit [start [end [BYTE | BIT]]]. Looking for a clear bit
+/// without an explicit end finds the first bit past the string when every
+/// bit is set, as the string is treated as padded with zeros.
+pub fn handle_bitpos(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("bitpos", args, 2, Some(5))?;
+    let key = arg_bytes(&args[0])?;
+    let bit = match arg_int(&args[1])? {
+        0 => false,
+        1 => true,
+        _ => return Err(CommandError::BitposBit),
+    };
+    let start = args.get(2).map(arg_int).transpose()?.unwrap_or(0);
+    let end = args.get(3).map(arg_int).transpose()?;
+    let in_bits = args
+        .get(4)
+        .map(parse_bit_unit)
+        .transpose()?
+        .unwrap_or(false);
+
+    let Some(value) = db.get_string_bytes(key)? else {
+        return Ok(RedisValueRef::Int(if bit { -1 } else { 0 }));
+    };
+    let end_given = end.is_some();
+    let range = Some((start, end.unwrap_or(-1), in_bits));
+    let Some((start, end)) = bit_range(value, range) else {
+        return Ok(RedisValueRef::Int(-1));
+    };
+    let position = match find_bit(value, bit, start, end) {
+        Some(position) => position as i64,
+        None if !bit && !end_given => value.len() as i64 * 8,
+        None => -1,
+    };
+    Ok(RedisValueRef::Int(position))
+}
+
+/// BITOP AND | OR | XOR | NOT destination key [key ...], returns the length
+/// of the stored string. Shorter strings are padded with zeros.
+pub fn handle_bitop(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("bitop", args, 3, None)?;
+    let operation = &args[0];
+    let destination = arg_bytes(&args[1])?;
+    let keys = &args[2..];
+    // None for NOT, which takes a single source
+    let combine: Option<fn(u8, u8) -> u8> = if is_keyword(operation, "and") {
+        Some(|a, b| a & b)
+    } else if is_keyword(operation, "or") {
+        Some(|a, b| a | b)
+    } else if is_keyword(operation, "xor") {
+        Some(|a, b| a ^ b)
+    } else if is_keyword(operation, "not") {
+        if keys.len() != 1 {
+            return Err(CommandError::BitopNotArity);
+        }
+        None
+    } else {
+        return Err(CommandError::Syntax);
+    };
+
+    let mut sources = Vec::with_capacity(keys.len());
+    for key in keys {
+        sources.push(db.get_string(arg_bytes(key)?)?.cloned().unwrap_or_default());
+    }
+    let len = sources.iter().map(|source| source.len()).max().unwrap_or(0);
+    let mut result = BytesMut::zeroed(len);
+    for (i, byte) in result.iter_mut().enumerate() {
+        let mut sources = sources
+            .iter()
+            .map(|source| source.get(i).copied().unwrap_or(0));
+        let first = sources.next().unwrap_or(0);
+        *byte = match combine {
+            Some(combine) => sources.fold(first, combine),
+            None => !first,
+        };
+    }
+
+    // An empty result deletes the destination, like every empty value
+    if result.is_empty() {
+        db.remove(destination);
+    } else {
+        db.insert(
+            destination.clone(),
+            SetObject::new(StoredValue::String(result.freeze().into()), None),
+        );
+    }
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// BITFIELD key [GET type offset] [SET type offset value] [INCRBY type
+/// offset increment] [OVERFLOW WRAP | SAT | FAIL] and BITFIELD_RO key [GET
+/// type offset ...]. Replies with one value per GET, SET and INCRBY, null
+/// for writes that failed under OVERFLOW FAIL.
+pub fn handle_bitfield(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 1, None)?;
+    let key = arg_bytes(&args[0])?;
+    let read_only = name == "bitfield_ro";
+
+    // Parse every operation before touching the string
+    let mut operations = Vec::new();
+    let mut overflow = Overflow::Wrap;
+    let mut rest = &args[1..];
+    while let Some(subcommand) = rest.first() {
+        if is_keyword(subcommand, "overflow") {
+            let mode = rest.get(1).ok_or(CommandError::Syntax)?;
+            overflow = if is_keyword(mode, "wrap") {
+                Overflow::Wrap
+            } else if is_keyword(mode, "sat") {
+                Overflow::Sat
+            } else if is_keyword(mode, "fail") {
+                Overflow::Fail
+            } else {
+                return Err(CommandError::BitfieldOverflow);
+            };
+            rest = &rest[2..];
+            continue;
+        }
+
+        let write = if is_keyword(subcommand, "get") {
+            None
+        } else if is_keyword(subcommand, "set") {
+            Some(false)
+        } else if is_keyword(subcommand, "incrby") {
+            Some(true)
+        } else {
+            return Err(CommandError::Syntax);
+        };
+        let used = if write.is_some() { 4 } else { 3 };
+        if rest.len() < used {
+            return Err(CommandError::Syntax);
+        }
+        let field = Field::parse(&rest[1], &rest[2])?;
+        let operation = match write {
+            None => FieldOperation::Get(field),
+            Some(incr) => {
+                if read_only {
+                    return Err(CommandError::BitfieldReadOnly);
+                }
+                let amount = arg_int(&rest[3])?;
+                match incr {
+                    true => FieldOperation::IncrBy(field, amount, overflow),
+                    false => FieldOperation::Set(field, amount, overflow),
+                }
+            }
+        };
+        operations.push(operation);
+        rest = &rest[used..];
+    }
+
+    // Writes extend the string to cover every field written
+    let write_end = operations
+        .iter()
+        .filter_map(|operation| match operation {
+            FieldOperation::Get(_) => None,
+            FieldOperation::Set(field, ..) | FieldOperation::IncrBy(field, ..) => {
+                Some(field.offset + field.bits as u64)
+            }
+        })
+        .max();
+    let mut replies = Vec::with_capacity(operations.len());
+    let Some(bits) = write_end else {
+        let value = db.get_string_bytes(key)?.unwrap_or_default();
+        for operation in &operations {
+            if let FieldOperation::Get(field) = operation {
+                replies.push(RedisValueRef::Int(field.get(value)));
+            }
+        }
+        return Ok(RedisValueRef::Array(replies));
+    };
+
+    let value = extended(db, key, bits)?;
+    for operation in &operations {
+        let reply = match *operation {
+            FieldOperation::Get(field) => Some(field.get(value)),
+            FieldOperation::Set(field, new, overflow) => {
+                let old = field.get(value);
+                field.fit(new as i128, overflow).map(|new| {
+                    field.set(value, new);
+                    old
+                })
+            }
+            FieldOperation::IncrBy(field, amount, overflow) => {
+                let old = field.get(value);
+                let new = field.fit(old as i128 + amount as i128, overflow);
+                if let Some(new) = new {
+                    field.set(value, new);
+                }
+                new
+            }
+        };
+        replies.push(reply.map_or(RedisValueRef::NullBulkString, RedisValueRef::Int));
+    }
+    db.signal_modified(key);
+    Ok(RedisValueRef::Array(replies))
+}
+
+/// How BITFIELD writes handle values that do not fit the field
+#[derive(Debug, Clone, Copy)]
+enum Overflow {
+    Wrap,
+    /// Saturate at the smallest or largest value
+    Sat,
+    /// Skip the write and reply with null
+    Fail,
+}
+
+#[derive(Debug, Clone, Copy)]
+enum FieldOperation {
+    Get(Field),
+    Set(Field, i64, Overflow),
+    IncrBy(Field, i64, Overflow),
+}
+
+/// An integer field at a bit offset, `i1` to `i64` or `u1` to `u63`
+#[derive(Debug, Clone, Copy)]
+struct Field {
+    signed: bool,
+    bits: u32,
+    offset: u64,
+}
+
+impl Field {
+    /// Parse a type like `u8` and an offset, where `#n` is the n-th field of
+    /// that type
+    fn parse(kind: &RedisValueRef, offset: &RedisValueRef) -> Result<Field, CommandError> {
+        let kind = arg_bytes(kind)?;
+        let (signed, max_bits) = match kind.first() {
+            Some(b'i' | b'I') => (true, 64),
+            Some(b'u' | b'U') => (false, 63),
+            _ => return Err(CommandError::BitfieldType),
+        };
+        let bits = parse_int(&kind[1..])
+            .filter(|bits| (1..=max_bits).contains(bits))
+            .ok_or(CommandError::BitfieldType)? as u32;
+
+        let offset = arg_bytes(offset)?;
+        let (offset, multiply) = match offset.strip_prefix(b"#") {
+            Some(index) => (index, bits as i64),
+            None => (offset.as_ref(), 1),
+        };
+        let offset = parse_int(offset)
+            .and_then(|offset| offset.checked_mul(multiply))
+            .filter(|offset| *offset >= 0 && (*offset as u64) + (bits as u64) <= MAX_BITS)
+            .ok_or(CommandError::BitOffset)?;
+        Ok(Field {
+            signed,
+            bits,
+            offset: offset as u64,
+        })
+    }
+
+    /// Read the field, bits past the end of the string are 0
+    fn get(&self, value: &[u8]) -> i64 {
+        let mut raw = 0u64;
+        for i in 0..self.bits as u64 {
+            raw = (raw << 1) | get_bit(value, self.offset + i) as u64;
+        }
+        if self.signed && self.bits < 64 && raw >> (self.bits - 1) & 1 == 1 {
+            // Sign extend
+            raw |= u64::MAX << self.bits;
+        }
+        raw as i64
+    }
+
+    /// Write the field, which must fit in the string
+    fn set(&self, value: &mut [u8], new: i64) {
+        let raw = new as u64;
+        for i in 0..self.bits as u64 {
+            let bit = raw >> (self.bits as u64 - 1 - i) & 1 == 1;
+            set_bit(value, self.offset + i, bit);
+        }
+    }
+
+    /// Bring a value into the field's range, None if it does not fit and
+    /// overflow is FAIL
+    fn fit(&self, value: i128, overflow: Overflow) -> Option<i64> {
+        let (min, max) = match self.signed {
+            true => (-(1i128 << (self.bits - 1)), (1i128 << (self.bits - 1)) - 1),
+            false => (0, (1i128 << self.bits) - 1),
+        };
+        if (min..=max).contains(&value) {
+            return Some(value as i64);
+        }
+        match overflow {
+            Overflow::Wrap => Some(((value - min).rem_euclid(1i128 << self.bits) + min) as i64),
+            Overflow::Sat => Some(value.clamp(min, max) as i64),
+            Overflow::Fail => None,
+        }
+    }
+}
+
+/// Parse a SETBIT or GETBIT offset, which must lie within the largest string
+fn parse_bit_offset(arg: &RedisValueRef) -> Result<u64, CommandError> {
+    parse_int(arg_bytes(arg)?)
+        .filter(|offset| (0..MAX_BITS as i64).contains(offset))
+        .map(|offset| offset as u64)
+        .ok_or(CommandError::BitOffset)
+}
+
+/// Parse BYTE or BIT, true for BIT
+fn parse_bit_unit(arg: &RedisValueRef) -> Result<bool, CommandError> {
+    if is_keyword(arg, "bit") {
+        Ok(true)
+    } else if is_keyword(arg, "byte") {
+        Ok(false)
+    } else {
+        Err(CommandError::Syntax)
+    }
+}
+
+/// Turn an optional `(start, end, in_bits)` range, whose indexes may count
+/// from the end, into an inclusive range of bits within the string
+fn bit_range(value: &[u8], range: Option<(i64, i64, bool)>) -> Option<(u64, u64)> {
+    let bits = value.len() * 8;
+    match range {
+        None => clamp_range(0, -1, bits).map(|(start, end)| (start as u64, end as u64)),
+        Some((start, end, true)) => {
+            clamp_range(start, end, bits).map(|(start, end)| (start as u64, end as u64))
+        }
+        Some((start, end, false)) => clamp_range(start, end, value.len())
+            .map(|(start, end)| (start as u64 * 8, end as u64 * 8 + 7)),
+    }
+}
+
+/// Count the set bits in an inclusive range of bits
+fn count_ones(value: &[u8], start: u64, end: u64) -> u64 {
+    let (first, last) = ((start / 8) as usize, (end / 8) as usize);
+    let mut count: u64 = value[first..=last]
+        .iter()
+        .map(|byte| byte.count_ones() as u64)
+        .sum();
+    // Leave out the bits of the end bytes that lie outside the range
+    let head = (start % 8) as u32;
+    if head > 0 {
+        count -= (value[first] >> (8 - head)).count_ones() as u64;
+    }
+    let tail = 7 - (end % 8) as u32;
+    count -= (value[last] & ((1u16 << tail) - 1) as u8).count_ones() as u64;
+    count
+}
+
+/// Find the first bit equal to `bit` in an inclusive range of bits
+fn find_bit(value: &[u8], bit: bool, start: u64, end: u64) -> Option<u64> {
+    // Bytes made only of the other bit are skipped whole
+    let skip = if bit { 0x00 } else { 0xff };
+    let mut position = start;
+    while position <= end {
+        let byte = value[(position / 8) as usize];
+        if position & 7 == 0 && position + 7 <= end && byte == skip {
+            position += 8;
+            continue;
+        }
+        if get_bit(value, position) == bit {
+            return Some(position);
+        }
+        position += 1;
+    }
+    None
+}
+
+/// The string at a key as a mutable buffer of at least `bits` bits, zero
+/// padded and created if missing. Callers must report changes with
+/// `Database::signal_modified`.
+fn extended<'a>(
+    db: &'a mut Database,
+    key: &Bytes,
+    bits: u64,
+) -> Result<&'a mut BytesMut, CommandError> {
+    let value = db.get_string_mut(key)?;
+    let len = bits.div_ceil(8) as usize;
+    if value.len() < len {
+        value.resize(len, 0);
+    }
+    Ok(value)
+}
+
+/// Read a bit, where bit 0 is the most significant bit of the first byte
+fn get_bit(value: &[u8], offset: u64) -> bool {
+    value
+        .get((offset / 8) as usize)
+        .is_some_and(|byte| byte & (0x80 >> (offset % 8)) != 0)
+}
+
+/// Write a bit that lies within the string
+fn set_bit(value: &mut [u8], offset: u64, bit: bool) {
+    let byte = &mut value[(offset / 8) as usize];
+    let mask = 0x80 >> (offset % 8);
+    if bit {
+        *byte |= mask;
+    } else {
+        *byte &= !mask;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args, current_time_millis, SetExpiry};
+
+    fn string(db: &mut Database, key: &[u8]) -> Vec<u8> {
+        db.get_string_bytes(key).unwrap().unwrap().to_vec()
+    }
+
+    #[test]
+    fn setbit_writes_in_place() {
+        let mut db = Database::new();
+        assert_eq!(
+            handle_setbit(&args("k 7 1"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(
+            handle_setbit(&args("k 7 1"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(string(&mut db, b"k"), b"\x01");
+        assert_eq!(
+            handle_setbit(&args("k 17 1"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(string(&mut db, b"k"), b"\x01\x00\x40");
+        assert_eq!(
+            handle_getbit(&args("k 17"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(
+            handle_getbit(&args("k 1000"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+
+        // Later writes inside the string reuse its buffer
+        handle_setbit(&args("k 8000 1"), &mut db).unwrap();
+        let buffer = db.get_string_bytes(b"k").unwrap().unwrap().as_ptr();
+        handle_setbit(&args("k 5 1"), &mut db).unwrap();
+        handle_setbit(&args("k 8000 0"), &mut db).unwrap();
+        handle_bitfield("bitfield", &args("k SET u8 16 255"), &mut db).unwrap();
+        assert_eq!(db.get_string_bytes(b"k").unwrap().unwrap().as_ptr(), buffer);
+        assert_eq!(&string(&mut db, b"k")[..3], b"\x05\x00\xff");
+
+        // The TTL survives
+        db.set_expiry(b"k", Some(current_time_millis() + 100_000));
+        handle_setbit(&args("k 0 1"), &mut db).unwrap();
+        handle_bitfield("bitfield", &args("k INCRBY u8 0 1"), &mut db).unwrap();
+        assert!(db.get(b"k").unwrap().expires_at.is_some());
+
+        db.insert(
+            Bytes::from("list"),
+            SetObject::new(StoredValue::List(Default::default()), None),
+        );
+        assert_eq!(
+            handle_setbit(&args("list 0 1"), &mut db),
+            Err(CommandError::WrongType)
+        );
+        assert_eq!(
+            handle_setbit(&args("k 0 2"), &mut db),
+            Err(CommandError::BitValue)
+        );
+    }
+
+    #[test]
+    fn bitpos() {
+        let mut db = Database::new();
+        db.set(
+            Bytes::from("k"),
+            Bytes::from_static(b"\xff\xf0\x00"),
+            SetExpiry::Persist,
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 0"), &mut db),
+            Ok(RedisValueRef::Int(12))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 1"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 1 1"), &mut db),
+            Ok(RedisValueRef::Int(8))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 1 2"), &mut db),
+            Ok(RedisValueRef::Int(-1))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 1 -2 -1"), &mut db),
+            Ok(RedisValueRef::Int(8))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 0 5 11 BIT"), &mut db),
+            Ok(RedisValueRef::Int(-1))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 0 5 12 BIT"), &mut db),
+            Ok(RedisValueRef::Int(12))
+        );
+
+        // A clear bit is found past the end unless the range has an end
+        db.set(
+            Bytes::from("k"),
+            Bytes::from_static(b"\xff\xff"),
+            SetExpiry::Persist,
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 0"), &mut db),
+            Ok(RedisValueRef::Int(16))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 0 0"), &mut db),
+            Ok(RedisValueRef::Int(16))
+        );
+        assert_eq!(
+            handle_bitpos(&args("k 0 0 -1"), &mut db),
+            Ok(RedisValueRef::Int(-1))
+        );
+
+        assert_eq!(
+            handle_bitpos(&args("missing 0"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(
+            handle_bitpos(&args("missing 1"), &mut db),
+            Ok(RedisValueRef::Int(-1))
+        );
+    }
+
+    #[test]
+    fn bitop() {
+        let mut db = Database::new();
+        db.set(
+            Bytes::from("a"),
+            Bytes::from_static(b"\xf0\x0f\xff"),
+            SetExpiry::Persist,
+        );
+        db.set(
+            Bytes::from("b"),
+            Bytes::from_static(b"\x3c"),
+            SetExpiry::Persist,
+        );
+
+        // The shorter source is padded with zeros
+        assert_eq!(
+            handle_bitop(&args("AND d a b"), &mut db),
+            Ok(RedisValueRef::Int(3))
+        );
+        assert_eq!(string(&mut db, b"d"), b"\x30\x00\x00");
+        assert_eq!(
+            handle_bitop(&args("OR d a b"), &mut db),
+            Ok(RedisValueRef::Int(3))
+        );
+        assert_eq!(string(&mut db, b"d"), b"\xfc\x0f\xff");
+        assert_eq!(
+            handle_bitop(&args("XOR d a b missing"), &mut db),
+            Ok(RedisValueRef::Int(3))
+        );
+        assert_eq!(string(&mut db, b"d"), b"\xcc\x0f\xff");
+        assert_eq!(
+            handle_bitop(&args("NOT d b"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(string(&mut db, b"d"), b"\xc3");
+
+        // An empty result deletes the destination
+        assert_eq!(
+            handle_bitop(&args("OR d missing"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert!(!db.exists(b"d"));
+
+        assert_eq!(
+            handle_bitop(&args("NOT d a b"), &mut db),
+            Err(CommandError::BitopNotArity)
+        );
+        assert_eq!(
+            handle_bitop(&args("NAND d a"), &mut db),
+            Err(CommandError::Syntax)
+        );
+    }
+
+    #[test]
+    fn bitfield_overflow_modes() {
+        let mut db = Database::new();
+        let bitfield = |line: &str, db: &mut Database| handle_bitfield("bitfield", &args(line), db);
+        let ints = |values: &[i64]| {
+            Ok(RedisValueRef::Array(
+                values
+                    .iter()
+                    .map(|&value| RedisValueRef::Int(value))
+                    .collect(),
+            ))
+        };
+
+        assert_eq!(
+            bitfield("k SET i8 0 100 INCRBY i8 0 100", &mut db),
+            ints(&[0, -56])
+        );
+        assert_eq!(
+            bitfield("k OVERFLOW SAT INCRBY i8 0 -100 INCRBY u4 8 20", &mut db),
+            ints(&[-128, 15])
+        );
+        assert_eq!(
+            bitfield("k OVERFLOW WRAP INCRBY u4 8 1", &mut db),
+            ints(&[0])
+        );
+        assert_eq!(
+            bitfield(
+                "k OVERFLOW FAIL INCRBY u4 8 16 INCRBY u4 8 15 GET u4 8",
+                &mut db
+            ),
+            Ok(RedisValueRef::Array(vec![
+                RedisValueRef::NullBulkString,
+                RedisValueRef::Int(15),
+                RedisValueRef::Int(15),
+            ]))
+        );
+        assert_eq!(
+            bitfield("k OVERFLOW FAIL SET i8 0 200", &mut db),
+            Ok(RedisValueRef::Array(vec![RedisValueRef::NullBulkString]))
+        );
+        assert_eq!(string(&mut db, b"k"), b"\x80\xf0");
+
+        // Reads never create or extend the string
+        assert_eq!(bitfield("k GET u8 100", &mut db), ints(&[0]));
+        assert_eq!(bitfield("missing GET u8 0", &mut db), ints(&[0]));
+        assert!(!db.exists(b"missing"));
+        assert_eq!(string(&mut db, b"k"), b"\x80\xf0");
+        assert_eq!(
+            handle_bitfield("bitfield_ro", &args("k SET u8 0 1"), &mut db),
+            Err(CommandError::BitfieldReadOnly)
+        );
+    }
+}
@@ -0,0 +1,101 @@
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use tokio::sync::oneshot;
+
+use crate::{
+    arg_bytes, parse_float, restore_list_waiter, serve_list_waiter, serve_stream_waiter,
+    BlockedOperation, CommandError, CommandResult, Database, RedisValueRef,
+};
+
+/// Wait for a client parked with `Database::block` to be served. On timeout
+/// the client is unblocked and gets a null reply. `None` waits forever.
+/// When `closed` resolves first the connection went away, and the client is
+/// unblocked so no data is handed to it.
+pub async fn wait_until_served(
+    client_id: u64,
+    store: &Arc<Mutex<Database>>,
+    mut receiver: oneshot::Receiver<RedisValueRef>,
+    timeout: Option<Duration>,
+    closed: impl Future<Output = ()>,
+) -> CommandResult {
+    let served = async {
+        match timeout {
+            Some(timeout) => tokio::time::timeout(timeout, &mut receiver).await.ok(),
+            None => Some((&mut receiver).await),
+        }
+    };
+    let reply = tokio::select! {
+        reply = served => reply,
+        _ = closed => None,
+    };
+    if let Some(Ok(reply)) = reply {
+        return Ok(reply);
+    }
+    // Timed out or gone, though a write may have served us before we got the lock
+    let mut db = store.lock().unwrap();
+    db.unblock(client_id);
+    Ok(receiver.try_recv().unwrap_or(RedisValueRef::NullArray))
+}
+
+/// Serve clients blocked on keys that were written to, longest waiting
+/// first. Called after every command while the database lock is still held,
+/// so no other client can take the new data first.
+pub fn serve_blocked(db: &mut Database) {
+    loop {
+        let ready = db.take_ready_keys();
+        if ready.is_empty() {
+            return;
+        }
+        for key in ready {
+            for client_id in db.waiters(&key) {
+                let Some(client) = db.blocked_client(client_id) else {
+                    continue;
+                };
+                // The connection went away without unblocking
+                if client.sender.is_closed() {
+                    db.unblock(client_id);
+                    continue;
+                }
+                let operation = client.operation.clone();
+                let reply = match operation {
+                    BlockedOperation::Pop(_) | BlockedOperation::Move { .. } => {
+                        serve_list_waiter(db, &key, &operation)
+                    }
+                    BlockedOperation::Read { .. } | BlockedOperation::ReadGroup { .. } => {
+                        serve_stream_waiter(db, &key, &operation)
+                    }
+                };
+                if let Some(reply) = reply {
+                    let client = db.unblock(client_id).expect("client is blocked");
+                    let reply = reply.unwrap_or_else(RedisValueRef::from);
+                    if let Err(reply) = client.sender.send(reply) {
+                        // The client went away after all. Stream reads take
+                        // nothing, entries delivered to a group stay pending.
+                        if let BlockedOperation::Pop(_) | BlockedOperation::Move { .. } = operation
+                        {
+                            restore_list_waiter(db, &key, &operation, reply);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Parse a blocking timeout in seconds, None waits forever
+pub fn parse_timeout(arg: &RedisValueRef) -> Result<Option<Duration>, CommandError> {
+    let seconds = parse_float(arg_bytes(arg)?).ok_or(CommandError::InvalidTimeout)?;
+    if seconds < 0.0 {
+        return Err(CommandError::NegativeTimeout);
+    }
+    if seconds == 0.0 {
+        return Ok(None);
+    }
+    Duration::try_from_secs_f64(seconds)
+        .map(Some)
+        .map_err(|_| CommandError::InvalidTimeout)
+}
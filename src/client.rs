@@ -0,0 +1,37 @@
+use bytes::Bytes;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use crate::{Key, Protocol, Transaction};
+
+// Source of unique ids handed to connections, as reported by HELLO
+static NEXT_CLIENT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// State kept for a single client connection
+#[derive(Debug)]
+pub struct Client {
+    pub id: u64,
+    pub protocol: Protocol,
+    pub name: Option<Bytes>,
+    /// Commands queued since MULTI, None outside a transaction
+    pub transaction: Option<Transaction>,
+    /// Keys watched for the next EXEC
+    pub watched: Vec<Key>,
+}
+
+impl Client {
+    pub fn new() -> Client {
+        Client {
+            id: NEXT_CLIENT_ID.fetch_add(1, Ordering::Relaxed),
+            protocol: Protocol::Resp2,
+            name: None,
+            transaction: None,
+            watched: Vec::new(),
+        }
+    }
+}
+
+impl Default for Client {
+    fn default() -> Self {
+        Self::new()
+    }
+}
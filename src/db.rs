@@ -1,51 +1,812 @@
+use bytes::{Bytes, BytesMut};
+use rand::Rng;
 use std::{
-    collections::HashMap,
+    collections::{hash_map::DefaultHasher, BTreeMap, BTreeSet, HashMap, HashSet, VecDeque},
+    fmt,
+    hash::Hasher,
     time::{Duration, Instant},
 };
+use tokio::sync::oneshot;
 
-#[derive(Clone, Debug, Default)]
+use crate::{
+    current_time_millis, glob_match, CommandError, End, Hash, Key, RedisValueRef, Set, SortedSet,
+    Value,
+};
+
+/// Keys sampled per round of the active expire cycle, as in Redis
+const ACTIVE_EXPIRE_SAMPLE: usize = 20;
+/// Time budget for one active expire cycle
+const ACTIVE_EXPIRE_BUDGET: Duration = Duration::from_millis(25);
+
+#[derive(Debug, Default)]
 pub struct Database {
-    pub store: HashMap<String, SetObject>,
+    store: HashMap<Key, SetObject>,
+    // Keys that carry a TTL, kept apart so they can be sampled cheaply
+    volatile: VolatileKeys,
+    // Every key ordered by its scan hash, for SCAN
+    scan_index: ScanIndex,
+    // Client ids parked in blocking commands, per key in the order they blocked
+    waiters: HashMap<Key, VecDeque<u64>>,
+    blocked: HashMap<u64, BlockedClient>,
+    // Keys written while clients were waiting on them
+    ready_keys: Vec<Key>,
+    // Client ids that WATCH each key, and those whose watched keys were
+    // modified since. Writes through `get_mut` must be reported with
+    // `signal_modified`.
+    watchers: HashMap<Key, Vec<u64>>,
+    dirty_watchers: HashSet<u64>,
 }
 
 impl Database {
     pub fn new() -> Database {
         Database {
             store: HashMap::new(),
+            volatile: VolatileKeys::default(),
+            scan_index: ScanIndex::default(),
+            waiters: HashMap::new(),
+            blocked: HashMap::new(),
+            ready_keys: Vec::new(),
+            watchers: HashMap::new(),
+            dirty_watchers: HashSet::new(),
+        }
+    }
+
+    /// Store a string value, returning the object it replaced
+    pub fn set(&mut self, key: Key, value: Value, expiry: SetExpiry) -> Option<SetObject> {
+        let duration = match expiry {
+            SetExpiry::Persist => None,
+            SetExpiry::KeepTtl => self.get(&key).and_then(|old| old.expires_at),
+            SetExpiry::At(millis) => Some(millis),
+        };
+        self.insert(
+            key,
+            SetObject::new(StoredValue::String(value.into()), duration),
+        )
+    }
+
+    /// Get the string held at a key, failing if the key holds another type
+    pub fn get_string(&mut self, key: &[u8]) -> Result<Option<&Bytes>, CommandError> {
+        match self.get_mut(key) {
+            Some(SetObject {
+                value: StoredValue::String(value),
+                ..
+            }) => Ok(Some(value.freeze())),
+            Some(_) => Err(CommandError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Borrow the string held at a key, leaving a string that bit commands
+    /// write to as a mutable buffer
+    pub fn get_string_bytes(&mut self, key: &[u8]) -> Result<Option<&[u8]>, CommandError> {
+        match self.get(key) {
+            Some(SetObject {
+                value: StoredValue::String(value),
+                ..
+            }) => Ok(Some(value.as_bytes())),
+            Some(_) => Err(CommandError::WrongType),
+            None => Ok(None),
+        }
+    }
+
+    /// Get the string held at a key as a mutable buffer, creating an empty
+    /// one if the key is missing. Callers must report changes with
+    /// `signal_modified`.
+    pub fn get_string_mut(&mut self, key: &Bytes) -> Result<&mut BytesMut, CommandError> {
+        let object = self.get_or_insert_with(key, || StoredValue::String(StringValue::default()));
+        match &mut object.value {
+            StoredValue::String(value) => Ok(value.make_mut()),
+            _ => Err(CommandError::WrongType),
+        }
+    }
+
+    /// Get a key for modification, creating it with `default` if missing.
+    /// Callers that change an existing value must call `signal_modified`.
+    pub fn get_or_insert_with(
+        &mut self,
+        key: &Bytes,
+        default: impl FnOnce() -> StoredValue,
+    ) -> &mut SetObject {
+        if self.get(key).is_none() {
+            self.insert(key.clone(), SetObject::new(default(), None));
+        }
+        self.store.get_mut(key).expect("key inserted above")
+    }
+
+    /// Delete a key if it holds an aggregate that became empty, Redis never
+    /// keeps empty lists, hashes, sets or sorted sets around
+    pub fn remove_if_empty(&mut self, key: &[u8]) {
+        if self
+            .store
+            .get(key)
+            .is_some_and(|object| object.value.is_empty())
+        {
+            self.remove(key);
+        }
+    }
+
+    /// Get a key, deleting it first if it has expired
+    pub fn get(&mut self, key: &[u8]) -> Option<&SetObject> {
+        self.expire_if_needed(key);
+        self.store.get(key)
+    }
+
+    /// Get a key without reclaiming it if it expired, so several keys can be
+    /// borrowed at once. Expired keys read as missing.
+    pub fn peek(&self, key: &[u8]) -> Option<&SetObject> {
+        self.store.get(key).filter(|object| !object.is_expired())
+    }
+
+    /// Get a key for modification, deleting it first if it has expired.
+    ///
+    /// TTLs must not be changed through the returned object, since the
+    /// volatile key index would not see it. Callers that change the value
+    /// must call `signal_modified` afterwards.
+    pub fn get_mut(&mut self, key: &[u8]) -> Option<&mut SetObject> {
+        self.expire_if_needed(key);
+        self.store.get_mut(key)
+    }
+
+    /// Change the absolute expiry time of a key, `None` makes it persistent.
+    /// Returns false if the key does not exist.
+    pub fn set_expiry(&mut self, key: &[u8], expires_at: Option<u64>) -> bool {
+        self.expire_if_needed(key);
+        let Some(object) = self.store.get_mut(key) else {
+            return false;
+        };
+        object.expires_at = expires_at;
+        match expires_at {
+            Some(_) => self.volatile.insert(Key::copy_from_slice(key)),
+            None => self.volatile.remove(key),
+        }
+        self.signal_modified(key);
+        true
+    }
+
+    /// Insert or replace an object, returning the one it replaced
+    pub fn insert(&mut self, key: Key, object: SetObject) -> Option<SetObject> {
+        if object.expires_at.is_some() {
+            self.volatile.insert(key.clone());
+        } else {
+            self.volatile.remove(&key);
+        }
+        if !self.store.contains_key(&key) {
+            self.scan_index.insert(key.clone());
+        }
+        self.mark_ready(&key);
+        self.signal_modified(&key);
+        self.store.insert(key, object)
+    }
+
+    /// Note a write to a key so clients blocked on it get another look
+    pub fn mark_ready(&mut self, key: &Key) {
+        if self.waiters.contains_key(key) && !self.ready_keys.contains(key) {
+            self.ready_keys.push(key.clone());
+        }
+    }
+
+    /// Park a client until one of `keys` is written to, the reply is sent
+    /// through the returned channel once the operation can be served
+    pub fn block(
+        &mut self,
+        client_id: u64,
+        keys: Vec<Key>,
+        operation: BlockedOperation,
+    ) -> oneshot::Receiver<RedisValueRef> {
+        for key in &keys {
+            self.waiters
+                .entry(key.clone())
+                .or_default()
+                .push_back(client_id);
+        }
+        let (sender, receiver) = oneshot::channel();
+        let client = BlockedClient {
+            keys,
+            operation,
+            sender,
+        };
+        self.blocked.insert(client_id, client);
+        receiver
+    }
+
+    /// A client parked by `block`
+    pub fn blocked_client(&self, client_id: u64) -> Option<&BlockedClient> {
+        self.blocked.get(&client_id)
+    }
+
+    /// Stop waiting on behalf of a client, returning what it was blocked on
+    pub fn unblock(&mut self, client_id: u64) -> Option<BlockedClient> {
+        let client = self.blocked.remove(&client_id)?;
+        for key in &client.keys {
+            if let Some(queue) = self.waiters.get_mut(key) {
+                queue.retain(|id| *id != client_id);
+                if queue.is_empty() {
+                    self.waiters.remove(key);
+                }
+            }
+        }
+        Some(client)
+    }
+
+    /// Clients waiting on a key, longest waiting first
+    pub fn waiters(&self, key: &[u8]) -> Vec<u64> {
+        self.waiters
+            .get(key)
+            .map_or_else(Vec::new, |queue| queue.iter().copied().collect())
+    }
+
+    /// Take the keys that were written while clients were blocked on them
+    pub fn take_ready_keys(&mut self) -> Vec<Key> {
+        std::mem::take(&mut self.ready_keys)
+    }
+
+    /// Delete a key, returning the object it held
+    pub fn remove(&mut self, key: &[u8]) -> Option<SetObject> {
+        let (key, object) = self.store.remove_entry(key)?;
+        if object.expires_at.is_some() {
+            self.volatile.remove(&key);
+        }
+        self.signal_modified(&key);
+        self.scan_index.remove(key);
+        Some(object)
+    }
+
+    /// Watch a key on behalf of a client, for EXEC to check it was not
+    /// modified since
+    pub fn watch(&mut self, client_id: u64, key: Key) {
+        let clients = self.watchers.entry(key).or_default();
+        if !clients.contains(&client_id) {
+            clients.push(client_id);
+        }
+    }
+
+    /// Stop watching keys for a client, returns whether any of the keys it
+    /// watched was modified in the meantime
+    pub fn unwatch(&mut self, client_id: u64, keys: &[Key]) -> bool {
+        for key in keys {
+            if let Some(clients) = self.watchers.get_mut(key) {
+                clients.retain(|id| *id != client_id);
+                if clients.is_empty() {
+                    self.watchers.remove(key);
+                }
+            }
+        }
+        self.dirty_watchers.remove(&client_id)
+    }
+
+    /// Note a modification of a key for the clients watching it
+    pub fn signal_modified(&mut self, key: &[u8]) {
+        if let Some(clients) = self.watchers.get(key) {
+            self.dirty_watchers.extend(clients.iter().copied());
+        }
+    }
+
+    /// Whether a live key exists
+    pub fn exists(&mut self, key: &[u8]) -> bool {
+        self.get(key).is_some()
+    }
+
+    /// Delete a live key, returns whether it existed
+    pub fn delete(&mut self, key: &[u8]) -> bool {
+        !self.expire_if_needed(key) && self.remove(key).is_some()
+    }
+
+    /// Name of the type held at a key, as reported by TYPE
+    pub fn key_type(&mut self, key: &[u8]) -> &'static str {
+        match self.get(key) {
+            Some(object) => object.value.type_name(),
+            None => "none",
+        }
+    }
+
+    /// Move a key and its TTL to a new name. With `only_if_missing` nothing
+    /// happens when the destination exists. Returns whether the key moved.
+    pub fn rename(
+        &mut self,
+        from: &[u8],
+        to: Key,
+        only_if_missing: bool,
+    ) -> Result<bool, CommandError> {
+        if !self.exists(from) {
+            return Err(CommandError::NoSuchKey);
+        }
+        if only_if_missing && self.exists(&to) {
+            return Ok(false);
         }
+        if from != to.as_ref() {
+            let object = self.remove(from).expect("key checked above");
+            self.insert(to, object);
+        }
+        Ok(true)
+    }
+
+    /// Copy a key and its TTL. Unless `replace` is set nothing happens when
+    /// the destination exists. Returns whether the key was copied.
+    pub fn copy(&mut self, from: &[u8], to: Key, replace: bool) -> Result<bool, CommandError> {
+        if from == to.as_ref() {
+            return Err(CommandError::SameObject);
+        }
+        let Some(object) = self.get(from).cloned() else {
+            return Ok(false);
+        };
+        if !replace && self.exists(&to) {
+            return Ok(false);
+        }
+        self.insert(to, object);
+        Ok(true)
+    }
+
+    /// Live keys matching a glob-style pattern
+    pub fn keys(&self, pattern: &[u8]) -> Vec<Key> {
+        self.store
+            .iter()
+            .filter(|(key, object)| !object.is_expired() && glob_match(pattern, key))
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Walk the keyspace from `cursor`, visiting about `count` keys, as
+    /// `ScanIndex::scan` does. Returns the next cursor, 0 once the iteration
+    /// is complete, and the live keys visited.
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<Key>) {
+        let (next, keys) = self.scan_index.scan(cursor, count, |key| {
+            self.store
+                .get(key)
+                .is_some_and(|object| !object.is_expired())
+        });
+        (next, keys.into_iter().cloned().collect())
+    }
+
+    /// Number of keys, including expired keys not reclaimed yet
+    pub fn len(&self) -> usize {
+        self.store.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.store.is_empty()
+    }
+
+    /// Lazily delete a key whose TTL has passed, returns whether it was deleted
+    pub fn expire_if_needed(&mut self, key: &[u8]) -> bool {
+        match self.store.get(key) {
+            Some(object) if object.is_expired() => {
+                self.remove(key);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    /// Reclaim expired keys the way Redis does: sample keys with a TTL and
+    /// delete the expired ones, going again while more than a quarter of the
+    /// sample was expired and the time budget allows. Returns the number of
+    /// keys deleted.
+    pub fn active_expire_cycle(&mut self) -> usize {
+        let start = Instant::now();
+        let mut deleted = 0;
+        loop {
+            let sample = self.volatile.sample(ACTIVE_EXPIRE_SAMPLE);
+            let sampled = sample.len();
+            let expired = sample
+                .iter()
+                .filter(|key| self.expire_if_needed(key))
+                .count();
+            deleted += expired;
+
+            if sampled == 0 || expired * 4 <= sampled || start.elapsed() > ACTIVE_EXPIRE_BUDGET {
+                return deleted;
+            }
+        }
+    }
+}
+
+/// Stable hash that orders keys for SCAN. Never 0, which is the cursor
+/// that starts and ends an iteration.
+pub fn scan_hash(key: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    hasher.write(key);
+    hasher.finish().max(1)
+}
+
+/// Elements of a keyspace or an aggregate ordered by their scan hash, which
+/// SCAN, HSCAN and SSCAN walk
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ScanIndex(BTreeSet<(u64, Bytes)>);
+
+impl ScanIndex {
+    pub fn insert(&mut self, element: Bytes) {
+        self.0.insert((scan_hash(&element), element));
     }
 
-    pub fn set(&mut self, key: &str, value: &str, ttl: Option<Duration>) -> Option<SetObject> {
-        match ttl {
-            Some(ttl) => {
-                let expiry_time = Instant::now()
-                    .checked_add(ttl)
-                    .expect("Failed to store ttl");
-                let set_object = SetObject::new(value.to_string(), Some(expiry_time));
-                self.store.insert(key.to_string(), set_object)
+    pub fn remove(&mut self, element: Bytes) {
+        self.0.remove(&(scan_hash(&element), element));
+    }
+
+    /// Walk from `cursor`, visiting about `count` elements that pass `keep`.
+    ///
+    /// Elements are visited in the order of their scan hash and the cursor is
+    /// the hash to resume from, so an element present for the whole
+    /// iteration is always returned regardless of concurrent inserts and
+    /// deletes. Returns the next cursor, 0 once the iteration is complete.
+    pub fn scan(
+        &self,
+        cursor: u64,
+        count: usize,
+        mut keep: impl FnMut(&Bytes) -> bool,
+    ) -> (u64, Vec<&Bytes>) {
+        let mut elements = Vec::with_capacity(count);
+        let mut last_hash = None;
+        for (hash, element) in self.0.range((cursor, Bytes::new())..) {
+            // Never split elements sharing a hash across calls, or the
+            // cursor could not move past them
+            if elements.len() >= count && last_hash != Some(*hash) {
+                return (*hash, elements);
             }
-            None => {
-                let set_object = SetObject::new(value.to_string(), None);
-                self.store.insert(key.to_string(), set_object)
+            last_hash = Some(*hash);
+            if keep(element) {
+                elements.push(element);
             }
         }
+        (0, elements)
+    }
+}
+
+/// TTL handling requested by SET
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SetExpiry {
+    /// Drop any TTL the key had
+    Persist,
+    /// Keep the TTL of the value being replaced
+    KeepTtl,
+    /// Expire at the given unix time in milliseconds
+    At(u64),
+}
+
+/// Set of keys with O(1) insert, remove and random sampling
+#[derive(Clone, Debug, Default)]
+struct VolatileKeys {
+    keys: Vec<Key>,
+    positions: HashMap<Key, usize>,
+}
+
+impl VolatileKeys {
+    fn insert(&mut self, key: Key) {
+        if !self.positions.contains_key(&key) {
+            self.positions.insert(key.clone(), self.keys.len());
+            self.keys.push(key);
+        }
+    }
+
+    fn remove(&mut self, key: &[u8]) {
+        if let Some(pos) = self.positions.remove(key) {
+            self.keys.swap_remove(pos);
+            // The last key moved into the hole
+            if let Some(moved) = self.keys.get(pos) {
+                self.positions.insert(moved.clone(), pos);
+            }
+        }
+    }
+
+    /// Pick up to `count` random keys, possibly with repeats
+    fn sample(&self, count: usize) -> Vec<Key> {
+        if self.keys.is_empty() {
+            return Vec::new();
+        }
+        let mut rng = rand::thread_rng();
+        (0..count.min(self.keys.len()))
+            .map(|_| self.keys[rng.gen_range(0..self.keys.len())].clone())
+            .collect()
+    }
+}
+
+/// A client parked in a blocking command
+#[derive(Debug)]
+pub struct BlockedClient {
+    pub keys: Vec<Key>,
+    pub operation: BlockedOperation,
+    pub sender: oneshot::Sender<RedisValueRef>,
+}
+
+/// What to do for a blocked client once one of its keys has data
+#[derive(Clone, Debug, PartialEq)]
+pub enum BlockedOperation {
+    /// BLPOP and BRPOP
+    Pop(End),
+    /// BLMOVE and BRPOPLPUSH
+    Move {
+        destination: Key,
+        from: End,
+        to: End,
+    },
+    /// XREAD BLOCK, waiting for entries after an ID in each stream
+    Read {
+        streams: Vec<(Key, StreamId)>,
+        count: Option<usize>,
+    },
+    /// XREADGROUP BLOCK with `>`, waiting for entries the group has not seen
+    ReadGroup {
+        group: Bytes,
+        consumer: Bytes,
+        count: Option<usize>,
+        noack: bool,
+    },
+}
+
+/// The data held by a key
+#[derive(Clone, Debug, PartialEq)]
+pub enum StoredValue {
+    String(StringValue),
+    List(VecDeque<Value>),
+    Hash(Hash),
+    Set(Set),
+    SortedSet(SortedSet),
+    Stream(Stream),
+}
+
+impl StoredValue {
+    /// Name of the type, as reported by TYPE
+    pub fn type_name(&self) -> &'static str {
+        match self {
+            StoredValue::String(_) => "string",
+            StoredValue::List(_) => "list",
+            StoredValue::Hash(_) => "hash",
+            StoredValue::Set(_) => "set",
+            StoredValue::SortedSet(_) => "zset",
+            StoredValue::Stream(_) => "stream",
+        }
+    }
+
+    /// Whether this is an aggregate without elements
+    pub fn is_empty(&self) -> bool {
+        match self {
+            StoredValue::String(_) => false,
+            StoredValue::List(list) => list.is_empty(),
+            StoredValue::Hash(hash) => hash.is_empty(),
+            StoredValue::Set(set) => set.is_empty(),
+            StoredValue::SortedSet(zset) => zset.is_empty(),
+            // Streams outlive their entries, they keep IDs and groups
+            StoredValue::Stream(_) => false,
+        }
+    }
+}
+
+/// A string value. Bit commands write to it as a mutable buffer, so a run of
+/// writes changes it in place, and other reads freeze the buffer back into
+/// shared bytes without copying it.
+#[derive(Clone, Debug)]
+pub enum StringValue {
+    Shared(Value),
+    Mutable(BytesMut),
+}
+
+impl StringValue {
+    pub fn as_bytes(&self) -> &[u8] {
+        match self {
+            StringValue::Shared(bytes) => bytes,
+            StringValue::Mutable(buffer) => buffer,
+        }
+    }
+
+    /// The value as shared bytes, freezing a mutable buffer
+    pub fn freeze(&mut self) -> &Bytes {
+        if let StringValue::Mutable(buffer) = self {
+            *self = StringValue::Shared(std::mem::take(buffer).freeze());
+        }
+        match self {
+            StringValue::Shared(bytes) => bytes,
+            StringValue::Mutable(_) => unreachable!("frozen above"),
+        }
+    }
+
+    /// The value as a mutable buffer, copying shared bytes the first time
+    pub fn make_mut(&mut self) -> &mut BytesMut {
+        if let StringValue::Shared(bytes) = self {
+            *self = StringValue::Mutable(BytesMut::from(bytes.as_ref()));
+        }
+        match self {
+            StringValue::Mutable(buffer) => buffer,
+            StringValue::Shared(_) => unreachable!("thawed above"),
+        }
     }
+}
 
-    pub fn get(&self, key: &str) -> Option<SetObject> {
-        let result = self.store.get(key);
+impl Default for StringValue {
+    fn default() -> Self {
+        StringValue::Shared(Value::new())
+    }
+}
+
+impl From<Value> for StringValue {
+    fn from(value: Value) -> Self {
+        StringValue::Shared(value)
+    }
+}
 
-        result.cloned()
+impl PartialEq for StringValue {
+    /// Strings are equal when they hold the same bytes, however stored
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
     }
 }
 
+/// Stream entry ID, a millisecond timestamp and a sequence number
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct StreamId {
+    pub ms: u64,
+    pub seq: u64,
+}
+
+impl StreamId {
+    pub const MIN: StreamId = StreamId { ms: 0, seq: 0 };
+    pub const MAX: StreamId = StreamId {
+        ms: u64::MAX,
+        seq: u64::MAX,
+    };
+
+    pub fn new(ms: u64, seq: u64) -> StreamId {
+        StreamId { ms, seq }
+    }
+
+    /// The ID right after this one, if any
+    pub fn next(self) -> Option<StreamId> {
+        match self.seq.checked_add(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_add(1)?, 0)),
+        }
+    }
+
+    /// The ID right before this one, if any
+    pub fn prev(self) -> Option<StreamId> {
+        match self.seq.checked_sub(1) {
+            Some(seq) => Some(StreamId::new(self.ms, seq)),
+            None => Some(StreamId::new(self.ms.checked_sub(1)?, u64::MAX)),
+        }
+    }
+}
+
+impl fmt::Display for StreamId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}-{}", self.ms, self.seq)
+    }
+}
+
+/// An append-only log of field-value entries
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stream {
+    pub entries: BTreeMap<StreamId, Vec<(Value, Value)>>,
+    /// Largest ID ever added, entries may have been deleted since
+    pub last_id: StreamId,
+    pub max_deleted_id: StreamId,
+    /// Entries added over the life of the stream
+    pub entries_added: u64,
+    pub groups: BTreeMap<Bytes, ConsumerGroup>,
+}
+
+/// A consumer group reading a stream, with its pending entries list
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ConsumerGroup {
+    pub last_delivered: StreamId,
+    /// Entries delivered and not acknowledged yet
+    pub pending: BTreeMap<StreamId, PendingEntry>,
+    pub consumers: BTreeMap<Bytes, Consumer>,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PendingEntry {
+    pub consumer: Bytes,
+    /// Unix time in milliseconds of the last delivery
+    pub delivered_at: u64,
+    pub delivery_count: u64,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Consumer {
+    /// Unix time in milliseconds the consumer last tried to read
+    pub seen_at: u64,
+    /// Unix time in milliseconds the consumer last got entries, if ever
+    pub active_at: Option<u64>,
+}
+
 #[derive(Clone, Debug)]
 pub struct SetObject {
-    pub value: String,
-    pub duration: Option<Instant>,
+    pub value: StoredValue,
+    /// Absolute unix time in milliseconds, so it means the same thing after
+    /// a restart or on a replica
+    pub expires_at: Option<u64>,
 }
 
 impl SetObject {
-    pub fn new(value: String, duration: Option<Instant>) -> Self {
-        SetObject { value, duration }
+    pub fn new(value: StoredValue, expires_at: Option<u64>) -> Self {
+        SetObject { value, expires_at }
+    }
+
+    pub fn is_expired(&self) -> bool {
+        self.expires_at
+            .is_some_and(|expires_at| current_time_millis() > expires_at)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keys_and_values_are_binary_safe() {
+        let mut db = Database::new();
+        let key = Key::from_static(b"\xff\x00key\r\n");
+        let value = Value::from_static(b"\x00\xc3\x28 not utf-8\r\n");
+        db.set(key.clone(), value.clone(), SetExpiry::Persist);
+        db.set(
+            Key::from_static(b"\xff"),
+            Value::from_static(b"other"),
+            SetExpiry::Persist,
+        );
+
+        assert_eq!(db.get_string(&key).unwrap(), Some(&value));
+        assert_eq!(db.get_string(b"\xff\x00key").unwrap(), None);
+        let mut keys = db.keys(b"\xff*");
+        keys.sort();
+        assert_eq!(keys, vec![Key::from_static(b"\xff"), key.clone()]);
+        assert_eq!(db.keys(b"*key\r\n"), vec![key.clone()]);
+        assert!(db.delete(&key));
+        assert!(!db.exists(&key));
+    }
+
+    fn set_expiring(db: &mut Database, key: &'static [u8], expires_at: Option<u64>) {
+        let object = SetObject::new(
+            StoredValue::String(Value::from_static(b"v").into()),
+            expires_at,
+        );
+        db.insert(Key::from_static(key), object);
+    }
+
+    #[test]
+    fn expired_keys_are_reclaimed_on_access() {
+        let mut db = Database::new();
+        set_expiring(&mut db, b"gone", Some(1));
+        set_expiring(&mut db, b"live", Some(current_time_millis() + 60_000));
+        assert_eq!(db.len(), 2);
+
+        // Expired keys are invisible before they are reclaimed
+        assert_eq!(db.keys(b"*"), vec![Key::from_static(b"live")]);
+        assert_eq!(db.scan(0, 10), (0, vec![Key::from_static(b"live")]));
+        assert_eq!(db.len(), 2);
+
+        assert!(db.get(b"gone").is_none());
+        assert_eq!(db.len(), 1);
+        assert!(db.volatile.positions.contains_key(&b"live"[..]));
+        assert!(!db.volatile.positions.contains_key(&b"gone"[..]));
+        assert!(db.scan_index.0.iter().all(|(_, key)| key != "gone"));
+    }
+
+    #[test]
+    fn active_expire_cycle_reclaims_untouched_keys() {
+        let mut db = Database::new();
+        for i in 0..200 {
+            let key = Key::from(format!("gone:{}", i));
+            let object = SetObject::new(StoredValue::String(Value::new().into()), Some(1));
+            db.insert(key, object);
+        }
+        set_expiring(&mut db, b"live", Some(current_time_millis() + 60_000));
+        set_expiring(&mut db, b"persistent", None);
+
+        // Samples are random, so a cycle may stop with a few expired keys left
+        let mut deleted = 0;
+        for _ in 0..100 {
+            if db.volatile.keys.len() == 1 {
+                break;
+            }
+            deleted += db.active_expire_cycle();
+        }
+        assert_eq!(deleted, 200);
+        let mut keys = db.keys(b"*");
+        keys.sort();
+        assert_eq!(
+            keys,
+            vec![Key::from_static(b"live"), Key::from_static(b"persistent")]
+        );
+        assert_eq!(db.len(), 2);
+        assert_eq!(db.volatile.keys, vec![Key::from_static(b"live")]);
+        assert_eq!(db.active_expire_cycle(), 0);
     }
 }
@@ -0,0 +1,452 @@
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, arg_float, arg_int, check_arity, geo_distance, geohash_encode, geohash_position,
+    is_keyword, zset, zset_or_create, CommandError, CommandResult, Database, GeoHash, GeoRange,
+    GeoShape, GeoShapeKind, RedisValueRef, ScoreBound, SetObject, SortedSet, StoredValue,
+    GEO_LAT_MAX, GEO_LAT_MIN, GEO_LONG_MAX, GEO_LONG_MIN, GEO_STEP_MAX, WGS84_RANGES,
+};
+
+/// Characters of the standard geohash strings
+const GEOHASH_ALPHABET: &[u8; 32] = b"0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// GEOADD key [NX | XX] [CH] longitude latitude member [longitude latitude
+/// member ...], members are stored in a sorted set scored by geohash
+pub fn handle_geoadd(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("geoadd", args, 4, None)?;
+    let key = arg_bytes(&args[0])?;
+
+    let (mut nx, mut xx, mut ch) = (false, false, false);
+    let mut next = 1;
+    for option in &args[1..] {
+        if is_keyword(option, "nx") {
+            nx = true;
+        } else if is_keyword(option, "xx") {
+            xx = true;
+        } else if is_keyword(option, "ch") {
+            ch = true;
+        } else {
+            break;
+        }
+        next += 1;
+    }
+    let triples = args[next..].chunks_exact(3);
+    if triples.len() == 0 || !triples.remainder().is_empty() || (nx && xx) {
+        return Err(CommandError::Syntax);
+    }
+    let mut elements = Vec::with_capacity(triples.len());
+    for triple in triples {
+        let (longitude, latitude) = parse_position(&triple[0], &triple[1])?;
+        let hash = geohash_encode(WGS84_RANGES, longitude, latitude, GEO_STEP_MAX)
+            .ok_or(CommandError::InvalidPosition(longitude, latitude))?;
+        elements.push((hash.align52() as f64, arg_bytes(&triple[2])?.clone()));
+    }
+
+    // XX never creates the key
+    if xx && zset(db, key)?.is_none() {
+        return Ok(RedisValueRef::Int(0));
+    }
+    let zset = zset_or_create(db, key)?;
+    let (mut added, mut changed) = (0, 0);
+    for (score, member) in elements {
+        match zset.score(&member) {
+            None if xx => continue,
+            None => added += 1,
+            Some(_) if nx => continue,
+            Some(current) if current != score => changed += 1,
+            Some(_) => {}
+        }
+        zset.insert(member, score);
+    }
+    if added + changed > 0 {
+        db.signal_modified(key);
+    }
+    Ok(RedisValueRef::Int(if ch { added + changed } else { added }))
+}
+
+/// GEODIST key member1 member2 [M | KM | FT | MI], null if either member is
+/// missing
+pub fn handle_geodist(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("geodist", args, 3, Some(4))?;
+    let key = arg_bytes(&args[0])?;
+    let conversion = args.get(3).map(parse_unit).transpose()?.unwrap_or(1.0);
+
+    let Some(zset) = zset(db, key)? else {
+        return Ok(RedisValueRef::NullBulkString);
+    };
+    let from = zset.score(arg_bytes(&args[1])?);
+    let to = zset.score(arg_bytes(&args[2])?);
+    let (Some(from), Some(to)) = (from, to) else {
+        return Ok(RedisValueRef::NullBulkString);
+    };
+    let (lon1, lat1) = geohash_position(from);
+    let (lon2, lat2) = geohash_position(to);
+    Ok(distance_reply(
+        geo_distance(lon1, lat1, lon2, lat2) / conversion,
+    ))
+}
+
+/// GEOPOS key [member ...], the position of each member or null
+pub fn handle_geopos(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("geopos", args, 1, None)?;
+    let key = arg_bytes(&args[0])?;
+    let zset = zset(db, key)?;
+
+    let mut positions = Vec::with_capacity(args.len() - 1);
+    for member in &args[1..] {
+        let member = arg_bytes(member)?;
+        let score = zset.and_then(|zset| zset.score(member));
+        positions.push(match score {
+            Some(score) => {
+                let (longitude, latitude) = geohash_position(score);
+                coordinates_reply(longitude, latitude)
+            }
+            None => RedisValueRef::NullArray,
+        });
+    }
+    Ok(RedisValueRef::Array(positions))
+}
+
+/// GEOHASH key [member ...], the standard 11 character geohash of each
+/// member or null
+pub fn handle_geohash(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("geohash", args, 1, None)?;
+    let key = arg_bytes(&args[0])?;
+    let zset = zset(db, key)?;
+
+    // Standard geohashes cover latitudes up to the poles
+    let ranges = (
+        WGS84_RANGES.0,
+        GeoRange {
+            min: -90.0,
+            max: 90.0,
+        },
+    );
+    let mut hashes = Vec::with_capacity(args.len() - 1);
+    for member in &args[1..] {
+        let member = arg_bytes(member)?;
+        let score = zset.and_then(|zset| zset.score(member));
+        let Some(score) = score else {
+            hashes.push(RedisValueRef::NullBulkString);
+            continue;
+        };
+        let (longitude, latitude) = geohash_position(score);
+        let bits =
+            geohash_encode(ranges, longitude, latitude, GEO_STEP_MAX).map_or(0, |hash| hash.bits);
+        // 52 bits make 10 characters, the 11th is always 0
+        let hash: Vec<u8> = (0..11)
+            .map(|i| match i {
+                10 => GEOHASH_ALPHABET[0],
+                _ => GEOHASH_ALPHABET[((bits >> (52 - (i + 1) * 5)) & 0x1f) as usize],
+            })
+            .collect();
+        hashes.push(RedisValueRef::String(Bytes::from(hash)));
+    }
+    Ok(RedisValueRef::Array(hashes))
+}
+
+/// GEOSEARCH key FROMMEMBER member | FROMLONLAT longitude latitude, BYRADIUS
+/// radius unit | BYBOX width height unit, [ASC | DESC] [COUNT count [ANY]]
+/// [WITHCOORD] [WITHDIST] [WITHHASH], and GEOSEARCHSTORE destination source
+/// with the same options plus STOREDIST, which stores the members found in a
+/// sorted set scored by geohash or distance
+pub fn handle_geosearch(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    let store = name == "geosearchstore";
+    check_arity(name, args, if store { 7 } else { 6 }, None)?;
+    let (destination, args) = match store {
+        true => (Some(arg_bytes(&args[0])?), &args[1..]),
+        false => (None, args),
+    };
+    let key = arg_bytes(&args[0])?;
+    let options = SearchOptions::parse(name, &args[1..])?;
+
+    let Some(zset) = zset(db, key)? else {
+        return Ok(match destination {
+            Some(destination) => {
+                db.remove(destination);
+                RedisValueRef::Int(0)
+            }
+            None => RedisValueRef::Array(vec![]),
+        });
+    };
+    let (longitude, latitude) = match &options.from {
+        SearchFrom::Member(member) => {
+            let score = zset.score(member).ok_or(CommandError::GeoMemberMissing)?;
+            geohash_position(score)
+        }
+        SearchFrom::Position(longitude, latitude) => (*longitude, *latitude),
+    };
+    let shape = GeoShape {
+        longitude,
+        latitude,
+        conversion: options.conversion,
+        kind: options.shape,
+    };
+    let mut points = search(zset, &shape, options.any.then_some(options.count).flatten());
+
+    if let Some(descending) = options.descending {
+        points.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+        if descending {
+            points.reverse();
+        }
+    }
+    if let Some(count) = options.count {
+        points.truncate(count);
+    }
+    for point in &mut points {
+        point.distance /= options.conversion;
+    }
+
+    if let Some(destination) = destination {
+        let len = points.len();
+        if points.is_empty() {
+            db.remove(destination);
+        } else {
+            let mut result = SortedSet::new();
+            for point in points {
+                let score = match options.store_distance {
+                    true => point.distance,
+                    false => point.score,
+                };
+                result.insert(point.member, score);
+            }
+            db.insert(
+                destination.clone(),
+                SetObject::new(StoredValue::SortedSet(result), None),
+            );
+        }
+        return Ok(RedisValueRef::Int(len as i64));
+    }
+
+    let detailed = options.with_distance || options.with_hash || options.with_coordinates;
+    let reply = points
+        .into_iter()
+        .map(|point| {
+            let member = RedisValueRef::String(point.member);
+            if !detailed {
+                return member;
+            }
+            let mut fields = vec![member];
+            if options.with_distance {
+                fields.push(distance_reply(point.distance));
+            }
+            if options.with_hash {
+                fields.push(RedisValueRef::Int(point.score as i64));
+            }
+            if options.with_coordinates {
+                fields.push(coordinates_reply(point.longitude, point.latitude));
+            }
+            RedisValueRef::Array(fields)
+        })
+        .collect();
+    Ok(RedisValueRef::Array(reply))
+}
+
+/// A member found by a search
+struct GeoPoint {
+    member: Bytes,
+    score: f64,
+    /// Meters from the center, converted to the search unit before replying
+    distance: f64,
+    longitude: f64,
+    latitude: f64,
+}
+
+/// Find the members inside a shape by scanning the score ranges of the
+/// cells covering it, stopping early once `limit` were found
+fn search(zset: &SortedSet, shape: &GeoShape, limit: Option<usize>) -> Vec<GeoPoint> {
+    let mut points = Vec::new();
+    let full = |points: &Vec<GeoPoint>| limit.is_some_and(|limit| points.len() >= limit);
+    for cell in shape.search_cells() {
+        if full(&points) {
+            break;
+        }
+        let next = GeoHash {
+            bits: cell.bits + 1,
+            step: cell.step,
+        };
+        let min = ScoreBound::Inclusive(cell.align52() as f64);
+        let max = ScoreBound::Exclusive(next.align52() as f64);
+        for (member, score) in zset.range_by_score(min, max, false, 0, None) {
+            if full(&points) {
+                break;
+            }
+            let (longitude, latitude) = geohash_position(score);
+            if let Some(distance) = shape.distance_to(longitude, latitude) {
+                points.push(GeoPoint {
+                    member,
+                    score,
+                    distance,
+                    longitude,
+                    latitude,
+                });
+            }
+        }
+    }
+    points
+}
+
+enum SearchFrom {
+    Member(Bytes),
+    Position(f64, f64),
+}
+
+struct SearchOptions {
+    from: SearchFrom,
+    shape: GeoShapeKind,
+    /// Meters per unit
+    conversion: f64,
+    /// Sort by distance, nearest first unless true
+    descending: Option<bool>,
+    count: Option<usize>,
+    /// Stop at the first COUNT members found rather than the nearest
+    any: bool,
+    with_coordinates: bool,
+    with_distance: bool,
+    with_hash: bool,
+    store_distance: bool,
+}
+
+impl SearchOptions {
+    fn parse(name: &'static str, args: &[RedisValueRef]) -> Result<SearchOptions, CommandError> {
+        let store = name == "geosearchstore";
+        let mut from = None;
+        let mut shape = None;
+        let mut options = SearchOptions {
+            from: SearchFrom::Position(0.0, 0.0),
+            shape: GeoShapeKind::Radius(0.0),
+            conversion: 1.0,
+            descending: None,
+            count: None,
+            any: false,
+            with_coordinates: false,
+            with_distance: false,
+            with_hash: false,
+            store_distance: false,
+        };
+
+        let mut i = 0;
+        while let Some(option) = args.get(i) {
+            let remaining = args.len() - i - 1;
+            if is_keyword(option, "withdist") {
+                options.with_distance = true;
+            } else if is_keyword(option, "withhash") {
+                options.with_hash = true;
+            } else if is_keyword(option, "withcoord") {
+                options.with_coordinates = true;
+            } else if is_keyword(option, "any") {
+                options.any = true;
+            } else if is_keyword(option, "asc") {
+                options.descending = Some(false);
+            } else if is_keyword(option, "desc") {
+                options.descending = Some(true);
+            } else if store && is_keyword(option, "storedist") {
+                options.store_distance = true;
+            } else if is_keyword(option, "count") && remaining >= 1 {
+                let count = arg_int(&args[i + 1])?;
+                if count <= 0 {
+                    return Err(CommandError::GeoCountNotPositive);
+                }
+                options.count = Some(count as usize);
+                i += 1;
+            } else if is_keyword(option, "frommember") && remaining >= 1 && from.is_none() {
+                from = Some(SearchFrom::Member(arg_bytes(&args[i + 1])?.clone()));
+                i += 1;
+            } else if is_keyword(option, "fromlonlat") && remaining >= 2 && from.is_none() {
+                let (longitude, latitude) = parse_position(&args[i + 1], &args[i + 2])?;
+                from = Some(SearchFrom::Position(longitude, latitude));
+                i += 2;
+            } else if is_keyword(option, "byradius") && remaining >= 2 && shape.is_none() {
+                let radius = arg_float(&args[i + 1])?;
+                if radius < 0.0 {
+                    return Err(CommandError::NegativeRadius);
+                }
+                options.conversion = parse_unit(&args[i + 2])?;
+                shape = Some(GeoShapeKind::Radius(radius));
+                i += 2;
+            } else if is_keyword(option, "bybox") && remaining >= 3 && shape.is_none() {
+                let width = arg_float(&args[i + 1])?;
+                let height = arg_float(&args[i + 2])?;
+                if width < 0.0 || height < 0.0 {
+                    return Err(CommandError::NegativeBox);
+                }
+                options.conversion = parse_unit(&args[i + 3])?;
+                shape = Some(GeoShapeKind::Box { width, height });
+                i += 3;
+            } else {
+                return Err(CommandError::Syntax);
+            }
+            i += 1;
+        }
+
+        options.from = from.ok_or(CommandError::GeoFromConflict(name))?;
+        options.shape = shape.ok_or(CommandError::GeoByConflict(name))?;
+        if options.any && options.count.is_none() {
+            return Err(CommandError::AnyWithoutCount);
+        }
+        if store && (options.with_distance || options.with_hash || options.with_coordinates) {
+            return Err(CommandError::GeoStoreWithOptions);
+        }
+        // Only the nearest members make sense to count, unless any will do
+        if options.count.is_some() && options.descending.is_none() && !options.any {
+            options.descending = Some(false);
+        }
+        Ok(options)
+    }
+}
+
+/// Parse a longitude and latitude, which must lie within Web Mercator
+fn parse_position(
+    longitude: &RedisValueRef,
+    latitude: &RedisValueRef,
+) -> Result<(f64, f64), CommandError> {
+    let longitude = arg_float(longitude)?;
+    let latitude = arg_float(latitude)?;
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+    {
+        return Err(CommandError::InvalidPosition(longitude, latitude));
+    }
+    Ok((longitude, latitude))
+}
+
+/// Parse a distance unit into meters per unit
+fn parse_unit(arg: &RedisValueRef) -> Result<f64, CommandError> {
+    if is_keyword(arg, "m") {
+        Ok(1.0)
+    } else if is_keyword(arg, "km") {
+        Ok(1000.0)
+    } else if is_keyword(arg, "ft") {
+        Ok(0.3048)
+    } else if is_keyword(arg, "mi") {
+        Ok(1609.34)
+    } else {
+        Err(CommandError::UnsupportedUnit)
+    }
+}
+
+/// Distances are replied with 4 decimals, enough even in kilometers
+fn distance_reply(distance: f64) -> RedisValueRef {
+    RedisValueRef::bulk_string(format!("{:.4}", distance))
+}
+
+/// `[longitude, latitude]` printed with 17 decimals less trailing zeros,
+/// like Redis' human readable long doubles
+fn coordinates_reply(longitude: f64, latitude: f64) -> RedisValueRef {
+    let human = |value: f64| {
+        let printed = format!("{:.17}", value);
+        let printed = printed.trim_end_matches('0').trim_end_matches('.');
+        match printed {
+            "-0" => "0".to_string(),
+            printed => printed.to_string(),
+        }
+    };
+    RedisValueRef::Array(vec![
+        RedisValueRef::bulk_string(human(longitude)),
+        RedisValueRef::bulk_string(human(latitude)),
+    ])
+}
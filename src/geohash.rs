@@ -0,0 +1,420 @@
+// Geohashes interleave latitude bits (even positions) with longitude bits
+// (odd positions), 26 steps of each giving the 52 bit integers stored as
+// sorted set scores. The math follows Redis' geohash.c so scores, distances
+// and search results match.
+
+/// Steps of precision in stored scores
+pub const GEO_STEP_MAX: u8 = 26;
+pub const GEO_LONG_MIN: f64 = -180.0;
+pub const GEO_LONG_MAX: f64 = 180.0;
+/// The latitude limits of Web Mercator
+pub const GEO_LAT_MIN: f64 = -85.05112878;
+pub const GEO_LAT_MAX: f64 = 85.05112878;
+const EARTH_RADIUS_IN_METERS: f64 = 6372797.560856;
+const MERCATOR_MAX: f64 = 20037726.37;
+
+/// Longitude and latitude ranges of the stored scores
+pub const WGS84_RANGES: (GeoRange, GeoRange) = (
+    GeoRange {
+        min: GEO_LONG_MIN,
+        max: GEO_LONG_MAX,
+    },
+    GeoRange {
+        min: GEO_LAT_MIN,
+        max: GEO_LAT_MAX,
+    },
+);
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct GeoRange {
+    pub min: f64,
+    pub max: f64,
+}
+
+/// A cell at some precision
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct GeoHash {
+    pub bits: u64,
+    pub step: u8,
+}
+
+impl GeoHash {
+    /// The zero hash marks a neighbor that need not be searched
+    fn is_zero(&self) -> bool {
+        self.bits == 0 && self.step == 0
+    }
+
+    /// The cell's hash scaled to 52 bits, the smallest score inside it
+    pub fn align52(&self) -> u64 {
+        self.bits << (52 - self.step as u32 * 2)
+    }
+
+    /// The cell next to this one, `dx` and `dy` each being -1, 0 or 1
+    fn moved(mut self, dx: i8, dy: i8) -> GeoHash {
+        let shift = 64 - self.step as u32 * 2;
+        for (delta, moving, other) in [
+            (dx, 0xaaaaaaaaaaaaaaaau64, 0x5555555555555555u64),
+            (dy, 0x5555555555555555, 0xaaaaaaaaaaaaaaaa),
+        ] {
+            if delta == 0 {
+                continue;
+            }
+            let mut axis = self.bits & moving;
+            let kept = self.bits & other;
+            // Setting the other axis' bits carries the addition across them
+            let fill = other >> shift;
+            axis = match delta > 0 {
+                true => axis.wrapping_add(fill + 1),
+                false => (axis | fill).wrapping_sub(fill + 1),
+            };
+            self.bits = (axis & (moving >> shift)) | kept;
+        }
+        self
+    }
+}
+
+/// The bounds of a cell
+#[derive(Debug, Clone, Copy)]
+pub struct GeoArea {
+    pub longitude: GeoRange,
+    pub latitude: GeoRange,
+}
+
+/// Encode a position into a cell of `step` bits per axis, None if it lies
+/// outside the ranges or the Web Mercator limits
+pub fn geohash_encode(
+    (long_range, lat_range): (GeoRange, GeoRange),
+    longitude: f64,
+    latitude: f64,
+    step: u8,
+) -> Option<GeoHash> {
+    if !(GEO_LONG_MIN..=GEO_LONG_MAX).contains(&longitude)
+        || !(GEO_LAT_MIN..=GEO_LAT_MAX).contains(&latitude)
+        || !(lat_range.min..=lat_range.max).contains(&latitude)
+        || !(long_range.min..=long_range.max).contains(&longitude)
+    {
+        return None;
+    }
+    let cells = (1u64 << step) as f64;
+    let lat_offset = (latitude - lat_range.min) / (lat_range.max - lat_range.min) * cells;
+    let long_offset = (longitude - long_range.min) / (long_range.max - long_range.min) * cells;
+    Some(GeoHash {
+        bits: interleave(lat_offset as u32, long_offset as u32),
+        step,
+    })
+}
+
+/// The bounds of a cell
+pub fn geohash_decode((long_range, lat_range): (GeoRange, GeoRange), hash: GeoHash) -> GeoArea {
+    let separated = deinterleave(hash.bits);
+    let lat_cell = separated as u32 as f64;
+    let long_cell = (separated >> 32) as u32 as f64;
+    let cells = (1u64 << hash.step) as f64;
+    let lat_scale = lat_range.max - lat_range.min;
+    let long_scale = long_range.max - long_range.min;
+    GeoArea {
+        latitude: GeoRange {
+            min: lat_range.min + (lat_cell / cells) * lat_scale,
+            max: lat_range.min + ((lat_cell + 1.0) / cells) * lat_scale,
+        },
+        longitude: GeoRange {
+            min: long_range.min + (long_cell / cells) * long_scale,
+            max: long_range.min + ((long_cell + 1.0) / cells) * long_scale,
+        },
+    }
+}
+
+/// The longitude and latitude at the center of a stored score's cell
+pub fn geohash_position(score: f64) -> (f64, f64) {
+    let hash = GeoHash {
+        bits: score as u64,
+        step: GEO_STEP_MAX,
+    };
+    let area = geohash_decode(WGS84_RANGES, hash);
+    let longitude = (area.longitude.min + area.longitude.max) / 2.0;
+    let latitude = (area.latitude.min + area.latitude.max) / 2.0;
+    (
+        longitude.clamp(GEO_LONG_MIN, GEO_LONG_MAX),
+        latitude.clamp(GEO_LAT_MIN, GEO_LAT_MAX),
+    )
+}
+
+/// Great circle distance in meters between two positions, by the haversine
+/// formula
+pub fn geo_distance(lon1: f64, lat1: f64, lon2: f64, lat2: f64) -> f64 {
+    let v = ((lon2.to_radians() - lon1.to_radians()) / 2.0).sin();
+    // Positions on the same meridian only differ in latitude
+    if v == 0.0 {
+        return geo_lat_distance(lat1, lat2);
+    }
+    let lat1 = lat1.to_radians();
+    let lat2 = lat2.to_radians();
+    let u = ((lat2 - lat1) / 2.0).sin();
+    let a = u * u + lat1.cos() * lat2.cos() * v * v;
+    2.0 * EARTH_RADIUS_IN_METERS * a.sqrt().asin()
+}
+
+fn geo_lat_distance(lat1: f64, lat2: f64) -> f64 {
+    EARTH_RADIUS_IN_METERS * (lat2.to_radians() - lat1.to_radians()).abs()
+}
+
+/// The area a GEOSEARCH looks in
+#[derive(Debug, Clone, Copy)]
+pub struct GeoShape {
+    pub longitude: f64,
+    pub latitude: f64,
+    /// Meters per unit of the radius, width and height
+    pub conversion: f64,
+    pub kind: GeoShapeKind,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub enum GeoShapeKind {
+    Radius(f64),
+    Box { width: f64, height: f64 },
+}
+
+impl GeoShape {
+    /// The distance in meters from the center to a position inside the
+    /// shape, None if it lies outside
+    pub fn distance_to(&self, longitude: f64, latitude: f64) -> Option<f64> {
+        match self.kind {
+            GeoShapeKind::Radius(radius) => {
+                let distance = geo_distance(self.longitude, self.latitude, longitude, latitude);
+                (distance <= radius * self.conversion).then_some(distance)
+            }
+            GeoShapeKind::Box { width, height } => {
+                // Latitude distance is cheaper, so it is checked first
+                if geo_lat_distance(latitude, self.latitude) > height * self.conversion / 2.0 {
+                    return None;
+                }
+                // Measured along the member's own parallel, as Redis does
+                let long_distance = geo_distance(longitude, latitude, self.longitude, latitude);
+                if long_distance > width * self.conversion / 2.0 {
+                    return None;
+                }
+                Some(geo_distance(
+                    self.longitude,
+                    self.latitude,
+                    longitude,
+                    latitude,
+                ))
+            }
+        }
+    }
+
+    /// The cells to scan for members inside the shape: the one holding the
+    /// center and those of its neighbors the shape reaches into, at a
+    /// precision where the cells are about as large as the shape
+    pub fn search_cells(&self) -> Vec<GeoHash> {
+        let (min_lon, min_lat, max_lon, max_lat) = self.bounding_box();
+        let radius = match self.kind {
+            GeoShapeKind::Radius(radius) => radius,
+            GeoShapeKind::Box { width, height } => {
+                ((width / 2.0) * (width / 2.0) + (height / 2.0) * (height / 2.0)).sqrt()
+            }
+        } * self.conversion;
+
+        let mut step = estimate_steps(radius, self.latitude);
+        let mut hash = self.center_cell(step);
+        let mut neighbors = Neighbors::of(hash);
+
+        // Near the edge of the center cell a neighbor may not reach far
+        // enough, in which case larger cells are used
+        let decrease = geohash_decode(WGS84_RANGES, neighbors.north).latitude.max < max_lat
+            || geohash_decode(WGS84_RANGES, neighbors.south).latitude.min > min_lat
+            || geohash_decode(WGS84_RANGES, neighbors.east).longitude.max < max_lon
+            || geohash_decode(WGS84_RANGES, neighbors.west).longitude.min > min_lon;
+        if step > 1 && decrease {
+            step -= 1;
+            hash = self.center_cell(step);
+            neighbors = Neighbors::of(hash);
+        }
+
+        // Leave out the neighbors the shape does not reach
+        let area = geohash_decode(WGS84_RANGES, hash);
+        if step >= 2 {
+            let zero = GeoHash::default();
+            if area.latitude.min < min_lat {
+                (neighbors.south, neighbors.south_west, neighbors.south_east) = (zero, zero, zero);
+            }
+            if area.latitude.max > max_lat {
+                (neighbors.north, neighbors.north_east, neighbors.north_west) = (zero, zero, zero);
+            }
+            if area.longitude.min < min_lon {
+                (neighbors.west, neighbors.south_west, neighbors.north_west) = (zero, zero, zero);
+            }
+            if area.longitude.max > max_lon {
+                (neighbors.east, neighbors.south_east, neighbors.north_east) = (zero, zero, zero);
+            }
+        }
+
+        let cells = [
+            hash,
+            neighbors.north,
+            neighbors.south,
+            neighbors.east,
+            neighbors.west,
+            neighbors.north_east,
+            neighbors.north_west,
+            neighbors.south_east,
+            neighbors.south_west,
+        ];
+        // With very large shapes adjacent neighbors can be the same cell.
+        // Like Redis, a neighbor repeating the center cell is not skipped.
+        let mut searched = Vec::with_capacity(cells.len());
+        let mut last = 0;
+        for (i, cell) in cells.iter().enumerate() {
+            if cell.is_zero() || (last != 0 && *cell == cells[last]) {
+                continue;
+            }
+            searched.push(*cell);
+            last = i;
+        }
+        searched
+    }
+
+    fn center_cell(&self, step: u8) -> GeoHash {
+        geohash_encode(WGS84_RANGES, self.longitude, self.latitude, step)
+            .expect("center was validated")
+    }
+
+    /// `(min_lon, min_lat, max_lon, max_lat)` around the shape
+    fn bounding_box(&self) -> (f64, f64, f64, f64) {
+        let (width, height) = match self.kind {
+            GeoShapeKind::Radius(radius) => (radius, radius),
+            GeoShapeKind::Box { width, height } => (width / 2.0, height / 2.0),
+        };
+        let (width, height) = (width * self.conversion, height * self.conversion);
+        let lat_delta = f64::to_degrees(height / EARTH_RADIUS_IN_METERS);
+        let long_delta_top = f64::to_degrees(
+            width / EARTH_RADIUS_IN_METERS / (self.latitude + lat_delta).to_radians().cos(),
+        );
+        let long_delta_bottom = f64::to_degrees(
+            width / EARTH_RADIUS_IN_METERS / (self.latitude - lat_delta).to_radians().cos(),
+        );
+        // The widest edge is nearest the equator
+        let long_delta = match self.latitude < 0.0 {
+            true => long_delta_bottom,
+            false => long_delta_top,
+        };
+        (
+            self.longitude - long_delta,
+            self.latitude - lat_delta,
+            self.longitude + long_delta,
+            self.latitude + lat_delta,
+        )
+    }
+}
+
+/// The eight cells around a cell
+struct Neighbors {
+    north: GeoHash,
+    south: GeoHash,
+    east: GeoHash,
+    west: GeoHash,
+    north_east: GeoHash,
+    north_west: GeoHash,
+    south_east: GeoHash,
+    south_west: GeoHash,
+}
+
+impl Neighbors {
+    fn of(hash: GeoHash) -> Neighbors {
+        Neighbors {
+            north: hash.moved(0, 1),
+            south: hash.moved(0, -1),
+            east: hash.moved(1, 0),
+            west: hash.moved(-1, 0),
+            north_east: hash.moved(1, 1),
+            north_west: hash.moved(-1, 1),
+            south_east: hash.moved(1, -1),
+            south_west: hash.moved(-1, -1),
+        }
+    }
+}
+
+/// The precision whose cells are about as large as `radius` meters, made
+/// coarser towards the poles where cells narrow
+fn estimate_steps(mut radius: f64, latitude: f64) -> u8 {
+    if radius == 0.0 {
+        return GEO_STEP_MAX;
+    }
+    let mut step: i32 = 1;
+    while radius < MERCATOR_MAX {
+        radius *= 2.0;
+        step += 1;
+    }
+    step -= 2;
+    if latitude.abs() > 66.0 {
+        step -= 1;
+        if latitude.abs() > 80.0 {
+            step -= 1;
+        }
+    }
+    step.clamp(1, GEO_STEP_MAX as i32) as u8
+}
+
+/// Spread `x` over the even bits and `y` over the odd bits
+fn interleave(x: u32, y: u32) -> u64 {
+    spread(x) | (spread(y) << 1)
+}
+
+/// Split even bits into the low half and odd bits into the high half
+fn deinterleave(bits: u64) -> u64 {
+    squash(bits) | (squash(bits >> 1) << 32)
+}
+
+fn spread(value: u32) -> u64 {
+    const MASKS: [(u32, u64); 5] = [
+        (16, 0x0000ffff0000ffff),
+        (8, 0x00ff00ff00ff00ff),
+        (4, 0x0f0f0f0f0f0f0f0f),
+        (2, 0x3333333333333333),
+        (1, 0x5555555555555555),
+    ];
+    let mut value = value as u64;
+    for (shift, mask) in MASKS {
+        value = (value | (value << shift)) & mask;
+    }
+    value
+}
+
+fn squash(value: u64) -> u64 {
+    const MASKS: [(u32, u64); 6] = [
+        (0, 0x5555555555555555),
+        (1, 0x3333333333333333),
+        (2, 0x0f0f0f0f0f0f0f0f),
+        (4, 0x00ff00ff00ff00ff),
+        (8, 0x0000ffff0000ffff),
+        (16, 0x00000000ffffffff),
+    ];
+    let mut value = value;
+    for (shift, mask) in MASKS {
+        value = (value | (value >> shift)) & mask;
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn box_measures_longitude_at_the_member_latitude() {
+        let shape = GeoShape {
+            longitude: 0.0,
+            latitude: 60.0,
+            conversion: 1000.0,
+            kind: GeoShapeKind::Box {
+                width: 200.0,
+                height: 200.0,
+            },
+        };
+        // About 99km east along 60.8N, but 101km along the center's 60N
+        let distance = shape.distance_to(1.824, 60.8).expect("inside the box");
+        assert!((distance - 134_004.6).abs() < 1.0, "{}", distance);
+        assert_eq!(shape.distance_to(1.9, 60.8), None);
+        assert_eq!(shape.distance_to(0.0, 61.0), None);
+    }
+}
@@ -0,0 +1,136 @@
+/// Match `string` against a Redis glob-style pattern.
+///
+/// Supports `*`, `?`, character classes such as `[abc]`, `[^a]` and `[a-z]`,
+/// and `\` to escape the next character, following Redis' `stringmatchlen`.
+pub fn glob_match(pattern: &[u8], string: &[u8]) -> bool {
+    let (mut p, mut s) = (0, 0);
+    // Where to resume after the last `*` if the rest fails to match
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while s < string.len() {
+        let matched = match pattern.get(p) {
+            Some(b'*') => {
+                // Collapse consecutive stars, then try to match nothing first
+                while pattern.get(p) == Some(&b'*') {
+                    p += 1;
+                }
+                if p == pattern.len() {
+                    return true;
+                }
+                backtrack = Some((p, s));
+                continue;
+            }
+            Some(b'?') => {
+                p += 1;
+                true
+            }
+            Some(b'[') => {
+                let (end, matched) = match_class(pattern, p + 1, string[s]);
+                p = end;
+                matched
+            }
+            Some(b'\\') if p + 1 < pattern.len() => {
+                p += 2;
+                pattern[p - 1] == string[s]
+            }
+            Some(&c) => {
+                p += 1;
+                c == string[s]
+            }
+            None => false,
+        };
+
+        if matched {
+            s += 1;
+        } else if let Some((star_p, star_s)) = backtrack {
+            // Let the last `*` swallow one more character
+            p = star_p;
+            s = star_s + 1;
+            backtrack = Some((star_p, star_s + 1));
+        } else {
+            return false;
+        }
+    }
+
+    // Only stars may be left in the pattern
+    pattern[p.min(pattern.len())..].iter().all(|&c| c == b'*')
+}
+
+/// Match a character against the class starting after `[` at `start`.
+/// Returns the position after the closing `]` and whether it matched.
+fn match_class(pattern: &[u8], start: usize, c: u8) -> (usize, bool) {
+    let mut p = start;
+    let negate = pattern.get(p) == Some(&b'^');
+    if negate {
+        p += 1;
+    }
+
+    let mut matched = false;
+    loop {
+        match pattern.get(p) {
+            // Like Redis, an unterminated class ends with the pattern
+            None => return (p, matched != negate),
+            Some(b']') => return (p + 1, matched != negate),
+            Some(b'\\') if p + 1 < pattern.len() => {
+                matched |= pattern[p + 1] == c;
+                p += 2;
+            }
+            Some(&low) if pattern.get(p + 1) == Some(&b'-') && p + 2 < pattern.len() => {
+                let high = pattern[p + 2];
+                let (low, high) = if low <= high {
+                    (low, high)
+                } else {
+                    (high, low)
+                };
+                matched |= (low..=high).contains(&c);
+                p += 3;
+            }
+            Some(&x) => {
+                matched |= x == c;
+                p += 1;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn matches_wildcards() {
+        assert!(glob_match(b"*", b""));
+        assert!(glob_match(b"*", b"anything"));
+        assert!(glob_match(b"h?llo", b"hello"));
+        assert!(!glob_match(b"h?llo", b"hllo"));
+        assert!(glob_match(b"h*llo", b"hllo"));
+        assert!(glob_match(b"h*llo", b"heeeello"));
+        assert!(glob_match(b"*a*b*", b"xxaxxbxx"));
+        assert!(!glob_match(b"*a*b", b"xxbxxa"));
+        assert!(glob_match(b"user:**:name", b"user:42:name"));
+        assert!(!glob_match(b"abc", b"abcd"));
+        assert!(!glob_match(b"", b"a"));
+    }
+
+    #[test]
+    fn matches_classes() {
+        assert!(glob_match(b"h[ae]llo", b"hallo"));
+        assert!(!glob_match(b"h[ae]llo", b"hillo"));
+        assert!(glob_match(b"h[^e]llo", b"hallo"));
+        assert!(!glob_match(b"h[^e]llo", b"hello"));
+        assert!(glob_match(b"h[a-b]llo", b"hbllo"));
+        assert!(glob_match(b"h[b-a]llo", b"hallo"));
+        assert!(!glob_match(b"h[a-b]llo", b"hcllo"));
+        assert!(glob_match(b"[\\]]", b"]"));
+        // An unterminated class ends with the pattern
+        assert!(glob_match(b"a[bc", b"ab"));
+    }
+
+    #[test]
+    fn matches_escapes() {
+        assert!(glob_match(b"a\\*b", b"a*b"));
+        assert!(!glob_match(b"a\\*b", b"axb"));
+        assert!(glob_match(b"a\\?", b"a?"));
+        assert!(!glob_match(b"a\\?", b"ab"));
+    }
+}
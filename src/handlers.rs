@@ -1,235 +1,866 @@
-use base64;
-use bytes::{BufMut, BytesMut};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use bytes::Bytes;
+use futures::StreamExt;
 use std::{
-    io::Read,
-    net::TcpStream,
-    str,
+    io,
     sync::{Arc, Mutex},
-    time::{Duration, Instant},
 };
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
 
 use crate::{
-    encode_simple_string, return_bulk_string, return_null, return_ok, write_response, Database,
-    Mode, RedisParser, RedisValueRef, Server,
+    arg_bytes, arg_int, bitmaps, blocking, check_arity, check_query_buffer, connection_closed, geo,
+    hashes, hyperloglog, is_keyword, keyspace, lists, sets, sorted_sets, streams, strings,
+    transactions, write_raw, write_response, Client, CommandError, CommandResult, Database, Mode,
+    Protocol, RedisParser, RedisValueRef, Server, SetExpiry,
 };
 
+/// Most bytes of client input echoed back in an error
+const ECHO_MAX_LEN: usize = 128;
+
 pub async fn handle_client(
-    mut stream: TcpStream,
+    stream: TcpStream,
     store: Arc<Mutex<Database>>,
     server_info: Arc<Mutex<Server>>,
-) {
-    let mut buffer = [0; 1024];
-    loop {
+) -> io::Result<()> {
+    // The codec keeps undecoded bytes across reads, so frames split over
+    // several packets are completed and pipelined commands run in order
+    let mut stream = Framed::new(stream, RedisParser::new());
+    let mut client = Client::new();
+    let mut result = Ok(());
+    while let Some(frame) = stream.next().await {
+        let output = match frame {
+            Ok(output) => output,
+            Err(e) => {
+                // Like Redis, report the protocol error and close the connection
+                let response = RedisValueRef::error(&format!("ERR {}", e));
+                let _ = write_response(response, &mut stream).await;
+                break;
+            }
+        };
+
+        // Process Commands
         let db = Arc::clone(&store);
         let server_info = Arc::clone(&server_info);
-        let bytes_read = stream
-            .read(&mut buffer)
-            .expect("Failed to read input command");
-
-        if bytes_read == 0 {
-            return;
+        result = process_command(&output, &mut stream, &mut client, db, server_info).await;
+        if result.is_err() {
+            break;
         }
-
-        // Parse and return inputs
-        let mut bytes = BytesMut::new();
-        bytes.put_slice(&buffer);
-        let mut parser = RedisParser::new();
-        let output = parser
-            .decode(&mut bytes)
-            .expect("failed to decode encryption")
-            .unwrap();
-
-        println!("output: {:?}", &output);
-        // Process Commands
-        process_command(&output, &mut stream, db, server_info).await;
     }
+
+    // Watched keys are the only state the database keeps for a client that
+    // is not parked in a blocking command
+    let mut db = store.lock().unwrap();
+    transactions::unwatch_all(&mut client, &mut db);
+    result
 }
 
 async fn process_command(
     commands: &RedisValueRef,
-    stream: &mut TcpStream,
+    stream: &mut Framed<TcpStream, RedisParser>,
+    client: &mut Client,
     store: Arc<Mutex<Database>>,
     server_info: Arc<Mutex<Server>>,
-) {
-    match commands {
-        RedisValueRef::Array(arr) => {
-            let start_cmd = &arr[0];
-            match start_cmd {
-                RedisValueRef::String(cmd) => {
-                    match str::from_utf8(cmd)
-                        .expect("unable to convert byte to string")
-                        .to_lowercase()
-                        .as_str()
-                    {
-                        "ping" => handle_ping(stream).await,
-                        "echo" => handle_echo(stream, &arr[1..]).await,
-                        "get" => handle_get(stream, &arr[1..], store).await,
-                        "set" => handle_set(stream, &arr[1..], store).await,
-                        "info" => handle_info(stream, &arr[1..], server_info).await,
-                        "replconf" => handle_replconf(stream, &arr[1..]),
-                        "psync" => handle_psync(stream, &arr[1..], server_info),
-                        _ => println!("Unknown command"),
-                    }
+) -> io::Result<()> {
+    let arr = match commands {
+        // Redis silently ignores empty commands
+        RedisValueRef::Array(arr) if arr.is_empty() => return Ok(()),
+        RedisValueRef::Array(arr) => arr,
+        _ => return write_response(CommandError::InvalidRequest.into(), stream).await,
+    };
+    let name = match arg_bytes(&arr[0]) {
+        Ok(name) => String::from_utf8_lossy(name).to_lowercase(),
+        Err(e) => return write_response(e.into(), stream).await,
+    };
+    let args = &arr[1..];
+
+    // Between MULTI and EXEC commands are only checked and queued
+    if client.transaction.is_some()
+        && !matches!(name.as_str(), "multi" | "exec" | "discard" | "watch")
+    {
+        let response = queue_command(&name, args, client);
+        return write_response(response, stream).await;
+    }
+
+    let response = match name.as_str() {
+        // PSYNC is followed by the raw RDB payload
+        "psync" => return handle_psync(stream, args, server_info).await,
+        "blpop" => {
+            let closed = connection_closed(stream);
+            lists::handle_blocking("blpop", args, client.id, &store, closed).await
+        }
+        "brpop" => {
+            let closed = connection_closed(stream);
+            lists::handle_blocking("brpop", args, client.id, &store, closed).await
+        }
+        "blmove" => {
+            let closed = connection_closed(stream);
+            lists::handle_blocking("blmove", args, client.id, &store, closed).await
+        }
+        "brpoplpush" => {
+            let closed = connection_closed(stream);
+            lists::handle_blocking("brpoplpush", args, client.id, &store, closed).await
+        }
+        "xread" => {
+            let closed = connection_closed(stream);
+            streams::handle_xread(args, client.id, &store, closed).await
+        }
+        "xreadgroup" => {
+            let closed = connection_closed(stream);
+            streams::handle_xreadgroup(args, client.id, &store, closed).await
+        }
+        "multi" => transactions::handle_multi(args, client),
+        "exec" => handle_exec(args, client, &store, &server_info),
+        _ => {
+            let mut db = store.lock().unwrap();
+            let response = execute(&name, args, client, &server_info, &mut db);
+            // Writes may have given data to clients blocked on a list or stream
+            blocking::serve_blocked(&mut db);
+            response
+        }
+    };
+    let response = response.unwrap_or_else(RedisValueRef::from);
+    check_query_buffer(stream)?;
+
+    // HELLO may have switched the protocol, its own reply already uses the new one
+    stream.codec_mut().set_protocol(client.protocol);
+    write_response(response, stream).await
+}
+
+/// Commands that only need the database
+type DbHandler = fn(&[RedisValueRef], &mut Database) -> CommandResult;
+
+/// Run a command that never waits, with the database lock held. EXEC runs
+/// its queued commands through here too.
+fn execute(
+    name: &str,
+    args: &[RedisValueRef],
+    client: &mut Client,
+    server_info: &Arc<Mutex<Server>>,
+    db: &mut Database,
+) -> CommandResult {
+    match name {
+        "ping" => handle_ping(args),
+        "echo" => handle_echo(args),
+        "info" => handle_info(args, Arc::clone(server_info)),
+        "hello" => handle_hello(args, client, Arc::clone(server_info)),
+        "replconf" => handle_replconf(args),
+        "watch" => transactions::handle_watch(args, client, db),
+        "unwatch" => transactions::handle_unwatch(args, client, db),
+        "discard" => transactions::handle_discard(args, client, db),
+        // Only queued blocking commands get here, they never wait
+        "blpop" => lists::try_blocking("blpop", args, db),
+        "brpop" => lists::try_blocking("brpop", args, db),
+        "blmove" => lists::try_blocking("blmove", args, db),
+        "brpoplpush" => lists::try_blocking("brpoplpush", args, db),
+        "xread" => streams::try_xread(args, db),
+        "xreadgroup" => streams::try_xreadgroup(args, db),
+        _ => match db_command(name) {
+            Some(handler) => handler(args, db),
+            None => Err(unknown_command(name, args)),
+        },
+    }
+}
+
+/// Look up a command that only needs the database
+fn db_command(name: &str) -> Option<DbHandler> {
+    let handler: DbHandler = match name {
+        "get" => handle_get,
+        "set" => handle_set,
+        "incr" => |args, db| strings::handle_incr("incr", args, db),
+        "decr" => |args, db| strings::handle_incr("decr", args, db),
+        "incrby" => |args, db| strings::handle_incr("incrby", args, db),
+        "decrby" => |args, db| strings::handle_incr("decrby", args, db),
+        "incrbyfloat" => strings::handle_incrbyfloat,
+        "append" => strings::handle_append,
+        "strlen" => strings::handle_strlen,
+        "getrange" => strings::handle_getrange,
+        "setrange" => strings::handle_setrange,
+        "mget" => strings::handle_mget,
+        "mset" => |args, db| strings::handle_mset("mset", args, db),
+        "msetnx" => |args, db| strings::handle_mset("msetnx", args, db),
+        "getset" => strings::handle_getset,
+        "getdel" => strings::handle_getdel,
+        "getex" => strings::handle_getex,
+        "setbit" => bitmaps::handle_setbit,
+        "getbit" => bitmaps::handle_getbit,
+        "bitcount" => bitmaps::handle_bitcount,
+        "bitpos" => bitmaps::handle_bitpos,
+        "bitop" => bitmaps::handle_bitop,
+        "bitfield" => |args, db| bitmaps::handle_bitfield("bitfield", args, db),
+        "bitfield_ro" => |args, db| bitmaps::handle_bitfield("bitfield_ro", args, db),
+        "pfadd" => hyperloglog::handle_pfadd,
+        "pfcount" => hyperloglog::handle_pfcount,
+        "pfmerge" => hyperloglog::handle_pfmerge,
+        "expire" => |args, db| keyspace::handle_expire("expire", args, db),
+        "pexpire" => |args, db| keyspace::handle_expire("pexpire", args, db),
+        "expireat" => |args, db| keyspace::handle_expire("expireat", args, db),
+        "pexpireat" => |args, db| keyspace::handle_expire("pexpireat", args, db),
+        "ttl" => |args, db| keyspace::handle_ttl("ttl", args, db),
+        "pttl" => |args, db| keyspace::handle_ttl("pttl", args, db),
+        "expiretime" => |args, db| keyspace::handle_ttl("expiretime", args, db),
+        "pexpiretime" => |args, db| keyspace::handle_ttl("pexpiretime", args, db),
+        "persist" => keyspace::handle_persist,
+        "del" => |args, db| keyspace::handle_del("del", args, db),
+        "unlink" => |args, db| keyspace::handle_del("unlink", args, db),
+        "exists" => |args, db| keyspace::handle_exists("exists", args, db),
+        "touch" => |args, db| keyspace::handle_exists("touch", args, db),
+        "type" => keyspace::handle_type,
+        "rename" => |args, db| keyspace::handle_rename("rename", args, db),
+        "renamenx" => |args, db| keyspace::handle_rename("renamenx", args, db),
+        "copy" => keyspace::handle_copy,
+        "keys" => keyspace::handle_keys,
+        "scan" => keyspace::handle_scan,
+        "lpush" => |args, db| lists::handle_push("lpush", args, db),
+        "rpush" => |args, db| lists::handle_push("rpush", args, db),
+        "lpushx" => |args, db| lists::handle_push("lpushx", args, db),
+        "rpushx" => |args, db| lists::handle_push("rpushx", args, db),
+        "lpop" => |args, db| lists::handle_pop("lpop", args, db),
+        "rpop" => |args, db| lists::handle_pop("rpop", args, db),
+        "llen" => lists::handle_llen,
+        "lrange" => lists::handle_lrange,
+        "lindex" => lists::handle_lindex,
+        "lset" => lists::handle_lset,
+        "lrem" => lists::handle_lrem,
+        "ltrim" => lists::handle_ltrim,
+        "linsert" => lists::handle_linsert,
+        "lmove" => lists::handle_lmove,
+        "rpoplpush" => lists::handle_rpoplpush,
+        "hset" => |args, db| hashes::handle_hset("hset", args, db),
+        "hmset" => |args, db| hashes::handle_hset("hmset", args, db),
+        "hget" => hashes::handle_hget,
+        "hmget" => hashes::handle_hmget,
+        "hdel" => hashes::handle_hdel,
+        "hgetall" => hashes::handle_hgetall,
+        "hkeys" => |args, db| hashes::handle_hkeys("hkeys", args, db),
+        "hvals" => |args, db| hashes::handle_hkeys("hvals", args, db),
+        "hlen" => hashes::handle_hlen,
+        "hexists" => hashes::handle_hexists,
+        "hincrby" => hashes::handle_hincrby,
+        "hscan" => hashes::handle_hscan,
+        "sadd" => sets::handle_sadd,
+        "srem" => sets::handle_srem,
+        "smembers" => sets::handle_smembers,
+        "sismember" => sets::handle_sismember,
+        "scard" => sets::handle_scard,
+        "spop" => sets::handle_spop,
+        "srandmember" => sets::handle_srandmember,
+        "sinter" => |args, db| sets::handle_setop("sinter", args, db),
+        "sunion" => |args, db| sets::handle_setop("sunion", args, db),
+        "sdiff" => |args, db| sets::handle_setop("sdiff", args, db),
+        "sinterstore" => |args, db| sets::handle_setop_store("sinterstore", args, db),
+        "sunionstore" => |args, db| sets::handle_setop_store("sunionstore", args, db),
+        "sdiffstore" => |args, db| sets::handle_setop_store("sdiffstore", args, db),
+        "sscan" => sets::handle_sscan,
+        "zadd" => sorted_sets::handle_zadd,
+        "zrange" => sorted_sets::handle_zrange,
+        "zrank" => |args, db| sorted_sets::handle_zrank("zrank", args, db),
+        "zrevrank" => |args, db| sorted_sets::handle_zrank("zrevrank", args, db),
+        "zscore" => sorted_sets::handle_zscore,
+        "zcard" => sorted_sets::handle_zcard,
+        "zrem" => sorted_sets::handle_zrem,
+        "zincrby" => sorted_sets::handle_zincrby,
+        "zcount" => sorted_sets::handle_zcount,
+        "zpopmin" => |args, db| sorted_sets::handle_zpop("zpopmin", args, db),
+        "zpopmax" => |args, db| sorted_sets::handle_zpop("zpopmax", args, db),
+        "zunionstore" => |args, db| sorted_sets::handle_zsetop_store("zunionstore", args, db),
+        "zinterstore" => |args, db| sorted_sets::handle_zsetop_store("zinterstore", args, db),
+        "geoadd" => geo::handle_geoadd,
+        "geodist" => geo::handle_geodist,
+        "geopos" => geo::handle_geopos,
+        "geohash" => geo::handle_geohash,
+        "geosearch" => |args, db| geo::handle_geosearch("geosearch", args, db),
+        "geosearchstore" => |args, db| geo::handle_geosearch("geosearchstore", args, db),
+        "xadd" => streams::handle_xadd,
+        "xrange" => |args, db| streams::handle_xrange("xrange", args, db),
+        "xrevrange" => |args, db| streams::handle_xrange("xrevrange", args, db),
+        "xlen" => streams::handle_xlen,
+        "xdel" => streams::handle_xdel,
+        "xgroup" => streams::handle_xgroup,
+        "xack" => streams::handle_xack,
+        "xpending" => streams::handle_xpending,
+        "xclaim" => streams::handle_xclaim,
+        "xautoclaim" => streams::handle_xautoclaim,
+        "xinfo" => streams::handle_xinfo,
+        _ => return None,
+    };
+    Some(handler)
+}
+
+/// Queue a command sent between MULTI and EXEC. A command that could never
+/// run is rejected right away and makes EXEC discard the transaction.
+fn queue_command(name: &str, args: &[RedisValueRef], client: &mut Client) -> RedisValueRef {
+    let transaction = client
+        .transaction
+        .as_mut()
+        .expect("client is in a transaction");
+    let queueable = matches!(
+        name,
+        "ping"
+            | "echo"
+            | "info"
+            | "hello"
+            | "unwatch"
+            | "blpop"
+            | "brpop"
+            | "blmove"
+            | "brpoplpush"
+            | "xread"
+            | "xreadgroup"
+    ) || db_command(name).is_some();
+    let error = match name {
+        "psync" | "replconf" => CommandError::NotInTransaction,
+        _ if queueable => {
+            transaction.commands.push((name.to_string(), args.to_vec()));
+            return RedisValueRef::simple_string("QUEUED");
+        }
+        _ => unknown_command(name, args),
+    };
+    transaction.failed = true;
+    error.into()
+}
+
+/// EXEC, runs the queued commands under a single database lock so no other
+/// client sees or changes anything in between. Replies null without running
+/// them when a watched key was modified since WATCH.
+fn handle_exec(
+    args: &[RedisValueRef],
+    client: &mut Client,
+    store: &Arc<Mutex<Database>>,
+    server_info: &Arc<Mutex<Server>>,
+) -> CommandResult {
+    check_arity("exec", args, 0, Some(0))?;
+    let transaction = client
+        .transaction
+        .take()
+        .ok_or(CommandError::ExecWithoutMulti)?;
+    let mut db = store.lock().unwrap();
+    // Unwatch first, the transaction's own writes must not count
+    let modified = transactions::unwatch_all(client, &mut db);
+    if transaction.failed {
+        return Err(CommandError::ExecAbort);
+    }
+    if modified {
+        return Ok(RedisValueRef::NullArray);
+    }
+    let replies = transaction
+        .commands
+        .iter()
+        .map(|(name, args)| {
+            execute(name, args, client, server_info, &mut db).unwrap_or_else(RedisValueRef::from)
+        })
+        .collect();
+    blocking::serve_blocked(&mut db);
+    Ok(RedisValueRef::Array(replies))
+}
+
+/// The error for an unknown command, echoing at most 128 bytes of the name
+/// and of its arguments like Redis does
+fn unknown_command(name: &str, args: &[RedisValueRef]) -> CommandError {
+    let name = &name.as_bytes()[..name.len().min(ECHO_MAX_LEN)];
+    let mut echoed = String::new();
+    for arg in args {
+        if echoed.len() >= ECHO_MAX_LEN {
+            break;
+        }
+        if let RedisValueRef::String(b) = arg {
+            let b = &b[..b.len().min(ECHO_MAX_LEN - echoed.len())];
+            echoed.push_str(&format!("'{}' ", String::from_utf8_lossy(b)));
+        }
+    }
+    CommandError::UnknownCommand(String::from_utf8_lossy(name).into_owned(), echoed)
+}
+
+fn handle_hello(
+    commands: &[RedisValueRef],
+    client: &mut Client,
+    server_info: Arc<Mutex<Server>>,
+) -> CommandResult {
+    let mut protocol = client.protocol;
+    let mut name = None;
+
+    if let Some(version) = commands.first() {
+        protocol = match arg_int(version) {
+            Ok(2) => Protocol::Resp2,
+            Ok(3) => Protocol::Resp3,
+            Ok(_) => return Err(CommandError::NoProto),
+            Err(e) => return Err(e),
+        };
+
+        let mut options = commands[1..].iter();
+        while let Some(option) = options.next() {
+            if is_keyword(option, "auth") {
+                match (options.next(), options.next()) {
+                    // No ACLs are configured, the default user accepts any password
+                    (Some(user), Some(_)) if is_keyword(user, "default") => {}
+                    (Some(_), Some(_)) => return Err(CommandError::WrongPass),
+                    _ => return Err(CommandError::Syntax),
+                }
+            } else if is_keyword(option, "setname") {
+                match options.next() {
+                    Some(n) => name = Some(arg_bytes(n)?.clone()),
+                    None => return Err(CommandError::Syntax),
                 }
-                _ => todo!(),
+            } else {
+                return Err(CommandError::Syntax);
             }
         }
-        _ => todo!(),
     }
+
+    client.protocol = protocol;
+    if name.is_some() {
+        client.name = name;
+    }
+
+    let role = match server_info.lock().unwrap().mode {
+        Mode::Master => "master",
+        Mode::Slave => "replica",
+    };
+    let proto = match protocol {
+        Protocol::Resp2 => 2,
+        Protocol::Resp3 => 3,
+    };
+    Ok(RedisValueRef::Map(vec![
+        (
+            RedisValueRef::bulk_string("server"),
+            RedisValueRef::bulk_string("redis"),
+        ),
+        (
+            RedisValueRef::bulk_string("version"),
+            RedisValueRef::bulk_string("7.2.0"),
+        ),
+        (
+            RedisValueRef::bulk_string("proto"),
+            RedisValueRef::Int(proto),
+        ),
+        (
+            RedisValueRef::bulk_string("id"),
+            RedisValueRef::Int(client.id as i64),
+        ),
+        (
+            RedisValueRef::bulk_string("mode"),
+            RedisValueRef::bulk_string("standalone"),
+        ),
+        (
+            RedisValueRef::bulk_string("role"),
+            RedisValueRef::bulk_string(role),
+        ),
+        (
+            RedisValueRef::bulk_string("modules"),
+            RedisValueRef::Array(vec![]),
+        ),
+    ]))
 }
 
-fn handle_psync(
-    stream: &mut TcpStream,
+async fn handle_psync(
+    stream: &mut Framed<TcpStream, RedisParser>,
     _commands: &[RedisValueRef],
     server_info: Arc<Mutex<Server>>,
-) {
-    let repl_id = server_info.lock().unwrap().clone().master_replid.unwrap();
-    let offset = server_info.lock().unwrap().clone().master_repl_offset;
+) -> io::Result<()> {
+    let (repl_id, offset) = {
+        let server_info = server_info.lock().unwrap();
+        (
+            server_info.master_replid.clone().unwrap_or_default(),
+            server_info.master_repl_offset,
+        )
+    };
 
     let response = format!("FULLRESYNC {} {}", repl_id, offset);
-    let response = encode_simple_string(&response);
-    return_bulk_string(response, stream);
+    write_response(RedisValueRef::simple_string(&response), stream).await?;
 
     // Decode and Sending RDB file
     let base64_rdb = "UkVESVMwMDEx+glyZWRpcy12ZXIFNy4yLjD6CnJlZGlzLWJpdHPAQPoFY3RpbWXCbQi8ZfoIdXNlZC1tZW3CsMQQAPoIYW9mLWJhc2XAAP/wbjv+wP9aog==";
-    let rdb = base64::decode(base64_rdb).unwrap();
+    let rdb = STANDARD
+        .decode(base64_rdb)
+        .expect("embedded RDB file is valid base64");
     let msg = format!("${}\r\n", rdb.len());
-    write_response(msg.as_bytes(), stream);
-    write_response(&rdb, stream);
+    write_raw(msg.as_bytes(), stream).await?;
+    write_raw(&rdb, stream).await
 }
 
-fn handle_replconf(stream: &mut TcpStream, _commands: &[RedisValueRef]) {
-    return_ok(stream)
+fn handle_replconf(_commands: &[RedisValueRef]) -> CommandResult {
+    Ok(RedisValueRef::ok())
 }
 
-async fn handle_info(
-    stream: &mut TcpStream,
-    _commands: &[RedisValueRef],
-    server_info: Arc<Mutex<Server>>,
-) {
-    // Extract master replid
-    let master_replid = server_info
+fn handle_info(_commands: &[RedisValueRef], server_info: Arc<Mutex<Server>>) -> CommandResult {
+    let server_info = server_info
         .lock()
-        .expect("unable to get lock for server info")
-        .master_replid
-        .clone()
-        .expect("unable to get master_replid");
+        .expect("unable to get lock for server info");
+
+    // Extract master replid
+    let master_replid = server_info.master_replid.clone().unwrap_or_default();
 
     // Extract master repl offset
-    let master_repl_offset = server_info
-        .lock()
-        .expect("unable to get lock for server info")
-        .master_repl_offset;
+    let master_repl_offset = server_info.master_repl_offset;
 
-    let mode = server_info.lock().unwrap().mode.clone();
+    let mode = server_info.mode.clone();
 
-    match mode {
-        Mode::Master => {
-            let response = format!(
-                "role:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:{}",
-                mode, master_replid, master_repl_offset
-            );
-            return_bulk_string(response, stream);
-        }
-        Mode::Slave => {
-            let value = format!("role:{}", mode);
-            return_bulk_string(value, stream);
-        }
-    }
+    let response = match mode {
+        Mode::Master => format!(
+            "role:{}\r\nmaster_replid:{}\r\nmaster_repl_offset:{}",
+            mode, master_replid, master_repl_offset
+        ),
+        Mode::Slave => format!("role:{}", mode),
+    };
+    Ok(RedisValueRef::VerbatimString(
+        Bytes::from_static(b"txt"),
+        Bytes::from(response),
+    ))
 }
 
-async fn handle_set(
-    stream: &mut TcpStream,
-    commands: &[RedisValueRef],
-    store: Arc<Mutex<Database>>,
-) {
-    if commands.len() == 2 {
-        // set without ttl
-        match (&commands[0], &commands[1]) {
-            (RedisValueRef::String(k), RedisValueRef::String(v)) => {
-                let key = str::from_utf8(k).expect("failed to decode buffer");
-                let value = str::from_utf8(v).expect("failed to decode buffer");
-                let _result = store.lock().unwrap().set(key, value, None);
-
-                // Write the response
-                return_ok(stream);
+/// SET key value [NX | XX] [GET] [EX seconds | PX milliseconds |
+/// EXAT unix-time-seconds | PXAT unix-time-milliseconds | KEEPTTL]
+fn handle_set(commands: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("set", commands, 2, None)?;
+    let key = arg_bytes(&commands[0])?.clone();
+    let value = arg_bytes(&commands[1])?.clone();
+
+    // None when unconditional, Some(true) for NX and Some(false) for XX
+    let mut if_missing = None;
+    let mut get = false;
+    let mut expiry = None;
+    let mut options = commands[2..].iter();
+    while let Some(option) = options.next() {
+        if is_keyword(option, "nx") || is_keyword(option, "xx") {
+            if if_missing.is_some() {
+                return Err(CommandError::Syntax);
             }
-            (_, _) => todo!(),
+            if_missing = Some(is_keyword(option, "nx"));
+        } else if is_keyword(option, "get") {
+            get = true;
+        } else if is_keyword(option, "keepttl") {
+            if expiry.is_some() {
+                return Err(CommandError::Syntax);
+            }
+            expiry = Some(SetExpiry::KeepTtl);
+        } else if let Some(unit) = ["ex", "px", "exat", "pxat"]
+            .into_iter()
+            .find(|unit| is_keyword(option, unit))
+        {
+            let amount = options.next().ok_or(CommandError::Syntax)?;
+            if expiry.is_some() {
+                return Err(CommandError::Syntax);
+            }
+            expiry = Some(SetExpiry::At(strings::expiry_millis(
+                "set",
+                unit,
+                arg_int(amount)?,
+            )?));
+        } else {
+            return Err(CommandError::Syntax);
         }
     }
 
-    if commands.len() == 4 {
-        match (&commands[0], &commands[1], &commands[2], &commands[3]) {
-            (
-                RedisValueRef::String(k),
-                RedisValueRef::String(v),
-                RedisValueRef::String(opt),
-                RedisValueRef::String(ttl),
-            ) => {
-                let key = str::from_utf8(k).expect("failed to decode buffer");
-                let value = str::from_utf8(v).expect("failed to decode buffer");
-                let opt = str::from_utf8(opt).expect("failed to decode buffer");
-                let ttl = str::from_utf8(ttl)
-                    .unwrap()
-                    .parse::<u64>()
-                    .expect("failed to decode TTL");
-                let ttl = Duration::from_millis(ttl);
-                if opt.to_lowercase() == "px" {
-                    let _result = store.lock().unwrap().set(key, value, Some(ttl));
-                    // Write the response
-                    return_ok(stream);
-                }
-            }
-            (_, _, _, _) => todo!(),
-        }
+    // Only GET cares about the old value being a string
+    let old_value = match db.get_string(&key) {
+        Ok(old) => old.cloned(),
+        Err(e) if get => return Err(e),
+        Err(_) => None,
+    };
+    let exists = db.exists(&key);
+    let apply = match if_missing {
+        Some(true) => !exists,
+        Some(false) => exists,
+        None => true,
+    };
+    if apply {
+        db.set(key, value, expiry.unwrap_or(SetExpiry::Persist));
+    }
+
+    match (get, old_value) {
+        (true, Some(old_value)) => Ok(RedisValueRef::bulk_string(old_value)),
+        (true, None) => Ok(RedisValueRef::NullBulkString),
+        (false, _) if apply => Ok(RedisValueRef::ok()),
+        (false, _) => Ok(RedisValueRef::NullBulkString),
     }
 }
 
-async fn handle_get(
-    stream: &mut TcpStream,
-    commands: &[RedisValueRef],
-    store: Arc<Mutex<Database>>,
-) {
-    match &commands[0] {
-        RedisValueRef::String(k) => {
-            let key = str::from_utf8(k).expect("failed to decode buffer");
-            let result = store.lock().unwrap().get(key);
-            match result {
-                Some(set_object) => match set_object.duration {
-                    Some(duration) => {
-                        // Compute the duration of set object
-                        // If not expired, return value else return NIL
-                        if Instant::now() > duration {
-                            return_null(stream)
-                        } else {
-                            return_bulk_string(set_object.value, stream);
-                        }
-                    }
-                    None => return_bulk_string(set_object.value, stream),
-                },
-                None => {
-                    return_null(stream);
-                }
-            }
-        }
-        _ => todo!(),
+fn handle_get(commands: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("get", commands, 1, Some(1))?;
+    let key = arg_bytes(&commands[0])?;
+    match db.get_string(key)? {
+        Some(value) => Ok(RedisValueRef::String(value.clone())),
+        None => Ok(RedisValueRef::NullBulkString),
     }
 }
 
-async fn handle_ping(stream: &mut TcpStream) {
-    write_response(b"+PONG\r\n", stream)
+fn handle_ping(commands: &[RedisValueRef]) -> CommandResult {
+    check_arity("ping", commands, 0, Some(1))?;
+    match commands.first() {
+        Some(message) => Ok(RedisValueRef::String(arg_bytes(message)?.clone())),
+        None => Ok(RedisValueRef::simple_string("PONG")),
+    }
 }
 
-async fn handle_echo(stream: &mut TcpStream, commands: &[RedisValueRef]) {
-    if commands.is_empty() || commands.len() > 1 {
-        println!("ERR: wrong number of arguments for echo")
-    } else {
-        match &commands[0] {
-            RedisValueRef::String(s) => {
-                let data = str::from_utf8(s).expect("failed to decode buffer");
-                return_bulk_string(data.to_string(), stream)
+fn handle_echo(commands: &[RedisValueRef]) -> CommandResult {
+    check_arity("echo", commands, 1, Some(1))?;
+    Ok(RedisValueRef::String(arg_bytes(&commands[0])?.clone()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args, current_time_millis, Key, SetObject, StoredValue};
+
+    fn set(db: &mut Database, line: &str) -> CommandResult {
+        handle_set(&args(line), db)
+    }
+
+    fn expires_at(db: &mut Database, key: &[u8]) -> Option<u64> {
+        db.get(key).and_then(|object| object.expires_at)
+    }
+
+    #[test]
+    fn set_nx_xx_and_get() {
+        let mut db = Database::new();
+        let null = Ok(RedisValueRef::NullBulkString);
+        assert_eq!(set(&mut db, "k v1 XX"), null);
+        assert_eq!(set(&mut db, "k v1 NX"), Ok(RedisValueRef::ok()));
+        assert_eq!(set(&mut db, "k v2 NX"), null);
+        assert_eq!(
+            set(&mut db, "k v2 XX GET"),
+            Ok(RedisValueRef::bulk_string("v1"))
+        );
+        // GET replies the old value even when NX keeps it
+        assert_eq!(
+            set(&mut db, "k v3 NX GET"),
+            Ok(RedisValueRef::bulk_string("v2"))
+        );
+        assert_eq!(set(&mut db, "new v GET"), null);
+        assert_eq!(db.get_string(b"k").unwrap().unwrap(), "v2");
+
+        db.insert(
+            Key::from_static(b"list"),
+            SetObject::new(StoredValue::List(Default::default()), None),
+        );
+        assert_eq!(set(&mut db, "list v GET"), Err(CommandError::WrongType));
+        assert_eq!(set(&mut db, "list v"), Ok(RedisValueRef::ok()));
+    }
+
+    #[test]
+    fn set_expiry_options() {
+        let mut db = Database::new();
+        let now = current_time_millis();
+        set(&mut db, "k v EX 100").unwrap();
+        let ttl = expires_at(&mut db, b"k").unwrap();
+        assert!((now + 99_000..=now + 101_000).contains(&ttl));
+
+        set(&mut db, "k v KEEPTTL").unwrap();
+        assert_eq!(expires_at(&mut db, b"k"), Some(ttl));
+        set(&mut db, "k v").unwrap();
+        assert_eq!(expires_at(&mut db, b"k"), None);
+
+        let at = now + 50_000;
+        set(&mut db, &format!("k v PXAT {}", at)).unwrap();
+        assert_eq!(expires_at(&mut db, b"k"), Some(at));
+        set(&mut db, &format!("k v EXAT {}", at / 1000)).unwrap();
+        assert_eq!(expires_at(&mut db, b"k"), Some(at / 1000 * 1000));
+
+        // A time in the past stores a key that is already gone
+        set(&mut db, "k v PXAT 1").unwrap();
+        assert!(!db.exists(b"k"));
+    }
+
+    #[test]
+    fn set_rejects_bad_options() {
+        let mut db = Database::new();
+        for line in [
+            "k v NX XX",
+            "k v EX 10 PX 10",
+            "k v EX 10 KEEPTTL",
+            "k v EX",
+            "k v FOO",
+        ] {
+            assert_eq!(set(&mut db, line), Err(CommandError::Syntax), "{}", line);
+        }
+        for line in ["k v EX 0", "k v PX -5", "k v EX 9223372036854775807"] {
+            assert_eq!(
+                set(&mut db, line),
+                Err(CommandError::InvalidExpireTime("set")),
+                "{}",
+                line
+            );
+        }
+        assert_eq!(set(&mut db, "k v EX ten"), Err(CommandError::NotInteger));
+        assert!(!db.exists(b"k"));
+    }
+
+    /// Run a command line for a client the way the connection loop would,
+    /// queueing it inside a transaction
+    fn run(line: &str, client: &mut Client, store: &Arc<Mutex<Database>>) -> RedisValueRef {
+        let server_info = Arc::new(Mutex::new(Server::new(
+            "127.0.0.1".to_string(),
+            "6379".to_string(),
+            Mode::Master,
+            0,
+            None,
+        )));
+        let line = args(line);
+        let name = String::from_utf8_lossy(arg_bytes(&line[0]).unwrap()).to_lowercase();
+        let args = &line[1..];
+        let response = match name.as_str() {
+            "multi" => transactions::handle_multi(args, client),
+            "exec" => handle_exec(args, client, store, &server_info),
+            _ if client.transaction.is_some() => Ok(queue_command(&name, args, client)),
+            _ => execute(
+                &name,
+                args,
+                client,
+                &server_info,
+                &mut store.lock().unwrap(),
+            ),
+        };
+        response.unwrap_or_else(RedisValueRef::from)
+    }
+
+    /// EXEC's reply for a client watching k, when another client runs
+    /// `setup` before the WATCH and `writes` after it
+    fn exec_after(setup: &[&str], writes: &[&str]) -> RedisValueRef {
+        let store = Arc::new(Mutex::new(Database::new()));
+        let (mut watcher, mut other) = (Client::new(), Client::new());
+        for line in setup {
+            run(line, &mut other, &store);
+        }
+        run("WATCH k", &mut watcher, &store);
+        for line in writes {
+            run(line, &mut other, &store);
+        }
+        run("MULTI", &mut watcher, &store);
+        run("PING", &mut watcher, &store);
+        run("EXEC", &mut watcher, &store)
+    }
+
+    fn executed() -> RedisValueRef {
+        RedisValueRef::Array(vec![RedisValueRef::simple_string("PONG")])
+    }
+
+    #[test]
+    fn watch_aborts_exec_after_every_kind_of_write() {
+        let cases: &[(&[&str], &str)] = &[
+            // Whole values inserted, replaced or removed
+            (&[], "SET k v"),
+            (&["SET k v"], "SET k w"),
+            (&["SET k v"], "DEL k"),
+            (&["SET k v"], "GETDEL k"),
+            (&["SET j v"], "MSET j w k v"),
+            (&["SET k 1"], "INCR k"),
+            (&["SET k v"], "APPEND k x"),
+            (&["SET k v"], "SETRANGE k 0 x"),
+            (&["SET j v"], "RENAME j k"),
+            (&["SET k v"], "RENAME k j"),
+            (&["SET j v"], "COPY j k"),
+            (&["SET j v"], "BITOP NOT k j"),
+            (&["SADD j a"], "SUNIONSTORE k j"),
+            // Strings written in place
+            (&[], "SETBIT k 3 1"),
+            (&["SET k v"], "SETBIT k 3 1"),
+            (&["SET k v"], "BITFIELD k INCRBY u8 0 1"),
+            (&[], "PFADD k a"),
+            // PFCOUNT stores the count it computed
+            (&["PFADD k a"], "PFCOUNT k"),
+            // Collections changed in place
+            (&["RPUSH k a b"], "LPOP k"),
+            (&["RPUSH k a"], "RPOP k"),
+            (&["RPUSH k a b"], "LSET k 0 x"),
+            (&["RPUSH k a b"], "LTRIM k 0 0"),
+            (&["RPUSH j a"], "LMOVE j k LEFT LEFT"),
+            (&["RPUSH k a"], "LINSERT k BEFORE a b"),
+            (&["HSET k f v"], "HSET k g v"),
+            (&["HSET k f v"], "HDEL k f"),
+            (&["HSET k f 1"], "HINCRBY k f 1"),
+            (&["SADD k a"], "SADD k b"),
+            (&["SADD k a b"], "SREM k a"),
+            (&["SADD k a b"], "SPOP k"),
+            (&["ZADD k 1 a"], "ZADD k 2 a"),
+            (&["ZADD k 1 a"], "ZINCRBY k 1 a"),
+            (&["ZADD k 1 a 2 b"], "ZPOPMIN k"),
+            (&[], "GEOADD k 13.361389 38.115556 Palermo"),
+            (&[], "XADD k * f v"),
+            (
+                &["XADD k 1-1 f v", "XGROUP CREATE k g 0"],
+                "XREADGROUP GROUP g c STREAMS k >",
+            ),
+            // Expiry changes
+            (&["SET k v"], "EXPIRE k 100"),
+            (&["SET k v EX 100"], "PERSIST k"),
+            (&["SET k v"], "GETEX k PX 100"),
+        ];
+        for (setup, write) in cases {
+            assert_eq!(
+                exec_after(setup, &[write]),
+                RedisValueRef::NullArray,
+                "{} after {:?}",
+                write,
+                setup
+            );
+        }
+    }
+
+    #[test]
+    fn watch_aborts_exec_when_the_key_expires() {
+        let store = Arc::new(Mutex::new(Database::new()));
+        let mut client = Client::new();
+        let exec = |client: &mut Client, reclaim: bool| {
+            run("SET k v PX 20", client, &store);
+            run("WATCH k", client, &store);
+            std::thread::sleep(std::time::Duration::from_millis(40));
+            if reclaim {
+                store.lock().unwrap().active_expire_cycle();
             }
-            _ => todo!(),
+            run("MULTI", client, &store);
+            run("PING", client, &store);
+            run("EXEC", client, &store)
+        };
+        // Whether the key is found expired by EXEC or reclaimed before
+        assert_eq!(exec(&mut client, false), RedisValueRef::NullArray);
+        assert_eq!(exec(&mut client, true), RedisValueRef::NullArray);
+
+        // A key that was already gone when watched never changes
+        run("SET k v PX 1", &mut client, &store);
+        std::thread::sleep(std::time::Duration::from_millis(5));
+        run("WATCH k", &mut client, &store);
+        run("MULTI", &mut client, &store);
+        run("PING", &mut client, &store);
+        assert_eq!(run("EXEC", &mut client, &store), executed());
+    }
+
+    #[test]
+    fn watch_ignores_writes_that_change_nothing() {
+        let cases: &[(&[&str], &str)] = &[
+            (&["SADD k a"], "SREM k missing"),
+            (&["SADD k a"], "SADD k a"),
+            (&["HSET k f v"], "HDEL k missing"),
+            (&["RPUSH k a"], "LREM k 0 missing"),
+            (&["ZADD k 1 a"], "ZREM k missing"),
+            (&["PFADD k a"], "PFADD k a"),
+            (&[], "DEL k"),
+            (&[], "LPOP k"),
+            (&[], "RPUSHX k a"),
+            (&[], "EXPIRE k 100"),
+            (&["SET k v"], "SET k w NX"),
+            (&["SET k v"], "PERSIST k"),
+            (&["SET k v"], "GET k"),
+            (&["SET k v"], "SET j v"),
+        ];
+        for (setup, write) in cases {
+            assert_eq!(
+                exec_after(setup, &[write]),
+                executed(),
+                "{} after {:?}",
+                write,
+                setup
+            );
         }
+
+        // Nor do the transaction's own writes
+        let store = Arc::new(Mutex::new(Database::new()));
+        let mut client = Client::new();
+        run("WATCH k", &mut client, &store);
+        run("MULTI", &mut client, &store);
+        run("SET k v", &mut client, &store);
+        assert_eq!(
+            run("EXEC", &mut client, &store),
+            RedisValueRef::Array(vec![RedisValueRef::ok()])
+        );
     }
 }
@@ -0,0 +1,73 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::ScanIndex;
+
+/// Field value pairs of a hash, plus the fields ordered by scan hash so an
+/// HSCAN call only walks the fields it returns
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Hash {
+    fields: HashMap<Bytes, Bytes>,
+    scan_index: ScanIndex,
+}
+
+impl Hash {
+    pub fn new() -> Hash {
+        Hash::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.fields.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.fields.is_empty()
+    }
+
+    pub fn get(&self, field: &[u8]) -> Option<&Bytes> {
+        self.fields.get(field)
+    }
+
+    pub fn contains_key(&self, field: &[u8]) -> bool {
+        self.fields.contains_key(field)
+    }
+
+    /// Set a field, returning the value it replaced
+    pub fn insert(&mut self, field: Bytes, value: Bytes) -> Option<Bytes> {
+        let old = self.fields.insert(field.clone(), value);
+        if old.is_none() {
+            self.scan_index.insert(field);
+        }
+        old
+    }
+
+    /// Delete a field, returning its value
+    pub fn remove(&mut self, field: &[u8]) -> Option<Bytes> {
+        let (field, value) = self.fields.remove_entry(field)?;
+        self.scan_index.remove(field);
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, &Bytes)> {
+        self.fields.iter()
+    }
+
+    pub fn keys(&self) -> impl Iterator<Item = &Bytes> {
+        self.fields.keys()
+    }
+
+    pub fn values(&self) -> impl Iterator<Item = &Bytes> {
+        self.fields.values()
+    }
+
+    /// Walk the fields from an HSCAN cursor, see `ScanIndex::scan`
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<(&Bytes, &Bytes)>) {
+        let (next, fields) = self.scan_index.scan(cursor, count, |_| true);
+        let pairs = fields
+            .into_iter()
+            .map(|field| (field, &self.fields[field]))
+            .collect();
+        (next, pairs)
+    }
+}
@@ -0,0 +1,259 @@
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, arg_int, check_arity, parse_cursor, parse_int, scan_reply, CommandError,
+    CommandResult, Database, Hash, RedisValueRef, ScanOptions, SetObject, StoredValue,
+};
+
+/// HSET and HMSET key field value [field value ...]
+pub fn handle_hset(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 3, None)?;
+    if args.len() % 2 != 1 {
+        return Err(CommandError::WrongArity(name));
+    }
+    let key = arg_bytes(&args[0])?;
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    for pair in args[1..].chunks(2) {
+        pairs.push((arg_bytes(&pair[0])?.clone(), arg_bytes(&pair[1])?.clone()));
+    }
+
+    let hash = hash_or_create(db, key)?;
+    let mut added = 0;
+    for (field, value) in pairs {
+        if hash.insert(field, value).is_none() {
+            added += 1;
+        }
+    }
+    db.signal_modified(key);
+    match name {
+        "hmset" => Ok(RedisValueRef::ok()),
+        _ => Ok(RedisValueRef::Int(added)),
+    }
+}
+
+/// HGET key field
+pub fn handle_hget(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hget", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let field = arg_bytes(&args[1])?;
+    match hash(db, key)?.and_then(|hash| hash.get(field)) {
+        Some(value) => Ok(RedisValueRef::String(value.clone())),
+        None => Ok(RedisValueRef::NullBulkString),
+    }
+}
+
+/// HMGET key field [field ...]
+pub fn handle_hmget(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hmget", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let hash = hash(db, key)?;
+    let mut values = Vec::with_capacity(args.len() - 1);
+    for field in &args[1..] {
+        let field = arg_bytes(field)?;
+        values.push(match hash.and_then(|hash| hash.get(field)) {
+            Some(value) => RedisValueRef::String(value.clone()),
+            None => RedisValueRef::NullBulkString,
+        });
+    }
+    Ok(RedisValueRef::Array(values))
+}
+
+/// HDEL key field [field ...]
+pub fn handle_hdel(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hdel", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let Some(hash) = hash_mut(db, key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let mut removed = 0;
+    for field in &args[1..] {
+        if hash.remove(arg_bytes(field)?).is_some() {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(RedisValueRef::Int(removed))
+}
+
+/// HGETALL key, a map reply in RESP3
+pub fn handle_hgetall(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hgetall", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let pairs = match hash(db, key)? {
+        Some(hash) => hash
+            .iter()
+            .map(|(field, value)| {
+                (
+                    RedisValueRef::String(field.clone()),
+                    RedisValueRef::String(value.clone()),
+                )
+            })
+            .collect(),
+        None => vec![],
+    };
+    Ok(RedisValueRef::Map(pairs))
+}
+
+/// HKEYS and HVALS key
+pub fn handle_hkeys(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let elements = match hash(db, key)? {
+        Some(hash) if name == "hkeys" => hash.keys().cloned().map(RedisValueRef::String).collect(),
+        Some(hash) => hash.values().cloned().map(RedisValueRef::String).collect(),
+        None => vec![],
+    };
+    Ok(RedisValueRef::Array(elements))
+}
+
+/// HLEN key
+pub fn handle_hlen(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hlen", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let len = hash(db, key)?.map_or(0, |hash| hash.len());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// HEXISTS key field
+pub fn handle_hexists(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hexists", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let field = arg_bytes(&args[1])?;
+    let exists = hash(db, key)?.is_some_and(|hash| hash.contains_key(field));
+    Ok(RedisValueRef::Int(exists as i64))
+}
+
+/// HINCRBY key field increment
+pub fn handle_hincrby(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hincrby", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let field = arg_bytes(&args[1])?;
+    let increment = arg_int(&args[2])?;
+
+    let current = match hash(db, key)?.and_then(|hash| hash.get(field)) {
+        Some(value) => parse_int(value).ok_or(CommandError::HashNotInteger)?,
+        None => 0,
+    };
+    let value = current
+        .checked_add(increment)
+        .ok_or(CommandError::Overflow)?;
+    hash_or_create(db, key)?.insert(field.clone(), Bytes::from(value.to_string()));
+    db.signal_modified(key);
+    Ok(RedisValueRef::Int(value))
+}
+
+/// HSCAN key cursor [MATCH pattern] [COUNT count]
+pub fn handle_hscan(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("hscan", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let cursor = parse_cursor(&args[1])?;
+    let options = ScanOptions::parse(&args[2..], false)?;
+
+    let Some(hash) = hash(db, key)? else {
+        return Ok(scan_reply(0, vec![]));
+    };
+    let (next, pairs) = hash.scan(cursor, options.count);
+    let mut elements = Vec::with_capacity(pairs.len() * 2);
+    for (field, value) in pairs {
+        if options.matches(field) {
+            elements.push(RedisValueRef::String(field.clone()));
+            elements.push(RedisValueRef::String(value.clone()));
+        }
+    }
+    Ok(scan_reply(next, elements))
+}
+
+/// Get the hash held at a key, failing if the key holds another type
+pub fn hash<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a Hash>, CommandError> {
+    match db.get(key) {
+        Some(SetObject {
+            value: StoredValue::Hash(hash),
+            ..
+        }) => Ok(Some(hash)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the hash held at a key for modification. Callers must report changes
+/// with `Database::signal_modified`, and call `Database::remove_if_empty` if
+/// they may empty the hash.
+pub fn hash_mut<'a>(
+    db: &'a mut Database,
+    key: &[u8],
+) -> Result<Option<&'a mut Hash>, CommandError> {
+    match db.get_mut(key) {
+        Some(SetObject {
+            value: StoredValue::Hash(hash),
+            ..
+        }) => Ok(Some(hash)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the hash held at a key for modification, creating an empty one if the
+/// key is missing. Callers must report changes with `Database::signal_modified`.
+fn hash_or_create<'a>(db: &'a mut Database, key: &Bytes) -> Result<&'a mut Hash, CommandError> {
+    match &mut db
+        .get_or_insert_with(key, || StoredValue::Hash(Hash::new()))
+        .value
+    {
+        StoredValue::Hash(hash) => Ok(hash),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args;
+
+    #[test]
+    fn hscan_cursor_survives_writes() {
+        let mut db = Database::new();
+        for i in 0..50 {
+            handle_hset("hset", &args(&format!("h field:{} {}", i, i)), &mut db).unwrap();
+        }
+
+        // Fields present for the whole iteration are returned exactly once,
+        // with their values, however many fields come and go in between
+        let mut seen = Vec::new();
+        let mut cursor = "0".to_string();
+        for round in 0.. {
+            let reply = handle_hscan(&args(&format!("h {} COUNT 3", cursor)), &mut db).unwrap();
+            let RedisValueRef::Array(reply) = reply else {
+                panic!("unexpected {:?}", reply);
+            };
+            let [RedisValueRef::String(next), RedisValueRef::Array(pairs)] = &reply[..] else {
+                panic!("unexpected {:?}", reply);
+            };
+            for pair in pairs.chunks(2) {
+                let (field, value) = (arg_bytes(&pair[0]).unwrap(), arg_bytes(&pair[1]).unwrap());
+                if let Some(i) = field.strip_prefix(b"field:") {
+                    assert_eq!(i, &value[..]);
+                    seen.push(field.clone());
+                }
+            }
+            handle_hset("hset", &args(&format!("h new:{} v", round)), &mut db).unwrap();
+            handle_hdel(&args(&format!("h new:{}", round - 1)), &mut db).unwrap();
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        seen.sort();
+        let mut expected = (0..50)
+            .map(|i| Bytes::from(format!("field:{}", i)))
+            .collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+}
@@ -0,0 +1,578 @@
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, check_arity, store_preserving_ttl, CommandError, CommandResult, Database,
+    RedisValueRef,
+};
+
+// HyperLogLogs are plain strings laid out exactly like Redis' so blobs can be
+// moved between servers with GET and SET. A 16 byte header holds the "HYLL"
+// magic, the encoding and a cached cardinality, followed by the registers,
+// either dense (6 bits each) or sparse (run length encoded opcodes).
+
+/// Bits of the hash used to pick a register
+const HLL_P: u32 = 14;
+/// Bits of the hash left to count zeros in
+const HLL_Q: u32 = 64 - HLL_P;
+const REGISTERS: usize = 1 << HLL_P;
+const REGISTER_BITS: usize = 6;
+const REGISTER_MAX: u8 = (1 << REGISTER_BITS) - 1;
+const HEADER_SIZE: usize = 16;
+const DENSE_SIZE: usize = HEADER_SIZE + (REGISTERS * REGISTER_BITS).div_ceil(8);
+const DENSE: u8 = 0;
+const SPARSE: u8 = 1;
+/// Sparse HyperLogLogs growing past this are converted to dense, Redis'
+/// default hll-sparse-max-bytes
+const SPARSE_MAX_BYTES: usize = 3000;
+const HASH_SEED: u64 = 0xadc83b19;
+/// 0.5 / ln(2)
+const ALPHA_INF: f64 = 0.721_347_520_444_481_7;
+
+// Sparse opcodes: ZERO 00xxxxxx and XZERO 01xxxxxx yyyyyyyy are runs of zero
+// registers, VAL 1vvvvvxx is a run of up to 4 registers set to 1 to 32
+const XZERO_BIT: u8 = 0x40;
+const VAL_BIT: u8 = 0x80;
+const VAL_MAX_VALUE: u8 = 32;
+const VAL_MAX_LEN: usize = 4;
+const ZERO_MAX_LEN: usize = 64;
+const XZERO_MAX_LEN: usize = 16384;
+
+/// PFADD key [element ...], returns 1 if any register changed
+pub fn handle_pfadd(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("pfadd", args, 1, None)?;
+    let key = arg_bytes(&args[0])?;
+
+    let (mut hll, mut updated) = match db.get_string(key)? {
+        Some(value) => {
+            check_hll(value)?;
+            (value.to_vec(), false)
+        }
+        None => (new_hll(), true),
+    };
+    for element in &args[1..] {
+        let (index, count) = pattern_len(arg_bytes(element)?);
+        updated |= set_register(&mut hll, index, count)?;
+    }
+    if updated {
+        invalidate_cache(&mut hll);
+        store_preserving_ttl(db, key, Bytes::from(hll));
+    }
+    Ok(RedisValueRef::Int(updated as i64))
+}
+
+/// PFCOUNT key [key ...], the estimated cardinality of the union. A single
+/// key caches the count in its header.
+pub fn handle_pfcount(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("pfcount", args, 1, None)?;
+
+    if let [key] = args {
+        let key = arg_bytes(key)?;
+        let Some(value) = db.get_string(key)? else {
+            return Ok(RedisValueRef::Int(0));
+        };
+        check_hll(value)?;
+        if let Some(count) = cached_count(value) {
+            return Ok(RedisValueRef::Int(count as i64));
+        }
+        let mut hll = value.to_vec();
+        let mut registers = [0; REGISTERS];
+        merge_registers(&mut registers, &hll)?;
+        let count = estimate(&registers);
+        hll[8..HEADER_SIZE].copy_from_slice(&count.to_le_bytes());
+        store_preserving_ttl(db, key, Bytes::from(hll));
+        return Ok(RedisValueRef::Int(count as i64));
+    }
+
+    let mut registers = [0; REGISTERS];
+    for key in args {
+        if let Some(value) = db.get_string(arg_bytes(key)?)? {
+            check_hll(value)?;
+            merge_registers(&mut registers, value)?;
+        }
+    }
+    Ok(RedisValueRef::Int(estimate(&registers) as i64))
+}
+
+/// PFMERGE destination [source ...], merging the destination itself too
+pub fn handle_pfmerge(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("pfmerge", args, 1, None)?;
+    let destination = arg_bytes(&args[0])?;
+
+    let mut registers = [0; REGISTERS];
+    let mut use_dense = false;
+    for key in args {
+        if let Some(value) = db.get_string(arg_bytes(key)?)? {
+            check_hll(value)?;
+            use_dense |= value[4] == DENSE;
+            merge_registers(&mut registers, value)?;
+        }
+    }
+
+    let mut hll = match db.get_string(destination)? {
+        Some(value) => value.to_vec(),
+        None => new_hll(),
+    };
+    if use_dense {
+        to_dense(&mut hll)?;
+    }
+    for (index, count) in registers.iter().enumerate() {
+        if *count > 0 {
+            set_register(&mut hll, index, *count)?;
+        }
+    }
+    invalidate_cache(&mut hll);
+    store_preserving_ttl(db, destination, Bytes::from(hll));
+    Ok(RedisValueRef::ok())
+}
+
+/// MurmurHash64A, the hash Redis uses for HyperLogLog elements
+fn murmur_hash64a(data: &[u8], seed: u64) -> u64 {
+    const M: u64 = 0xc6a4a7935bd1e995;
+    const R: u32 = 47;
+    let mut h = seed ^ (data.len() as u64).wrapping_mul(M);
+
+    let mut chunks = data.chunks_exact(8);
+    for chunk in &mut chunks {
+        let mut k = u64::from_le_bytes(chunk.try_into().expect("8 byte chunk"));
+        k = k.wrapping_mul(M);
+        k ^= k >> R;
+        k = k.wrapping_mul(M);
+        h ^= k;
+        h = h.wrapping_mul(M);
+    }
+    let tail = chunks.remainder();
+    if !tail.is_empty() {
+        for (i, byte) in tail.iter().enumerate() {
+            h ^= (*byte as u64) << (8 * i);
+        }
+        h = h.wrapping_mul(M);
+    }
+
+    h ^= h >> R;
+    h = h.wrapping_mul(M);
+    h ^= h >> R;
+    h
+}
+
+/// The register an element maps to and the length of the run of zeros in
+/// the rest of its hash plus one
+fn pattern_len(element: &[u8]) -> (usize, u8) {
+    let hash = murmur_hash64a(element, HASH_SEED);
+    let index = (hash & (REGISTERS as u64 - 1)) as usize;
+    // The extra bit stops the count at Q + 1
+    let hash = (hash >> HLL_P) | (1 << HLL_Q);
+    (index, hash.trailing_zeros() as u8 + 1)
+}
+
+/// An empty sparse HyperLogLog, whose cached cardinality of 0 is valid
+fn new_hll() -> Vec<u8> {
+    let mut hll = Vec::with_capacity(HEADER_SIZE + 2);
+    hll.extend_from_slice(b"HYLL");
+    hll.push(SPARSE);
+    hll.resize(HEADER_SIZE, 0);
+    let mut remaining = REGISTERS;
+    while remaining > 0 {
+        let len = remaining.min(XZERO_MAX_LEN);
+        hll.extend_from_slice(&xzero(len));
+        remaining -= len;
+    }
+    hll
+}
+
+/// Fail unless the string is a HyperLogLog
+fn check_hll(value: &[u8]) -> Result<(), CommandError> {
+    let valid = value.len() >= HEADER_SIZE
+        && value.starts_with(b"HYLL")
+        && match value[4] {
+            DENSE => value.len() == DENSE_SIZE,
+            SPARSE => true,
+            _ => false,
+        };
+    match valid {
+        true => Ok(()),
+        false => Err(CommandError::NotHll),
+    }
+}
+
+fn cached_count(hll: &[u8]) -> Option<u64> {
+    // The most significant bit flags a stale cache
+    match hll[15] & 0x80 {
+        0 => Some(u64::from_le_bytes(hll[8..HEADER_SIZE].try_into().ok()?)),
+        _ => None,
+    }
+}
+
+fn invalidate_cache(hll: &mut [u8]) {
+    hll[15] |= 0x80;
+}
+
+/// Raise a register to `count` if it is lower, returning whether it changed
+fn set_register(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, CommandError> {
+    if hll[4] == SPARSE {
+        return sparse_set(hll, index, count);
+    }
+    let registers = &mut hll[HEADER_SIZE..];
+    if dense_get(registers, index) >= count {
+        return Ok(false);
+    }
+    dense_set(registers, index, count);
+    Ok(true)
+}
+
+fn dense_get(registers: &[u8], index: usize) -> u8 {
+    let byte = index * REGISTER_BITS / 8;
+    let shift = index * REGISTER_BITS % 8;
+    let low = registers[byte] as u16;
+    let high = registers.get(byte + 1).copied().unwrap_or(0) as u16;
+    (((low >> shift) | (high << (8 - shift))) & REGISTER_MAX as u16) as u8
+}
+
+fn dense_set(registers: &mut [u8], index: usize, value: u8) {
+    let byte = index * REGISTER_BITS / 8;
+    let shift = index * REGISTER_BITS % 8;
+    let value = value as u16;
+    let mask = REGISTER_MAX as u16;
+    registers[byte] &= !((mask << shift) as u8);
+    registers[byte] |= (value << shift) as u8;
+    // The last register ends on a byte boundary
+    if let Some(next) = registers.get_mut(byte + 1) {
+        *next &= !((mask >> (8 - shift)) as u8);
+        *next |= (value >> (8 - shift)) as u8;
+    }
+}
+
+/// A sparse opcode: the registers it covers, its value and its size in
+/// bytes, or None past the end
+fn sparse_opcode(sparse: &[u8], at: usize) -> Option<(usize, u8, usize)> {
+    let op = *sparse.get(at)?;
+    Some(if op & VAL_BIT != 0 {
+        ((op & 0x3) as usize + 1, ((op >> 2) & 0x1f) + 1, 1)
+    } else if op & XZERO_BIT != 0 {
+        let len = (((op & 0x3f) as usize) << 8 | *sparse.get(at + 1)? as usize) + 1;
+        (len, 0, 2)
+    } else {
+        ((op & 0x3f) as usize + 1, 0, 1)
+    })
+}
+
+fn val(value: u8, len: usize) -> u8 {
+    VAL_BIT | (value - 1) << 2 | (len - 1) as u8
+}
+
+fn xzero(len: usize) -> [u8; 2] {
+    let len = len - 1;
+    [XZERO_BIT | (len >> 8) as u8, (len & 0xff) as u8]
+}
+
+/// A run of zero registers as a ZERO or XZERO opcode
+fn zero_run(len: usize) -> Vec<u8> {
+    match len > ZERO_MAX_LEN {
+        true => xzero(len).to_vec(),
+        false => vec![(len - 1) as u8],
+    }
+}
+
+/// Raise a register of a sparse HyperLogLog, splitting the opcode covering
+/// it the same way Redis does so both produce identical strings. Converts to
+/// dense when the value is too large for a VAL opcode or the string grows
+/// past SPARSE_MAX_BYTES.
+fn sparse_set(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, CommandError> {
+    if count > VAL_MAX_VALUE {
+        return promote(hll, index, count);
+    }
+
+    // Find the opcode covering the register
+    let mut at = HEADER_SIZE;
+    let mut prev = None;
+    let mut first = 0;
+    let (span, value, op_len) = loop {
+        let (span, value, op_len) = sparse_opcode(hll, at).ok_or(CommandError::CorruptHll)?;
+        if index < first + span {
+            break (span, value, op_len);
+        }
+        prev = Some(at);
+        at += op_len;
+        first += span;
+    };
+    let is_val = hll[at] & VAL_BIT != 0;
+
+    if is_val && value >= count {
+        return Ok(false);
+    }
+    if span == 1 && op_len == 1 {
+        // A single register, either a VAL or a ZERO, is updated in place
+        hll[at] = val(count, 1);
+    } else {
+        let last = first + span - 1;
+        let mut sequence = Vec::with_capacity(5);
+        if index != first {
+            match is_val {
+                true => sequence.push(val(value, index - first)),
+                false => sequence.extend(zero_run(index - first)),
+            }
+        }
+        sequence.push(val(count, 1));
+        if index != last {
+            match is_val {
+                true => sequence.push(val(value, last - index)),
+                false => sequence.extend(zero_run(last - index)),
+            }
+        }
+        if sequence.len() > op_len && hll.len() + sequence.len() - op_len > SPARSE_MAX_BYTES {
+            return promote(hll, index, count);
+        }
+        hll.splice(at..at + op_len, sequence);
+    }
+
+    // Merge adjacent VAL opcodes with the same value, scanning up to 5
+    // opcodes from the one before the update
+    let mut at = prev.unwrap_or(HEADER_SIZE);
+    let mut scan = 5;
+    while at < hll.len() && scan > 0 {
+        scan -= 1;
+        let op = hll[at];
+        if op & VAL_BIT == 0 {
+            at += if op & XZERO_BIT != 0 { 2 } else { 1 };
+            continue;
+        }
+        if hll.get(at + 1).is_some_and(|next| next & VAL_BIT != 0) {
+            let (len, value, _) = sparse_opcode(hll, at).expect("opcode");
+            let (next_len, next_value, _) = sparse_opcode(hll, at + 1).expect("opcode");
+            if value == next_value && len + next_len <= VAL_MAX_LEN {
+                hll[at + 1] = val(value, len + next_len);
+                hll.remove(at);
+                // Try the merged opcode with the one on its right
+                continue;
+            }
+        }
+        at += 1;
+    }
+    Ok(true)
+}
+
+/// Convert to dense and set the register there
+fn promote(hll: &mut Vec<u8>, index: usize, count: u8) -> Result<bool, CommandError> {
+    to_dense(hll)?;
+    dense_set(&mut hll[HEADER_SIZE..], index, count);
+    Ok(true)
+}
+
+/// Convert a sparse HyperLogLog to dense, keeping the header
+fn to_dense(hll: &mut Vec<u8>) -> Result<(), CommandError> {
+    if hll[4] == DENSE {
+        return Ok(());
+    }
+    let mut registers = [0; REGISTERS];
+    merge_registers(&mut registers, hll)?;
+    hll.truncate(HEADER_SIZE);
+    hll[4] = DENSE;
+    hll.resize(DENSE_SIZE, 0);
+    for (index, value) in registers.iter().enumerate() {
+        if *value > 0 {
+            dense_set(&mut hll[HEADER_SIZE..], index, *value);
+        }
+    }
+    Ok(())
+}
+
+/// Raise every register in `max` to the HyperLogLog's, failing if a sparse
+/// HyperLogLog does not cover exactly every register
+fn merge_registers(max: &mut [u8; REGISTERS], hll: &[u8]) -> Result<(), CommandError> {
+    if hll[4] == DENSE {
+        for (index, max) in max.iter_mut().enumerate() {
+            *max = (*max).max(dense_get(&hll[HEADER_SIZE..], index));
+        }
+        return Ok(());
+    }
+
+    let mut index = 0;
+    let mut at = HEADER_SIZE;
+    while let Some((span, value, op_len)) = sparse_opcode(hll, at) {
+        if index + span > REGISTERS {
+            return Err(CommandError::CorruptHll);
+        }
+        for max in &mut max[index..index + span] {
+            *max = (*max).max(value);
+        }
+        index += span;
+        at += op_len;
+    }
+    match index == REGISTERS && at == hll.len() {
+        true => Ok(()),
+        false => Err(CommandError::CorruptHll),
+    }
+}
+
+/// Estimate the cardinality from the registers, using the estimator from
+/// "New cardinality estimation algorithms for HyperLogLog sketches" by Otmar
+/// Ertl like Redis does
+fn estimate(registers: &[u8; REGISTERS]) -> u64 {
+    let mut histogram = [0u32; 64];
+    for register in registers {
+        histogram[*register as usize] += 1;
+    }
+
+    let m = REGISTERS as f64;
+    let mut z = m * tau((m - histogram[HLL_Q as usize + 1] as f64) / m);
+    for j in (1..=HLL_Q as usize).rev() {
+        z += histogram[j] as f64;
+        z *= 0.5;
+    }
+    z += m * sigma(histogram[0] as f64 / m);
+    (ALPHA_INF * m * m / z).round() as u64
+}
+
+fn sigma(mut x: f64) -> f64 {
+    if x == 1.0 {
+        return f64::INFINITY;
+    }
+    let mut y = 1.0;
+    let mut z = x;
+    loop {
+        x *= x;
+        let previous = z;
+        z += x * y;
+        y += y;
+        if previous == z {
+            return z;
+        }
+    }
+}
+
+fn tau(mut x: f64) -> f64 {
+    if x == 0.0 || x == 1.0 {
+        return 0.0;
+    }
+    let mut y = 1.0;
+    let mut z = 1.0 - x;
+    loop {
+        x = x.sqrt();
+        let previous = z;
+        y *= 0.5;
+        z -= (1.0 - x).powi(2) * y;
+        if previous == z {
+            return z / 3.0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args, SetExpiry};
+
+    fn pfadd(line: &str, db: &mut Database) -> CommandResult {
+        handle_pfadd(&args(line), db)
+    }
+
+    fn pfcount(line: &str, db: &mut Database) -> CommandResult {
+        handle_pfcount(&args(line), db)
+    }
+
+    fn blob(db: &mut Database, key: &[u8]) -> Bytes {
+        db.get_string(key).unwrap().unwrap().clone()
+    }
+
+    fn header(cache: [u8; 8]) -> Vec<u8> {
+        let mut header = b"HYLL\x01\x00\x00\x00".to_vec();
+        header.extend_from_slice(&cache);
+        header
+    }
+
+    #[test]
+    fn matches_redis_blobs_and_counts() {
+        let mut db = Database::new();
+
+        // An empty HyperLogLog is one XZERO covering every register. Creating
+        // it counts as an update, which flags the cache as stale.
+        let stale = [0, 0, 0, 0, 0, 0, 0, 0x80];
+        assert_eq!(pfadd("empty", &mut db), Ok(RedisValueRef::Int(1)));
+        assert_eq!(
+            blob(&mut db, b"empty"),
+            [header(stale), b"\x7f\xff".to_vec()].concat()
+        );
+        assert_eq!(pfcount("empty", &mut db), Ok(RedisValueRef::Int(0)));
+
+        // a, b and c set registers 12711 to 2, 15780 to 1 and 8436 to 1
+        let registers = b"\x60\xf3\x80\x50\xb1\x84\x4b\xfb\x80\x42\x5a";
+        assert_eq!(pfadd("hll a b c", &mut db), Ok(RedisValueRef::Int(1)));
+        assert_eq!(
+            blob(&mut db, b"hll"),
+            [header(stale), registers.to_vec()].concat()
+        );
+        assert_eq!(pfcount("hll", &mut db), Ok(RedisValueRef::Int(3)));
+        let cached = [3, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(
+            blob(&mut db, b"hll"),
+            [header(cached), registers.to_vec()].concat()
+        );
+        assert_eq!(pfadd("hll c b a", &mut db), Ok(RedisValueRef::Int(0)));
+        assert_eq!(
+            blob(&mut db, b"hll"),
+            [header(cached), registers.to_vec()].concat()
+        );
+
+        // A blob written by another server counts the same
+        let copied = [header(stale), registers.to_vec()].concat();
+        db.set(Bytes::from("copy"), Bytes::from(copied), SetExpiry::Persist);
+        assert_eq!(pfcount("copy", &mut db), Ok(RedisValueRef::Int(3)));
+
+        // Counts from the Redis documentation and test suite
+        pfadd("letters a b c d e f g", &mut db).unwrap();
+        assert_eq!(pfcount("letters", &mut db), Ok(RedisValueRef::Int(7)));
+        pfadd("digits 1 2 3 4 5", &mut db).unwrap();
+        assert_eq!(pfcount("digits", &mut db), Ok(RedisValueRef::Int(5)));
+        pfadd("digits 6 7 8 8 9 10", &mut db).unwrap();
+        assert_eq!(pfcount("digits", &mut db), Ok(RedisValueRef::Int(10)));
+        pfadd("words foo bar zap", &mut db).unwrap();
+        pfadd("numbers 1 2 3", &mut db).unwrap();
+        assert_eq!(pfcount("words numbers", &mut db), Ok(RedisValueRef::Int(6)));
+        pfadd("h2 b c d", &mut db).unwrap();
+        pfadd("h3 c d e", &mut db).unwrap();
+        handle_pfmerge(&args("union hll h2 h3"), &mut db).unwrap();
+        assert_eq!(pfcount("union", &mut db), Ok(RedisValueRef::Int(5)));
+    }
+
+    #[test]
+    fn promotes_to_dense_past_sparse_max_bytes() {
+        let mut db = Database::new();
+        for i in 0..1648 {
+            pfadd(&format!("hll {}", i), &mut db).unwrap();
+        }
+        let sparse = blob(&mut db, b"hll");
+        assert_eq!(sparse[4], SPARSE);
+        assert_eq!(sparse.len(), SPARSE_MAX_BYTES);
+
+        // Element 1648 splits a run, which would grow the string past the limit
+        assert_eq!(pfadd("hll 1648", &mut db), Ok(RedisValueRef::Int(1)));
+        let dense = blob(&mut db, b"hll");
+        assert_eq!(dense[4], DENSE);
+        assert_eq!(dense.len(), DENSE_SIZE);
+
+        let mut expected = [0; REGISTERS];
+        merge_registers(&mut expected, &sparse).unwrap();
+        let (index, count) = pattern_len(b"1648");
+        expected[index] = expected[index].max(count);
+        let mut registers = [0; REGISTERS];
+        merge_registers(&mut registers, &dense).unwrap();
+        assert_eq!(registers, expected);
+        assert_eq!(pfcount("hll", &mut db), Ok(RedisValueRef::Int(1656)));
+    }
+
+    #[test]
+    fn values_above_val_max_promote_to_dense() {
+        let mut db = Database::new();
+        assert_eq!(pattern_len(b"1692856687"), (6288, 33));
+        pfadd("hll a b c", &mut db).unwrap();
+        assert_eq!(pfadd("hll 1692856687", &mut db), Ok(RedisValueRef::Int(1)));
+
+        let dense = blob(&mut db, b"hll");
+        assert_eq!(dense[4], DENSE);
+        let registers = &dense[HEADER_SIZE..];
+        assert_eq!(dense_get(registers, 6288), 33);
+        assert_eq!(dense_get(registers, 12711), 2);
+        assert_eq!(dense_get(registers, 15780), 1);
+        assert_eq!(dense_get(registers, 8436), 1);
+        assert_eq!(pfcount("hll", &mut db), Ok(RedisValueRef::Int(4)));
+    }
+}
@@ -0,0 +1,561 @@
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, arg_int, check_arity, current_time_millis, glob_match, is_keyword, CommandError,
+    CommandResult, Database, RedisValueRef,
+};
+
+/// EXPIRE, PEXPIRE, EXPIREAT and PEXPIREAT key time [NX | XX | GT | LT]
+pub fn handle_expire(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let amount = arg_int(&args[1])?;
+
+    let (mut nx, mut xx, mut gt, mut lt) = (false, false, false, false);
+    for option in &args[2..] {
+        if is_keyword(option, "nx") {
+            nx = true;
+        } else if is_keyword(option, "xx") {
+            xx = true;
+        } else if is_keyword(option, "gt") {
+            gt = true;
+        } else if is_keyword(option, "lt") {
+            lt = true;
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    if nx && (xx || gt || lt) {
+        return Err(CommandError::ExpireNxConflict);
+    }
+    if gt && lt {
+        return Err(CommandError::ExpireGtLtConflict);
+    }
+
+    // Work out the absolute expiry in milliseconds, which may be in the past
+    let invalid = CommandError::InvalidExpireTime(name);
+    let millis = if name.starts_with('p') {
+        amount
+    } else {
+        amount.checked_mul(1000).ok_or(invalid.clone())?
+    };
+    let expires_at = if name.ends_with("at") {
+        millis
+    } else {
+        millis
+            .checked_add(current_time_millis() as i64)
+            .ok_or(invalid)?
+    };
+
+    let Some(object) = db.get(key) else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    // A key without a TTL behaves as if it expired infinitely far away
+    let current = object.expires_at;
+    let allowed = match current {
+        _ if nx => current.is_none(),
+        None if xx || gt => false,
+        Some(current) if gt => expires_at > current as i64,
+        Some(current) if lt => expires_at < current as i64,
+        _ => true,
+    };
+    if !allowed {
+        return Ok(RedisValueRef::Int(0));
+    }
+
+    if expires_at <= current_time_millis() as i64 {
+        db.remove(key);
+    } else {
+        db.set_expiry(key, Some(expires_at as u64));
+    }
+    Ok(RedisValueRef::Int(1))
+}
+
+/// TTL, PTTL, EXPIRETIME and PEXPIRETIME key
+pub fn handle_ttl(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let expires_at = match db.get(key) {
+        None => return Ok(RedisValueRef::Int(-2)),
+        Some(object) => match object.expires_at {
+            None => return Ok(RedisValueRef::Int(-1)),
+            Some(expires_at) => expires_at,
+        },
+    };
+
+    let value = match name {
+        "ttl" => (expires_at.saturating_sub(current_time_millis()) + 500) / 1000,
+        "pttl" => expires_at.saturating_sub(current_time_millis()),
+        "expiretime" => (expires_at + 500) / 1000,
+        _ => expires_at,
+    };
+    Ok(RedisValueRef::Int(value as i64))
+}
+
+/// PERSIST key
+pub fn handle_persist(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("persist", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let had_ttl = db
+        .get(key)
+        .is_some_and(|object| object.expires_at.is_some());
+    if had_ttl {
+        db.set_expiry(key, None);
+    }
+    Ok(RedisValueRef::Int(had_ttl as i64))
+}
+
+/// DEL and UNLINK key [key ...], returns the number of keys removed
+pub fn handle_del(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 1, None)?;
+    let mut deleted = 0;
+    for key in args {
+        if db.delete(arg_bytes(key)?) {
+            deleted += 1;
+        }
+    }
+    Ok(RedisValueRef::Int(deleted))
+}
+
+/// EXISTS and TOUCH key [key ...], a key given twice is counted twice
+pub fn handle_exists(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 1, None)?;
+    let mut found = 0;
+    for key in args {
+        if db.exists(arg_bytes(key)?) {
+            found += 1;
+        }
+    }
+    Ok(RedisValueRef::Int(found))
+}
+
+/// TYPE key
+pub fn handle_type(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("type", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    Ok(RedisValueRef::simple_string(db.key_type(key)))
+}
+
+/// RENAME and RENAMENX key newkey
+pub fn handle_rename(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 2, Some(2))?;
+    let from = arg_bytes(&args[0])?;
+    let to = arg_bytes(&args[1])?.clone();
+    if name == "renamenx" {
+        let renamed = db.rename(from, to, true)?;
+        Ok(RedisValueRef::Int(renamed as i64))
+    } else {
+        db.rename(from, to, false)?;
+        Ok(RedisValueRef::ok())
+    }
+}
+
+/// COPY source destination [DB destination-db] [REPLACE]
+pub fn handle_copy(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("copy", args, 2, None)?;
+    let from = arg_bytes(&args[0])?;
+    let to = arg_bytes(&args[1])?.clone();
+
+    let mut replace = false;
+    let mut options = args[2..].iter();
+    while let Some(option) = options.next() {
+        if is_keyword(option, "replace") {
+            replace = true;
+        } else if is_keyword(option, "db") {
+            // There is a single logical database
+            let index = options.next().ok_or(CommandError::Syntax)?;
+            if arg_int(index)? != 0 {
+                return Err(CommandError::DbIndexOutOfRange);
+            }
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+
+    let copied = db.copy(from, to, replace)?;
+    Ok(RedisValueRef::Int(copied as i64))
+}
+
+/// KEYS pattern
+pub fn handle_keys(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("keys", args, 1, Some(1))?;
+    let pattern = arg_bytes(&args[0])?;
+    let keys = db.keys(pattern);
+    Ok(RedisValueRef::Array(
+        keys.into_iter().map(RedisValueRef::String).collect(),
+    ))
+}
+
+/// SCAN cursor [MATCH pattern] [COUNT count] [TYPE type]
+pub fn handle_scan(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("scan", args, 1, None)?;
+    let cursor = parse_cursor(&args[0])?;
+    let options = ScanOptions::parse(&args[1..], true)?;
+
+    let (next, keys) = db.scan(cursor, options.count);
+    let mut matched = Vec::with_capacity(keys.len());
+    for key in keys {
+        if !options.matches(&key) {
+            continue;
+        }
+        if let Some(key_type) = &options.key_type {
+            if !db.key_type(&key).as_bytes().eq_ignore_ascii_case(key_type) {
+                continue;
+            }
+        }
+        matched.push(RedisValueRef::String(key));
+    }
+    Ok(scan_reply(next, matched))
+}
+
+/// Parse a SCAN family cursor
+pub fn parse_cursor(arg: &RedisValueRef) -> Result<u64, CommandError> {
+    std::str::from_utf8(arg_bytes(arg)?)
+        .ok()
+        .and_then(|s| s.parse().ok())
+        .ok_or(CommandError::InvalidCursor)
+}
+
+/// The `[cursor, [elements...]]` reply shared by the SCAN family
+pub fn scan_reply(cursor: u64, elements: Vec<RedisValueRef>) -> RedisValueRef {
+    RedisValueRef::Array(vec![
+        RedisValueRef::bulk_string(cursor.to_string()),
+        RedisValueRef::Array(elements),
+    ])
+}
+
+/// Options shared by the SCAN family of commands
+pub struct ScanOptions {
+    pub pattern: Option<Bytes>,
+    pub count: usize,
+    pub key_type: Option<Bytes>,
+}
+
+impl ScanOptions {
+    /// Parse `[MATCH pattern] [COUNT count]`, plus `[TYPE type]` when allowed
+    pub fn parse(args: &[RedisValueRef], allow_type: bool) -> Result<ScanOptions, CommandError> {
+        let mut options = ScanOptions {
+            pattern: None,
+            count: 10,
+            key_type: None,
+        };
+        let mut args = args.iter();
+        while let Some(option) = args.next() {
+            let value = args.next().ok_or(CommandError::Syntax)?;
+            if is_keyword(option, "match") {
+                options.pattern = Some(arg_bytes(value)?.clone());
+            } else if is_keyword(option, "count") {
+                options.count = match arg_int(value)? {
+                    count if count < 1 => return Err(CommandError::Syntax),
+                    count => count as usize,
+                };
+            } else if allow_type && is_keyword(option, "type") {
+                options.key_type = Some(arg_bytes(value)?.clone());
+            } else {
+                return Err(CommandError::Syntax);
+            }
+        }
+        Ok(options)
+    }
+
+    /// Whether an element passes the MATCH filter
+    pub fn matches(&self, element: &[u8]) -> bool {
+        match &self.pattern {
+            Some(pattern) => glob_match(pattern, element),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args, SetExpiry};
+
+    fn run(
+        handler: fn(&'static str, &[RedisValueRef], &mut Database) -> CommandResult,
+        name: &'static str,
+        line: &str,
+        db: &mut Database,
+    ) -> CommandResult {
+        handler(name, &args(line), db)
+    }
+
+    fn int(value: i64) -> CommandResult {
+        Ok(RedisValueRef::Int(value))
+    }
+
+    fn set(db: &mut Database, key: &'static str) {
+        db.set(Bytes::from(key), Bytes::from("v"), SetExpiry::Persist);
+    }
+
+    #[test]
+    fn expire_conditions() {
+        let mut db = Database::new();
+        assert_eq!(run(handle_expire, "expire", "k 100", &mut db), int(0));
+        set(&mut db, "k");
+
+        // Without a TTL: NX applies, XX and GT do not, LT does
+        assert_eq!(run(handle_expire, "expire", "k 100 XX", &mut db), int(0));
+        assert_eq!(run(handle_expire, "expire", "k 100 GT", &mut db), int(0));
+        assert_eq!(run(handle_expire, "expire", "k 100 LT", &mut db), int(1));
+        assert_eq!(run(handle_expire, "expire", "k 200 NX", &mut db), int(0));
+        assert_eq!(run(handle_ttl, "ttl", "k", &mut db), int(100));
+
+        assert_eq!(run(handle_expire, "expire", "k 50 GT", &mut db), int(0));
+        assert_eq!(run(handle_expire, "expire", "k 200 GT XX", &mut db), int(1));
+        assert_eq!(run(handle_expire, "expire", "k 300 LT", &mut db), int(0));
+        assert_eq!(run(handle_expire, "expire", "k 10 LT", &mut db), int(1));
+        assert_eq!(run(handle_ttl, "ttl", "k", &mut db), int(10));
+
+        for line in ["k 10 NX XX", "k 10 NX GT"] {
+            assert_eq!(
+                run(handle_expire, "expire", line, &mut db),
+                Err(CommandError::ExpireNxConflict)
+            );
+        }
+        assert_eq!(
+            run(handle_expire, "expire", "k 10 GT LT", &mut db),
+            Err(CommandError::ExpireGtLtConflict)
+        );
+        assert_eq!(
+            run(handle_expire, "expire", "k 10 FOO", &mut db),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            run(handle_expire, "expire", "k 9223372036854775807", &mut db),
+            Err(CommandError::InvalidExpireTime("expire"))
+        );
+    }
+
+    #[test]
+    fn expire_units_and_past_times() {
+        let mut db = Database::new();
+        set(&mut db, "k");
+        let at = current_time_millis() + 100_000;
+        assert_eq!(
+            run(handle_expire, "pexpireat", &format!("k {}", at), &mut db),
+            int(1)
+        );
+        assert_eq!(run(handle_ttl, "pexpiretime", "k", &mut db), int(at as i64));
+        assert_eq!(
+            run(handle_ttl, "expiretime", "k", &mut db),
+            int(((at + 500) / 1000) as i64)
+        );
+        let pttl = run(handle_ttl, "pttl", "k", &mut db).unwrap();
+        assert!(matches!(pttl, RedisValueRef::Int(ms) if (99_000..=100_000).contains(&ms)));
+
+        // A time in the past deletes the key
+        assert_eq!(run(handle_expire, "pexpire", "k -1", &mut db), int(1));
+        assert!(!db.exists(b"k"));
+        set(&mut db, "k");
+        assert_eq!(run(handle_expire, "expireat", "k 1", &mut db), int(1));
+        assert!(!db.exists(b"k"));
+    }
+
+    #[test]
+    fn ttl_and_persist() {
+        let mut db = Database::new();
+        assert_eq!(run(handle_ttl, "ttl", "k", &mut db), int(-2));
+        assert_eq!(run(handle_ttl, "expiretime", "k", &mut db), int(-2));
+        set(&mut db, "k");
+        assert_eq!(run(handle_ttl, "pttl", "k", &mut db), int(-1));
+        assert_eq!(handle_persist(&args("k"), &mut db), int(0));
+
+        run(handle_expire, "expire", "k 100", &mut db).unwrap();
+        assert_eq!(handle_persist(&args("k"), &mut db), int(1));
+        assert_eq!(run(handle_ttl, "ttl", "k", &mut db), int(-1));
+        assert_eq!(handle_persist(&args("missing"), &mut db), int(0));
+    }
+
+    #[test]
+    fn del_exists_and_type() {
+        let mut db = Database::new();
+        set(&mut db, "a");
+        set(&mut db, "b");
+        assert_eq!(
+            run(handle_exists, "exists", "a a missing b", &mut db),
+            int(3)
+        );
+        assert_eq!(run(handle_exists, "touch", "a missing", &mut db), int(1));
+        assert_eq!(
+            handle_type(&args("a"), &mut db),
+            Ok(RedisValueRef::simple_string("string"))
+        );
+        assert_eq!(
+            handle_type(&args("missing"), &mut db),
+            Ok(RedisValueRef::simple_string("none"))
+        );
+        assert_eq!(run(handle_del, "del", "a a missing", &mut db), int(1));
+        assert_eq!(run(handle_del, "unlink", "b", &mut db), int(1));
+        assert!(db.is_empty());
+    }
+
+    #[test]
+    fn rename_keeps_the_ttl() {
+        let mut db = Database::new();
+        assert_eq!(
+            run(handle_rename, "rename", "missing b", &mut db),
+            Err(CommandError::NoSuchKey)
+        );
+        set(&mut db, "a");
+        run(handle_expire, "expire", "a 100", &mut db).unwrap();
+        assert_eq!(
+            run(handle_rename, "rename", "a b", &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        assert!(!db.exists(b"a"));
+        assert_eq!(run(handle_ttl, "ttl", "b", &mut db), int(100));
+        // Renaming onto itself keeps the key
+        assert_eq!(
+            run(handle_rename, "rename", "b b", &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        assert!(db.exists(b"b"));
+
+        set(&mut db, "c");
+        assert_eq!(run(handle_rename, "renamenx", "b c", &mut db), int(0));
+        assert_eq!(run(handle_rename, "renamenx", "b d", &mut db), int(1));
+        assert_eq!(
+            run(handle_rename, "rename", "d c", &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        assert_eq!(run(handle_ttl, "ttl", "c", &mut db), int(100));
+    }
+
+    #[test]
+    fn copy_options() {
+        let mut db = Database::new();
+        set(&mut db, "a");
+        run(handle_expire, "expire", "a 100", &mut db).unwrap();
+        assert_eq!(handle_copy(&args("a b"), &mut db), int(1));
+        assert_eq!(run(handle_ttl, "ttl", "b", &mut db), int(100));
+        assert_eq!(handle_copy(&args("missing c"), &mut db), int(0));
+
+        db.set(Bytes::from("c"), Bytes::from("old"), SetExpiry::Persist);
+        assert_eq!(handle_copy(&args("a c"), &mut db), int(0));
+        assert_eq!(db.get_string(b"c").unwrap().unwrap(), "old");
+        assert_eq!(handle_copy(&args("a c DB 0 REPLACE"), &mut db), int(1));
+        assert_eq!(db.get_string(b"c").unwrap().unwrap(), "v");
+
+        // A copy is independent of its source
+        db.set(Bytes::from("a"), Bytes::from("changed"), SetExpiry::Persist);
+        assert_eq!(db.get_string(b"b").unwrap().unwrap(), "v");
+
+        assert_eq!(
+            handle_copy(&args("a a"), &mut db),
+            Err(CommandError::SameObject)
+        );
+        assert_eq!(
+            handle_copy(&args("a d DB 1"), &mut db),
+            Err(CommandError::DbIndexOutOfRange)
+        );
+        assert_eq!(
+            handle_copy(&args("a d DB"), &mut db),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            handle_copy(&args("a d FOO"), &mut db),
+            Err(CommandError::Syntax)
+        );
+        assert!(!db.exists(b"d"));
+    }
+
+    /// Run SCAN to completion, returning every key seen
+    fn scan_all(db: &mut Database, options: &str) -> Vec<Bytes> {
+        let mut cursor = "0".to_string();
+        let mut keys = Vec::new();
+        loop {
+            let reply = handle_scan(&args(&format!("{} {}", cursor, options)), db).unwrap();
+            let RedisValueRef::Array(reply) = reply else {
+                panic!("unexpected {:?}", reply);
+            };
+            let [RedisValueRef::String(next), RedisValueRef::Array(batch)] = &reply[..] else {
+                panic!("unexpected {:?}", reply);
+            };
+            keys.extend(batch.iter().map(|key| arg_bytes(key).unwrap().clone()));
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                keys.sort();
+                return keys;
+            }
+        }
+    }
+
+    #[test]
+    fn keys_and_scan_filters() {
+        let mut db = Database::new();
+        for key in ["user:1", "user:2", "admin:1"] {
+            db.set(Bytes::from(key), Bytes::from("v"), SetExpiry::Persist);
+        }
+        crate::handle_sadd(&args("user:set member"), &mut db).unwrap();
+
+        let RedisValueRef::Array(keys) = handle_keys(&args("user:?"), &mut db).unwrap() else {
+            panic!("KEYS replies an array");
+        };
+        assert_eq!(keys.len(), 2);
+
+        assert_eq!(scan_all(&mut db, "COUNT 1").len(), 4);
+        assert_eq!(
+            scan_all(&mut db, "MATCH user:* TYPE string"),
+            vec![Bytes::from("user:1"), Bytes::from("user:2")]
+        );
+        assert_eq!(scan_all(&mut db, "TYPE set"), vec![Bytes::from("user:set")]);
+        assert_eq!(scan_all(&mut db, "TYPE hash"), Vec::<Bytes>::new());
+
+        for line in ["0 COUNT 0", "0 MATCH", "0 FOO bar"] {
+            assert_eq!(handle_scan(&args(line), &mut db), Err(CommandError::Syntax));
+        }
+        assert_eq!(
+            handle_scan(&args("-1"), &mut db),
+            Err(CommandError::InvalidCursor)
+        );
+    }
+
+    #[test]
+    fn scan_cursor_survives_writes() {
+        let mut db = Database::new();
+        let keys = (0..100).map(|i| Bytes::from(format!("key:{}", i)));
+        for key in keys.clone() {
+            db.set(key, Bytes::from("v"), SetExpiry::Persist);
+        }
+
+        // Keys present for the whole iteration are returned exactly once,
+        // however many keys come and go in between calls
+        let mut seen = Vec::new();
+        let mut cursor = 0;
+        let mut round = 0;
+        loop {
+            let (next, batch) = db.scan(cursor, 7);
+            seen.extend(batch);
+            db.set(
+                Bytes::from(format!("new:{}", round)),
+                Bytes::from("v"),
+                SetExpiry::Persist,
+            );
+            db.delete(format!("new:{}", round.max(1) - 1).as_bytes());
+            round += 1;
+            cursor = next;
+            if cursor == 0 {
+                break;
+            }
+        }
+        seen.retain(|key| key.starts_with(b"key:"));
+        seen.sort();
+        let mut expected = keys.collect::<Vec<_>>();
+        expected.sort();
+        assert_eq!(seen, expected);
+    }
+}
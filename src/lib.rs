@@ -1,18 +1,58 @@
+pub mod bitmaps;
+pub mod blocking;
+pub mod client;
 pub mod config;
 pub mod db;
+pub mod error;
+pub mod geo;
+pub mod geohash;
+pub mod glob;
 pub mod handlers;
+pub mod hash;
+pub mod hashes;
+pub mod hyperloglog;
+pub mod keyspace;
+pub mod lists;
+pub mod long_double;
 pub mod master;
 pub mod parser;
 pub mod replica;
+pub mod set;
+pub mod sets;
+pub mod sorted_set;
+pub mod sorted_sets;
+pub mod streams;
+pub mod strings;
 pub mod thread_pool;
+pub mod transactions;
 pub mod utils;
 
 // public re-export
+pub use bitmaps::*;
+pub use blocking::*;
+pub use client::*;
 pub use config::*;
 pub use db::*;
+pub use error::*;
+pub use geo::*;
+pub use geohash::*;
+pub use glob::*;
 pub use handlers::*;
+pub use hash::*;
+pub use hashes::*;
+pub use hyperloglog::*;
+pub use keyspace::*;
+pub use lists::*;
+pub use long_double::*;
 pub use master::*;
 pub use parser::*;
 pub use replica::*;
+pub use set::*;
+pub use sets::*;
+pub use sorted_set::*;
+pub use sorted_sets::*;
+pub use streams::*;
+pub use strings::*;
 pub use thread_pool::*;
+pub use transactions::*;
 pub use utils::*;
@@ -0,0 +1,833 @@
+use std::{
+    collections::VecDeque,
+    future::Future,
+    sync::{Arc, Mutex},
+};
+
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, arg_int, check_arity, clamp_range, is_keyword, parse_timeout, serve_blocked,
+    wait_until_served, BlockedOperation, CommandError, CommandResult, Database, RedisValueRef,
+    SetObject, StoredValue,
+};
+
+/// Which end of a list an operation works on
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum End {
+    Left,
+    Right,
+}
+
+impl End {
+    /// Parse a LEFT or RIGHT argument
+    pub fn parse(arg: &RedisValueRef) -> Result<End, CommandError> {
+        if is_keyword(arg, "left") {
+            Ok(End::Left)
+        } else if is_keyword(arg, "right") {
+            Ok(End::Right)
+        } else {
+            Err(CommandError::Syntax)
+        }
+    }
+}
+
+/// LPUSH, RPUSH, LPUSHX and RPUSHX key element [element ...]
+pub fn handle_push(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let end = if name.starts_with('l') {
+        End::Left
+    } else {
+        End::Right
+    };
+
+    let exists = list(db, key)?.is_some();
+    // The X variants only push onto lists that already exist
+    if name.ends_with('x') && !exists {
+        return Ok(RedisValueRef::Int(0));
+    }
+    let mut values = Vec::with_capacity(args.len() - 1);
+    for arg in &args[1..] {
+        values.push(arg_bytes(arg)?.clone());
+    }
+    let len = push(db, key, end, values);
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// LPOP and RPOP key [count]
+pub fn handle_pop(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 1, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let end = if name.starts_with('l') {
+        End::Left
+    } else {
+        End::Right
+    };
+    let count = match args.get(1) {
+        Some(count) => {
+            let count = arg_int(count)?;
+            if count < 0 {
+                return Err(CommandError::NotPositive);
+            }
+            Some(count as usize)
+        }
+        None => None,
+    };
+
+    let Some(list) = list_mut(db, key)? else {
+        return Ok(match count {
+            Some(_) => RedisValueRef::NullArray,
+            None => RedisValueRef::NullBulkString,
+        });
+    };
+    let reply = match count {
+        Some(count) => {
+            let count = count.min(list.len());
+            let popped = match end {
+                End::Left => list.drain(..count).collect::<Vec<_>>(),
+                End::Right => list.drain(list.len() - count..).rev().collect(),
+            };
+            RedisValueRef::Array(popped.into_iter().map(RedisValueRef::String).collect())
+        }
+        None => match pop_end(list, end) {
+            Some(value) => RedisValueRef::String(value),
+            None => RedisValueRef::NullBulkString,
+        },
+    };
+    if count != Some(0) {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(reply)
+}
+
+/// LLEN key
+pub fn handle_llen(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("llen", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let len = list(db, key)?.map_or(0, |list| list.len());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// LRANGE key start stop
+pub fn handle_lrange(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("lrange", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let start = arg_int(&args[1])?;
+    let end = arg_int(&args[2])?;
+
+    let Some(list) = list(db, key)? else {
+        return Ok(RedisValueRef::Array(vec![]));
+    };
+    let elements = match clamp_range(start, end, list.len()) {
+        Some((start, end)) => list
+            .range(start..=end)
+            .map(|value| RedisValueRef::String(value.clone()))
+            .collect(),
+        None => vec![],
+    };
+    Ok(RedisValueRef::Array(elements))
+}
+
+/// LINDEX key index
+pub fn handle_lindex(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("lindex", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let index = arg_int(&args[1])?;
+
+    let value = list(db, key)?
+        .and_then(|list| resolve_index(index, list.len()).map(|index| list[index].clone()));
+    match value {
+        Some(value) => Ok(RedisValueRef::String(value)),
+        None => Ok(RedisValueRef::NullBulkString),
+    }
+}
+
+/// LSET key index element
+pub fn handle_lset(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("lset", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let index = arg_int(&args[1])?;
+    let value = arg_bytes(&args[2])?;
+
+    let list = list_mut(db, key)?.ok_or(CommandError::NoSuchKey)?;
+    let index = resolve_index(index, list.len()).ok_or(CommandError::IndexOutOfRange)?;
+    list[index] = value.clone();
+    db.signal_modified(key);
+    Ok(RedisValueRef::ok())
+}
+
+/// LREM key count element. A positive count removes from the head, a
+/// negative one from the tail and zero removes every match.
+pub fn handle_lrem(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("lrem", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let count = arg_int(&args[1])?;
+    let element = arg_bytes(&args[2])?;
+
+    let Some(list) = list_mut(db, key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let limit = match count {
+        0 => usize::MAX,
+        count => count.unsigned_abs() as usize,
+    };
+    let mut removed = 0;
+    if count >= 0 {
+        list.retain(|value| {
+            if removed < limit && value == element {
+                removed += 1;
+                return false;
+            }
+            true
+        });
+    } else {
+        let mut index = list.len();
+        while index > 0 && removed < limit {
+            index -= 1;
+            if list[index] == element {
+                list.remove(index);
+                removed += 1;
+            }
+        }
+    }
+    if removed > 0 {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(RedisValueRef::Int(removed as i64))
+}
+
+/// LTRIM key start stop
+pub fn handle_ltrim(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("ltrim", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let start = arg_int(&args[1])?;
+    let end = arg_int(&args[2])?;
+
+    let Some(list) = list_mut(db, key)? else {
+        return Ok(RedisValueRef::ok());
+    };
+    let len = list.len();
+    match clamp_range(start, end, len) {
+        Some((start, end)) => {
+            list.truncate(end + 1);
+            list.drain(..start);
+        }
+        None => list.clear(),
+    }
+    if list.len() != len {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(RedisValueRef::ok())
+}
+
+/// LINSERT key BEFORE | AFTER pivot element
+pub fn handle_linsert(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("linsert", args, 4, Some(4))?;
+    let key = arg_bytes(&args[0])?;
+    let after = if is_keyword(&args[1], "after") {
+        true
+    } else if is_keyword(&args[1], "before") {
+        false
+    } else {
+        return Err(CommandError::Syntax);
+    };
+    let pivot = arg_bytes(&args[2])?;
+    let element = arg_bytes(&args[3])?;
+
+    let Some(list) = list_mut(db, key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let Some(position) = list.iter().position(|value| value == pivot) else {
+        return Ok(RedisValueRef::Int(-1));
+    };
+    list.insert(position + after as usize, element.clone());
+    let len = list.len();
+    db.signal_modified(key);
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// LMOVE source destination LEFT | RIGHT LEFT | RIGHT
+pub fn handle_lmove(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("lmove", args, 4, Some(4))?;
+    let from = End::parse(&args[2])?;
+    let to = End::parse(&args[3])?;
+    move_element(arg_bytes(&args[0])?, arg_bytes(&args[1])?, from, to, db)
+}
+
+/// RPOPLPUSH source destination, the same as LMOVE source destination RIGHT LEFT
+pub fn handle_rpoplpush(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("rpoplpush", args, 2, Some(2))?;
+    move_element(
+        arg_bytes(&args[0])?,
+        arg_bytes(&args[1])?,
+        End::Right,
+        End::Left,
+        db,
+    )
+}
+
+/// BLPOP and BRPOP key [key ...] timeout, BLMOVE source destination
+/// LEFT | RIGHT LEFT | RIGHT timeout and BRPOPLPUSH source destination timeout.
+/// When every list is empty the client is parked until another client pushes
+/// onto one of them or the timeout, in seconds with 0 meaning forever, elapses.
+pub async fn handle_blocking(
+    name: &'static str,
+    args: &[RedisValueRef],
+    client_id: u64,
+    store: &Arc<Mutex<Database>>,
+    closed: impl Future<Output = ()>,
+) -> CommandResult {
+    let (keys, operation) = parse_blocking(name, args)?;
+    let timeout = parse_timeout(&args[args.len() - 1])?;
+
+    let receiver = {
+        let mut db = store.lock().unwrap();
+        if let Some(reply) = serve_first(&mut db, &keys, &operation) {
+            serve_blocked(&mut db);
+            return reply;
+        }
+        db.block(client_id, keys, operation)
+    };
+    wait_until_served(client_id, store, receiver, timeout, closed).await
+}
+
+/// A blocking list command run inside a transaction, which never waits and
+/// replies null right away when every list is empty: a null array for the
+/// pops and a null bulk string for the moves, as LMOVE does
+pub fn try_blocking(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    let (keys, operation) = parse_blocking(name, args)?;
+    parse_timeout(&args[args.len() - 1])?;
+    let empty = match operation {
+        BlockedOperation::Move { .. } => RedisValueRef::NullBulkString,
+        _ => RedisValueRef::NullArray,
+    };
+    serve_first(db, &keys, &operation).unwrap_or(Ok(empty))
+}
+
+/// The keys a blocking list command waits on and what it does with them
+fn parse_blocking(
+    name: &'static str,
+    args: &[RedisValueRef],
+) -> Result<(Vec<Bytes>, BlockedOperation), CommandError> {
+    match name {
+        "blmove" => {
+            check_arity(name, args, 5, Some(5))?;
+            let operation = BlockedOperation::Move {
+                destination: arg_bytes(&args[1])?.clone(),
+                from: End::parse(&args[2])?,
+                to: End::parse(&args[3])?,
+            };
+            Ok((vec![arg_bytes(&args[0])?.clone()], operation))
+        }
+        "brpoplpush" => {
+            check_arity(name, args, 3, Some(3))?;
+            let operation = BlockedOperation::Move {
+                destination: arg_bytes(&args[1])?.clone(),
+                from: End::Right,
+                to: End::Left,
+            };
+            Ok((vec![arg_bytes(&args[0])?.clone()], operation))
+        }
+        _ => {
+            check_arity(name, args, 2, None)?;
+            let end = if name == "blpop" {
+                End::Left
+            } else {
+                End::Right
+            };
+            let mut keys = Vec::with_capacity(args.len() - 1);
+            for key in &args[..args.len() - 1] {
+                keys.push(arg_bytes(key)?.clone());
+            }
+            Ok((keys, BlockedOperation::Pop(end)))
+        }
+    }
+}
+
+/// Serve a blocking list command from the first key holding a list, None
+/// when every list is empty
+fn serve_first(
+    db: &mut Database,
+    keys: &[Bytes],
+    operation: &BlockedOperation,
+) -> Option<CommandResult> {
+    for key in keys {
+        match list(db, key) {
+            Ok(Some(_)) => return Some(serve(db, key, operation)),
+            Ok(None) => {}
+            Err(e) => return Some(Err(e)),
+        }
+    }
+    None
+}
+
+/// Serve a client blocked on a list that was pushed to, None when the key
+/// holds no list elements yet
+pub fn serve_list_waiter(
+    db: &mut Database,
+    key: &Bytes,
+    operation: &BlockedOperation,
+) -> Option<CommandResult> {
+    if !matches!(list(db, key), Ok(Some(_))) {
+        return None;
+    }
+    Some(serve(db, key, operation))
+}
+
+/// Run a blocked operation against a key holding a non-empty list
+fn serve(db: &mut Database, key: &Bytes, operation: &BlockedOperation) -> CommandResult {
+    match operation {
+        BlockedOperation::Pop(end) => {
+            let value = list_mut(db, key)?
+                .and_then(|list| pop_end(list, *end))
+                .expect("served lists are never empty");
+            db.signal_modified(key);
+            db.remove_if_empty(key);
+            Ok(RedisValueRef::Array(vec![
+                RedisValueRef::String(key.clone()),
+                RedisValueRef::String(value),
+            ]))
+        }
+        BlockedOperation::Move {
+            destination,
+            from,
+            to,
+        } => move_element(key, destination, *from, *to, db),
+        _ => unreachable!("list waiters only pop or move"),
+    }
+}
+
+/// Undo serving a list waiter whose reply could not be delivered, putting
+/// the element back where it was taken from
+pub fn restore_list_waiter(
+    db: &mut Database,
+    key: &Bytes,
+    operation: &BlockedOperation,
+    reply: RedisValueRef,
+) {
+    match (operation, reply) {
+        (BlockedOperation::Pop(end), RedisValueRef::Array(mut reply)) => {
+            if let Some(RedisValueRef::String(value)) = reply.pop() {
+                push(db, key, *end, vec![value]);
+            }
+        }
+        (
+            BlockedOperation::Move {
+                destination,
+                from,
+                to,
+            },
+            RedisValueRef::String(_),
+        ) => {
+            let value = list_mut(db, destination)
+                .ok()
+                .flatten()
+                .and_then(|list| pop_end(list, *to))
+                .expect("moved element is on the destination");
+            db.signal_modified(destination);
+            db.remove_if_empty(destination);
+            push(db, key, *from, vec![value]);
+        }
+        // Failed operations took nothing
+        _ => {}
+    }
+}
+
+/// Pop an element from one end of `source` and push it onto `destination`
+pub fn move_element(
+    source: &Bytes,
+    destination: &Bytes,
+    from: End,
+    to: End,
+    db: &mut Database,
+) -> CommandResult {
+    if list(db, source)?.is_none() {
+        return Ok(RedisValueRef::NullBulkString);
+    }
+    // Fail before popping so a bad destination leaves the source untouched
+    list(db, destination)?;
+
+    let value = list_mut(db, source)?
+        .and_then(|list| pop_end(list, from))
+        .expect("source list is never empty");
+    db.signal_modified(source);
+    push(db, destination, to, vec![value.clone()]);
+    // Done after the push so rotating a single element list keeps the key
+    db.remove_if_empty(source);
+    Ok(RedisValueRef::String(value))
+}
+
+/// Push values onto a list one at a time, creating it if missing. The caller
+/// must have checked that the key does not hold another type. Returns the
+/// new length of the list.
+pub fn push(db: &mut Database, key: &Bytes, end: End, values: Vec<Bytes>) -> usize {
+    let object = db.get_or_insert_with(key, || StoredValue::List(VecDeque::new()));
+    let SetObject {
+        value: StoredValue::List(list),
+        ..
+    } = object
+    else {
+        unreachable!("push onto a key holding another type");
+    };
+    for value in values {
+        match end {
+            End::Left => list.push_front(value),
+            End::Right => list.push_back(value),
+        }
+    }
+    let len = list.len();
+    db.signal_modified(key);
+    len
+}
+
+/// Get the list held at a key, failing if the key holds another type
+pub fn list<'a>(
+    db: &'a mut Database,
+    key: &[u8],
+) -> Result<Option<&'a VecDeque<Bytes>>, CommandError> {
+    match db.get(key) {
+        Some(SetObject {
+            value: StoredValue::List(list),
+            ..
+        }) => Ok(Some(list)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the list held at a key for modification. Callers must report changes
+/// with `Database::signal_modified`, and call `Database::remove_if_empty` if
+/// they may empty the list.
+pub fn list_mut<'a>(
+    db: &'a mut Database,
+    key: &[u8],
+) -> Result<Option<&'a mut VecDeque<Bytes>>, CommandError> {
+    match db.get_mut(key) {
+        Some(SetObject {
+            value: StoredValue::List(list),
+            ..
+        }) => Ok(Some(list)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+fn pop_end(list: &mut VecDeque<Bytes>, end: End) -> Option<Bytes> {
+    match end {
+        End::Left => list.pop_front(),
+        End::Right => list.pop_back(),
+    }
+}
+
+/// Resolve an index that may count from the end of the list
+fn resolve_index(index: i64, len: usize) -> Option<usize> {
+    let index = if index < 0 { len as i64 + index } else { index };
+    (0..len as i64).contains(&index).then_some(index as usize)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{args, SetExpiry};
+
+    fn strings(values: &[&'static str]) -> CommandResult {
+        Ok(RedisValueRef::Array(
+            values
+                .iter()
+                .map(|value| RedisValueRef::bulk_string(*value))
+                .collect(),
+        ))
+    }
+
+    fn bulk(value: &'static str) -> CommandResult {
+        Ok(RedisValueRef::bulk_string(value))
+    }
+
+    fn contents(db: &mut Database, key: &Bytes) -> Vec<Bytes> {
+        list(db, key)
+            .unwrap()
+            .map_or_else(Vec::new, |list| list.iter().cloned().collect())
+    }
+
+    #[test]
+    fn restores_undelivered_pops_and_moves() {
+        let mut db = Database::new();
+        let (source, destination) = (Bytes::from("source"), Bytes::from("destination"));
+        let values = ["a", "b", "c"].map(Bytes::from).to_vec();
+        push(&mut db, &source, End::Right, values.clone());
+
+        let pop = BlockedOperation::Pop(End::Left);
+        let reply = serve(&mut db, &source, &pop).unwrap();
+        restore_list_waiter(&mut db, &source, &pop, reply);
+        assert_eq!(contents(&mut db, &source), values);
+
+        let operation = BlockedOperation::Move {
+            destination: destination.clone(),
+            from: End::Right,
+            to: End::Left,
+        };
+        let reply = serve(&mut db, &source, &operation).unwrap();
+        assert_eq!(contents(&mut db, &destination), vec![Bytes::from("c")]);
+        restore_list_waiter(&mut db, &source, &operation, reply);
+        assert_eq!(contents(&mut db, &source), values);
+        assert!(!db.exists(&destination));
+    }
+
+    #[test]
+    fn push_and_pop() {
+        let mut db = Database::new();
+        assert_eq!(
+            handle_push("lpushx", &args("l a"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert!(!db.exists(b"l"));
+        assert_eq!(
+            handle_push("lpush", &args("l b a"), &mut db),
+            Ok(RedisValueRef::Int(2))
+        );
+        assert_eq!(
+            handle_push("rpush", &args("l c d e"), &mut db),
+            Ok(RedisValueRef::Int(5))
+        );
+        assert_eq!(
+            handle_push("rpushx", &args("l f"), &mut db),
+            Ok(RedisValueRef::Int(6))
+        );
+        assert_eq!(
+            handle_lrange(&args("l 0 -1"), &mut db),
+            strings(&["a", "b", "c", "d", "e", "f"])
+        );
+
+        assert_eq!(handle_pop("lpop", &args("l"), &mut db), bulk("a"));
+        assert_eq!(
+            handle_pop("rpop", &args("l 2"), &mut db),
+            strings(&["f", "e"])
+        );
+        assert_eq!(handle_pop("lpop", &args("l 0"), &mut db), strings(&[]));
+        assert_eq!(
+            handle_pop("lpop", &args("l -1"), &mut db),
+            Err(CommandError::NotPositive)
+        );
+        // Popping the last element removes the key
+        assert_eq!(
+            handle_pop("lpop", &args("l 10"), &mut db),
+            strings(&["b", "c", "d"])
+        );
+        assert!(!db.exists(b"l"));
+        assert_eq!(
+            handle_pop("lpop", &args("l"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+        assert_eq!(
+            handle_pop("lpop", &args("l 1"), &mut db),
+            Ok(RedisValueRef::NullArray)
+        );
+
+        db.set(Bytes::from("s"), Bytes::from("v"), SetExpiry::Persist);
+        assert_eq!(
+            handle_push("lpush", &args("s a"), &mut db),
+            Err(CommandError::WrongType)
+        );
+        assert_eq!(
+            handle_pop("rpop", &args("s"), &mut db),
+            Err(CommandError::WrongType)
+        );
+        assert_eq!(
+            handle_llen(&args("s"), &mut db),
+            Err(CommandError::WrongType)
+        );
+    }
+
+    #[test]
+    fn indexes_and_ranges() {
+        let mut db = Database::new();
+        handle_push("rpush", &args("l a b c d"), &mut db).unwrap();
+        assert_eq!(handle_llen(&args("l"), &mut db), Ok(RedisValueRef::Int(4)));
+        assert_eq!(
+            handle_llen(&args("missing"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(handle_lrange(&args("l 1 2"), &mut db), strings(&["b", "c"]));
+        assert_eq!(
+            handle_lrange(&args("l -100 100"), &mut db),
+            strings(&["a", "b", "c", "d"])
+        );
+        assert_eq!(handle_lrange(&args("l 3 1"), &mut db), strings(&[]));
+        assert_eq!(handle_lrange(&args("missing 0 -1"), &mut db), strings(&[]));
+
+        assert_eq!(handle_lindex(&args("l 0"), &mut db), bulk("a"));
+        assert_eq!(handle_lindex(&args("l -1"), &mut db), bulk("d"));
+        assert_eq!(
+            handle_lindex(&args("l 4"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+
+        assert_eq!(
+            handle_lset(&args("l -2 C"), &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        assert_eq!(handle_lindex(&args("l 2"), &mut db), bulk("C"));
+        assert_eq!(
+            handle_lset(&args("l 4 x"), &mut db),
+            Err(CommandError::IndexOutOfRange)
+        );
+        assert_eq!(
+            handle_lset(&args("missing 0 x"), &mut db),
+            Err(CommandError::NoSuchKey)
+        );
+    }
+
+    #[test]
+    fn lrem_ltrim_and_linsert() {
+        let mut db = Database::new();
+        handle_push("rpush", &args("l x a x b x c x"), &mut db).unwrap();
+        assert_eq!(
+            handle_lrem(&args("l 2 x"), &mut db),
+            Ok(RedisValueRef::Int(2))
+        );
+        assert_eq!(
+            handle_lrange(&args("l 0 -1"), &mut db),
+            strings(&["a", "b", "x", "c", "x"])
+        );
+        assert_eq!(
+            handle_lrem(&args("l -1 x"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(
+            handle_lrange(&args("l 0 -1"), &mut db),
+            strings(&["a", "b", "x", "c"])
+        );
+        assert_eq!(
+            handle_lrem(&args("l 0 x"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(
+            handle_lrem(&args("l 0 x"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+
+        assert_eq!(
+            handle_linsert(&args("l BEFORE b 1"), &mut db),
+            Ok(RedisValueRef::Int(4))
+        );
+        assert_eq!(
+            handle_linsert(&args("l after c 2"), &mut db),
+            Ok(RedisValueRef::Int(5))
+        );
+        assert_eq!(
+            handle_linsert(&args("l after nope 3"), &mut db),
+            Ok(RedisValueRef::Int(-1))
+        );
+        assert_eq!(
+            handle_linsert(&args("missing after a 3"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(
+            handle_linsert(&args("l middle a 3"), &mut db),
+            Err(CommandError::Syntax)
+        );
+        assert_eq!(
+            handle_lrange(&args("l 0 -1"), &mut db),
+            strings(&["a", "1", "b", "c", "2"])
+        );
+
+        assert_eq!(
+            handle_ltrim(&args("l 1 -2"), &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        assert_eq!(
+            handle_lrange(&args("l 0 -1"), &mut db),
+            strings(&["1", "b", "c"])
+        );
+        // An empty range deletes the list
+        assert_eq!(
+            handle_ltrim(&args("l 5 10"), &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        assert!(!db.exists(b"l"));
+    }
+
+    #[test]
+    fn lmove_and_rpoplpush() {
+        let mut db = Database::new();
+        handle_push("rpush", &args("src a b c"), &mut db).unwrap();
+        assert_eq!(
+            handle_lmove(&args("src dst LEFT RIGHT"), &mut db),
+            bulk("a")
+        );
+        assert_eq!(handle_rpoplpush(&args("src dst"), &mut db), bulk("c"));
+        assert_eq!(
+            handle_lrange(&args("dst 0 -1"), &mut db),
+            strings(&["c", "a"])
+        );
+
+        // Moving within one list rotates it
+        assert_eq!(
+            handle_lmove(&args("dst dst left right"), &mut db),
+            bulk("c")
+        );
+        assert_eq!(
+            handle_lrange(&args("dst 0 -1"), &mut db),
+            strings(&["a", "c"])
+        );
+
+        // Emptying the source removes it, a missing source moves nothing
+        assert_eq!(handle_rpoplpush(&args("src dst"), &mut db), bulk("b"));
+        assert!(!db.exists(b"src"));
+        assert_eq!(
+            handle_rpoplpush(&args("src dst"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+        assert_eq!(
+            handle_lmove(&args("dst src up down"), &mut db),
+            Err(CommandError::Syntax)
+        );
+
+        // A destination of the wrong type leaves the source untouched
+        db.set(Bytes::from("s"), Bytes::from("v"), SetExpiry::Persist);
+        assert_eq!(
+            handle_rpoplpush(&args("dst s"), &mut db),
+            Err(CommandError::WrongType)
+        );
+        assert_eq!(
+            handle_llen(&args("dst"), &mut db),
+            Ok(RedisValueRef::Int(3))
+        );
+    }
+
+    #[test]
+    fn blocking_commands_in_transactions_never_wait() {
+        let mut db = Database::new();
+        assert_eq!(
+            try_blocking("blpop", &args("a b 0"), &mut db),
+            Ok(RedisValueRef::NullArray)
+        );
+        assert_eq!(
+            try_blocking("blmove", &args("a b left right 0"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+        assert_eq!(
+            try_blocking("brpoplpush", &args("a b 0"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+
+        handle_push("rpush", &args("b x y"), &mut db).unwrap();
+        assert_eq!(
+            try_blocking("brpop", &args("a b 0"), &mut db),
+            strings(&["b", "y"])
+        );
+        assert_eq!(
+            try_blocking("brpoplpush", &args("b a 0"), &mut db),
+            bulk("x")
+        );
+        assert_eq!(handle_llen(&args("a"), &mut db), Ok(RedisValueRef::Int(1)));
+    }
+}
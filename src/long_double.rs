@@ -0,0 +1,457 @@
+use std::cmp::Ordering;
+use std::ops::Add;
+
+// Redis parses INCRBYFLOAT operands with strtold, adds them as x87 long
+// doubles (a 64 bit mantissa) and prints the sum with "%.17Lf". Emulating
+// that precision is what makes 0.1 + 0.2 come out as 0.3, so replies and
+// stored values match Redis exactly.
+
+/// Bits in the mantissa of an x87 long double
+const MANTISSA_BITS: usize = 64;
+/// Fractional digits printed, Redis' "%.17Lf"
+const FRACTION_DIGITS: u32 = 17;
+/// Significant digits kept when parsing, far beyond what can change the
+/// rounding of a 64 bit mantissa
+const MAX_PARSE_DIGITS: usize = 800;
+/// Decimal exponents past which a value overflows or underflows for sure
+const MAX_DECIMAL_EXPONENT: i64 = 5000;
+
+/// A long double `mantissa * 2^exponent`, rounded to 64 significant bits
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LongDouble {
+    negative: bool,
+    mantissa: u64,
+    exponent: i64,
+}
+
+impl LongDouble {
+    pub const ZERO: LongDouble = LongDouble {
+        negative: false,
+        mantissa: 0,
+        exponent: 0,
+    };
+
+    /// Parse a finite decimal number like `-1.5`, `.25` or `3e-7`, rounding
+    /// to the nearest long double as strtold does. None if it is malformed
+    /// or out of range.
+    pub fn parse(text: &[u8]) -> Option<LongDouble> {
+        let (negative, text) = match text {
+            [b'-', rest @ ..] => (true, rest),
+            [b'+', rest @ ..] => (false, rest),
+            _ => (false, text),
+        };
+        let (number, exponent) = match text.iter().position(|c| matches!(c, b'e' | b'E')) {
+            Some(at) => (&text[..at], Some(&text[at + 1..])),
+            None => (text, None),
+        };
+        let mut exponent: i64 = match exponent {
+            Some(exponent) => std::str::from_utf8(exponent)
+                .ok()?
+                .parse::<i64>()
+                .ok()?
+                .clamp(-2 * MAX_DECIMAL_EXPONENT, 2 * MAX_DECIMAL_EXPONENT),
+            None => 0,
+        };
+
+        let mut digits = Vec::new();
+        let mut seen_digit = false;
+        let mut seen_point = false;
+        let mut sticky = false;
+        for &c in number {
+            match c {
+                b'.' if !seen_point => seen_point = true,
+                b'0'..=b'9' => {
+                    seen_digit = true;
+                    if seen_point {
+                        exponent -= 1;
+                    }
+                    if digits.is_empty() && c == b'0' {
+                        continue;
+                    }
+                    if digits.len() < MAX_PARSE_DIGITS {
+                        digits.push(c - b'0');
+                    } else {
+                        // Dropped digits only matter as a tie breaker
+                        exponent += 1;
+                        sticky |= c != b'0';
+                    }
+                }
+                _ => return None,
+            }
+        }
+        if !seen_digit {
+            return None;
+        }
+        if digits.is_empty() {
+            return Some(LongDouble {
+                negative,
+                ..Self::ZERO
+            });
+        }
+        if sticky {
+            digits.push(1);
+            exponent -= 1;
+        }
+
+        let magnitude = exponent + digits.len() as i64;
+        if magnitude > MAX_DECIMAL_EXPONENT {
+            return None;
+        }
+        if magnitude < -MAX_DECIMAL_EXPONENT {
+            return Some(LongDouble {
+                negative,
+                ..Self::ZERO
+            });
+        }
+        let mut value = Big::default();
+        for digit in digits {
+            value.mul_small(10);
+            value.add_small(digit as u32);
+        }
+        let parsed = if exponent >= 0 {
+            value.mul_pow10(exponent as u32);
+            Self::round(negative, &value, 0)
+        } else {
+            let mut divisor = Big::from_u64(1);
+            divisor.mul_pow10(exponent.unsigned_abs() as u32);
+            // Enough quotient bits to round to the mantissa correctly
+            let shift = (MANTISSA_BITS + 2 + divisor.bit_len()).saturating_sub(value.bit_len());
+            let (quotient, remainder) = value.shl(shift).div_rem(&divisor);
+            let mut quotient = quotient;
+            // A non-zero remainder breaks ties, like dropped digits do
+            quotient = quotient.shl(1);
+            if !remainder.is_zero() {
+                quotient.add_small(1);
+            }
+            Self::round(negative, &quotient, -(shift as i64) - 1)
+        };
+        Some(parsed)
+    }
+
+    /// Format like Redis' ld2string in human mode: "%.17Lf" with trailing
+    /// zeros and a trailing point removed
+    pub fn to_human_string(self) -> String {
+        let mut scaled = Big::from_u64(self.mantissa);
+        scaled.mul_pow10(FRACTION_DIGITS);
+        let scaled = if self.exponent >= 0 {
+            scaled.shl(self.exponent as usize)
+        } else {
+            scaled.shr_round(self.exponent.unsigned_abs() as usize)
+        };
+
+        let digits = scaled.to_decimal();
+        let fraction = FRACTION_DIGITS as usize;
+        let digits = format!("{:0>width$}", digits, width = fraction + 1);
+        let (integer, fraction) = digits.split_at(digits.len() - fraction);
+        let fraction = fraction.trim_end_matches('0');
+        let mut text = String::new();
+        // "-0" comes out as "0"
+        if self.negative && (integer != "0" || !fraction.is_empty()) {
+            text.push('-');
+        }
+        text.push_str(integer);
+        if !fraction.is_empty() {
+            text.push('.');
+            text.push_str(fraction);
+        }
+        text
+    }
+
+    /// Round `value * 2^exponent` to 64 significant bits, ties to even.
+    /// The lowest bit of `value` must already account for anything dropped.
+    fn round(negative: bool, value: &Big, exponent: i64) -> LongDouble {
+        let extra = value.bit_len().saturating_sub(MANTISSA_BITS);
+        let mut mantissa = value.shr_round(extra);
+        let mut exponent = exponent + extra as i64;
+        // Rounding up may carry into a 65th bit, which is then a zero
+        if mantissa.bit_len() > MANTISSA_BITS {
+            mantissa = mantissa.shr_round(1);
+            exponent += 1;
+        }
+        LongDouble {
+            negative,
+            mantissa: mantissa.to_u64(),
+            exponent,
+        }
+    }
+}
+
+impl Add for LongDouble {
+    type Output = LongDouble;
+
+    /// The sum rounded to the nearest long double
+    fn add(self, other: LongDouble) -> LongDouble {
+        if self.mantissa == 0 {
+            return other;
+        }
+        if other.mantissa == 0 {
+            return self;
+        }
+        let exponent = self.exponent.min(other.exponent);
+        let a = Big::from_u64(self.mantissa).shl((self.exponent - exponent) as usize);
+        let b = Big::from_u64(other.mantissa).shl((other.exponent - exponent) as usize);
+        if self.negative == other.negative {
+            return Self::round(self.negative, &a.add(&b), exponent);
+        }
+        match a.cmp(&b) {
+            Ordering::Equal => Self::ZERO,
+            Ordering::Greater => Self::round(self.negative, &a.sub(&b), exponent),
+            Ordering::Less => Self::round(other.negative, &b.sub(&a), exponent),
+        }
+    }
+}
+
+/// Unsigned arbitrary precision integer, little-endian 32 bit limbs without
+/// trailing zero limbs
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+struct Big(Vec<u32>);
+
+impl Big {
+    fn from_u64(value: u64) -> Big {
+        let mut big = Big(vec![value as u32, (value >> 32) as u32]);
+        big.trim();
+        big
+    }
+
+    fn to_u64(&self) -> u64 {
+        self.0
+            .iter()
+            .take(2)
+            .enumerate()
+            .fold(0, |value, (i, limb)| value | (*limb as u64) << (32 * i))
+    }
+
+    fn trim(&mut self) {
+        while self.0.last() == Some(&0) {
+            self.0.pop();
+        }
+    }
+
+    fn is_zero(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    fn bit_len(&self) -> usize {
+        match self.0.last() {
+            Some(top) => self.0.len() * 32 - top.leading_zeros() as usize,
+            None => 0,
+        }
+    }
+
+    fn bit(&self, index: usize) -> bool {
+        self.0
+            .get(index / 32)
+            .is_some_and(|limb| limb >> (index % 32) & 1 == 1)
+    }
+
+    /// Whether any bit below `index` is set
+    fn any_below(&self, index: usize) -> bool {
+        let limbs = index / 32;
+        self.0.iter().take(limbs).any(|limb| *limb != 0)
+            || self
+                .0
+                .get(limbs)
+                .is_some_and(|limb| limb & ((1u32 << (index % 32)) - 1) != 0)
+    }
+
+    fn mul_small(&mut self, factor: u32) {
+        let mut carry = 0u64;
+        for limb in &mut self.0 {
+            let product = *limb as u64 * factor as u64 + carry;
+            *limb = product as u32;
+            carry = product >> 32;
+        }
+        if carry != 0 {
+            self.0.push(carry as u32);
+        }
+        self.trim();
+    }
+
+    fn mul_pow10(&mut self, power: u32) {
+        for _ in 0..power / 9 {
+            self.mul_small(1_000_000_000);
+        }
+        self.mul_small(10u32.pow(power % 9));
+    }
+
+    fn add_small(&mut self, addend: u32) {
+        let mut carry = addend as u64;
+        for limb in &mut self.0 {
+            if carry == 0 {
+                return;
+            }
+            let sum = *limb as u64 + carry;
+            *limb = sum as u32;
+            carry = sum >> 32;
+        }
+        if carry != 0 {
+            self.0.push(carry as u32);
+        }
+    }
+
+    fn add(&self, other: &Big) -> Big {
+        let mut sum = Vec::with_capacity(self.0.len().max(other.0.len()) + 1);
+        let mut carry = 0u64;
+        for i in 0..self.0.len().max(other.0.len()) {
+            let total =
+                *self.0.get(i).unwrap_or(&0) as u64 + *other.0.get(i).unwrap_or(&0) as u64 + carry;
+            sum.push(total as u32);
+            carry = total >> 32;
+        }
+        sum.push(carry as u32);
+        let mut sum = Big(sum);
+        sum.trim();
+        sum
+    }
+
+    /// `self - other`, which must not be negative
+    fn sub(&self, other: &Big) -> Big {
+        let mut difference = Vec::with_capacity(self.0.len());
+        let mut borrow = 0i64;
+        for (i, limb) in self.0.iter().enumerate() {
+            let mut total = *limb as i64 - *other.0.get(i).unwrap_or(&0) as i64 - borrow;
+            borrow = (total < 0) as i64;
+            total += borrow << 32;
+            difference.push(total as u32);
+        }
+        let mut difference = Big(difference);
+        difference.trim();
+        difference
+    }
+
+    fn shl(&self, bits: usize) -> Big {
+        if self.is_zero() {
+            return Big::default();
+        }
+        let (limbs, bits) = (bits / 32, bits % 32);
+        let mut shifted = vec![0; limbs];
+        let mut carry = 0u32;
+        for limb in &self.0 {
+            shifted.push(limb << bits | carry);
+            carry = if bits == 0 { 0 } else { limb >> (32 - bits) };
+        }
+        shifted.push(carry);
+        let mut shifted = Big(shifted);
+        shifted.trim();
+        shifted
+    }
+
+    /// `self / 2^bits` rounded to the nearest integer, ties to even
+    fn shr_round(&self, bits: usize) -> Big {
+        if bits == 0 {
+            return self.clone();
+        }
+        let (limbs, offset) = (bits / 32, bits % 32);
+        let mut shifted: Vec<u32> = self
+            .0
+            .iter()
+            .skip(limbs)
+            .enumerate()
+            .map(|(i, limb)| {
+                let high = match self.0.get(limbs + i + 1) {
+                    Some(next) if offset != 0 => next << (32 - offset),
+                    _ => 0,
+                };
+                limb >> offset | high
+            })
+            .collect();
+        let half = self.bit(bits - 1);
+        let odd = shifted.first().is_some_and(|limb| limb & 1 == 1);
+        let mut rounded = Big(std::mem::take(&mut shifted));
+        rounded.trim();
+        if half && (odd || self.any_below(bits - 1)) {
+            rounded.add_small(1);
+        }
+        rounded
+    }
+
+    /// Quotient and remainder, by binary long division
+    fn div_rem(&self, divisor: &Big) -> (Big, Big) {
+        let mut quotient = vec![0u32; self.0.len()];
+        let mut remainder = Big::default();
+        for index in (0..self.bit_len()).rev() {
+            remainder = remainder.shl(1);
+            if self.bit(index) {
+                remainder.add_small(1);
+            }
+            if remainder.cmp(divisor) != Ordering::Less {
+                remainder = remainder.sub(divisor);
+                quotient[index / 32] |= 1 << (index % 32);
+            }
+        }
+        let mut quotient = Big(quotient);
+        quotient.trim();
+        (quotient, remainder)
+    }
+
+    fn cmp(&self, other: &Big) -> Ordering {
+        self.0
+            .len()
+            .cmp(&other.0.len())
+            .then_with(|| self.0.iter().rev().cmp(other.0.iter().rev()))
+    }
+
+    fn to_decimal(&self) -> String {
+        let mut chunks = Vec::new();
+        let mut value = self.0.clone();
+        while !value.is_empty() {
+            let mut remainder = 0u64;
+            for limb in value.iter_mut().rev() {
+                let current = remainder << 32 | *limb as u64;
+                *limb = (current / 1_000_000_000) as u32;
+                remainder = current % 1_000_000_000;
+            }
+            while value.last() == Some(&0) {
+                value.pop();
+            }
+            chunks.push(remainder as u32);
+        }
+        let Some((first, rest)) = chunks.split_last() else {
+            return "0".to_string();
+        };
+        let mut text = first.to_string();
+        for chunk in rest.iter().rev() {
+            text.push_str(&format!("{:09}", chunk));
+        }
+        text
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sum(a: &str, b: &str) -> String {
+        let a = LongDouble::parse(a.as_bytes()).unwrap();
+        let b = LongDouble::parse(b.as_bytes()).unwrap();
+        (a + b).to_human_string()
+    }
+
+    #[test]
+    fn adds_like_redis() {
+        // Expected values from strtold, long double addition and "%.17Lf"
+        assert_eq!(sum("0.1", "0.2"), "0.3");
+        assert_eq!(sum("10.50", "0.1"), "10.6");
+        assert_eq!(sum("5.0e3", "2.0e2"), "5200");
+        assert_eq!(sum("1.1", "0"), "1.1");
+        assert_eq!(sum("1e20", "0.1"), "100000000000000000000");
+        assert_eq!(sum("3", "-3"), "0");
+        assert_eq!(sum("-0", "0"), "0");
+        assert_eq!(sum("-1.5", "0.25"), "-1.25");
+        assert_eq!(sum("1e-18", "0"), "0");
+        assert_eq!(sum("-1e-18", "0"), "0");
+        assert_eq!(sum("5e-18", "0"), "0");
+        assert_eq!(sum("6e-18", "0"), "0.00000000000000001");
+        assert_eq!(sum(".5", "1."), "1.5");
+        assert_eq!(sum("+2", "1E2"), "102");
+    }
+
+    #[test]
+    fn rejects_malformed_numbers() {
+        for text in [
+            "", "-", ".", "e5", "1e", "1.2.3", "1x", "inf", "nan", "0x10",
+        ] {
+            assert_eq!(LongDouble::parse(text.as_bytes()), None, "{}", text);
+        }
+    }
+}
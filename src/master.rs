@@ -1,27 +1,40 @@
 use std::{
-    net::TcpListener,
     sync::{Arc, Mutex},
+    time::Duration,
 };
 
+use tokio::net::TcpListener;
+
 use crate::{handle_client, Database, Server};
 
+/// How often expired keys are actively reclaimed, Redis' default `hz 10`
+const ACTIVE_EXPIRE_INTERVAL: Duration = Duration::from_millis(100);
+
 pub async fn run_master(server_info: Server) {
     //Setting up master server
-    let listener =
-        TcpListener::bind(format!("{}:{}", &server_info.host, &server_info.port)).unwrap();
+    let listener = TcpListener::bind(format!("{}:{}", &server_info.host, &server_info.port))
+        .await
+        .unwrap();
 
     // Preparing for multithreading
     let database = Arc::new(Mutex::new(Database::new()));
     let server_info = Arc::new(Mutex::new(server_info));
 
+    // Free keys that expire without ever being accessed again
+    tokio::spawn(active_expire(Arc::clone(&database)));
+
     // Processing the stream
-    for stream in listener.incoming() {
-        match stream {
-            Ok(stream) => {
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
                 println!("accepted new connection");
                 let store = Arc::clone(&database);
                 let server_info = Arc::clone(&server_info);
-                tokio::spawn(async move { handle_client(stream, store, server_info).await });
+                tokio::spawn(async move {
+                    if let Err(e) = handle_client(stream, store, server_info).await {
+                        println!("error: {}", e);
+                    }
+                });
             }
             Err(e) => {
                 println!("error: {}", e);
@@ -29,3 +42,11 @@ pub async fn run_master(server_info: Server) {
         }
     }
 }
+
+async fn active_expire(database: Arc<Mutex<Database>>) {
+    let mut interval = tokio::time::interval(ACTIVE_EXPIRE_INTERVAL);
+    loop {
+        interval.tick().await;
+        database.lock().unwrap().active_expire_cycle();
+    }
+}
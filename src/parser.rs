@@ -1,18 +1,64 @@
-use bytes::{Bytes, BytesMut};
+use bytes::{BufMut, Bytes, BytesMut};
 use memchr::memchr;
-use std::str;
+use std::{
+    fmt::{self, Write},
+    io, str,
+};
+use tokio_util::codec::{Decoder, Encoder};
 
 pub type Value = Bytes;
 pub type Key = Bytes;
 
 #[derive(PartialEq, Clone, Debug)]
 pub enum RedisValueRef {
+    SimpleString(Bytes),
     String(Bytes),
     Error(Bytes),
     Int(i64),
     Array(Vec<RedisValueRef>),
     NullArray,
     NullBulkString,
+    // RESP3 types
+    Map(Vec<(RedisValueRef, RedisValueRef)>),
+    Set(Vec<RedisValueRef>),
+    Double(f64),
+    Boolean(bool),
+    Null,
+    BigNumber(Bytes),
+    /// Three byte format (e.g. `txt`) and the string itself
+    VerbatimString(Bytes, Bytes),
+    Attribute(Vec<(RedisValueRef, RedisValueRef)>),
+    Push(Vec<RedisValueRef>),
+}
+
+/// RESP version spoken on a connection, negotiated with HELLO
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Protocol {
+    #[default]
+    Resp2,
+    Resp3,
+}
+
+impl RedisValueRef {
+    /// The `+OK` reply
+    pub fn ok() -> Self {
+        RedisValueRef::SimpleString(Bytes::from_static(b"OK"))
+    }
+
+    pub fn simple_string(s: &str) -> Self {
+        RedisValueRef::SimpleString(Bytes::copy_from_slice(s.as_bytes()))
+    }
+
+    pub fn bulk_string(s: impl Into<Bytes>) -> Self {
+        RedisValueRef::String(s.into())
+    }
+
+    /// An error reply. Errors may quote client input, and a CR or LF in it
+    /// would end the line early, so those become spaces.
+    pub fn error(msg: &str) -> Self {
+        let msg = msg.replace(['\r', '\n'], " ");
+        RedisValueRef::Error(Bytes::from(msg))
+    }
 }
 
 /// A type for viewing byte slices
@@ -20,12 +66,22 @@ pub struct BufSplit(usize, usize);
 
 /// BufSplit based equivalent for RedisValueRef
 pub enum RedisBufSplit {
+    SimpleString(BufSplit),
     String(BufSplit),
     Error(BufSplit),
     Int(i64),
     Array(Vec<RedisBufSplit>),
     NullArray,
     NullBulkString,
+    Map(Vec<(RedisBufSplit, RedisBufSplit)>),
+    Set(Vec<RedisBufSplit>),
+    Double(f64),
+    Boolean(bool),
+    Null,
+    BigNumber(BufSplit),
+    VerbatimString(BufSplit, BufSplit),
+    Attribute(Vec<(RedisBufSplit, RedisBufSplit)>),
+    Push(Vec<RedisBufSplit>),
 }
 
 #[derive(Debug)]
@@ -36,55 +92,113 @@ pub enum RESPError {
     IntParseFailure,
     BadBulkStringSize(i64),
     BadArraySize(i64),
+    BadBoolean,
+    BadNull,
+    DoubleParseFailure,
+    BadVerbatimString,
+    UnbalancedQuotes,
+    InlineTooBig,
+    LineTooLong,
+    MissingCrlf,
+    TooDeep,
+}
+
+impl fmt::Display for RESPError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RESPError::UnbalancedQuotes => {
+                write!(f, "Protocol error: unbalanced quotes in request")
+            }
+            RESPError::InlineTooBig => write!(f, "Protocol error: too big inline request"),
+            RESPError::LineTooLong => write!(f, "Protocol error: too big line"),
+            RESPError::MissingCrlf => write!(f, "Protocol error: expected '\\r\\n'"),
+            RESPError::TooDeep => write!(f, "Protocol error: too many nested aggregates"),
+            RESPError::BadBulkStringSize(_) => write!(f, "Protocol error: invalid bulk length"),
+            RESPError::BadArraySize(_) => write!(f, "Protocol error: invalid multibulk length"),
+            RESPError::IOError(e) => write!(f, "{}", e),
+            e => write!(f, "Protocol error: {:?}", e),
+        }
+    }
+}
+
+impl From<io::Error> for RESPError {
+    fn from(e: io::Error) -> Self {
+        RESPError::IOError(e)
+    }
 }
 
+/// Longest inline command or protocol line accepted before its line ending
+/// shows up, as in Redis
+const INLINE_MAX_SIZE: usize = 64 * 1024;
+/// Largest bulk string accepted, Redis' default proto-max-bulk-len
+const BULK_MAX_SIZE: i64 = 512 * 1024 * 1024;
+/// Most elements accepted in an aggregate, as in Redis
+const AGGREGATE_MAX_LEN: i64 = 1024 * 1024;
+/// Deepest nesting of aggregates accepted. Commands are flat arrays, the
+/// limit keeps hostile input from exhausting the stack of the recursive parser.
+const MAX_DEPTH: usize = 32;
+
 pub type RedisResult = Result<Option<(usize, RedisBufSplit)>, RESPError>;
 
-pub struct RedisParser;
+pub struct RedisParser {
+    protocol: Protocol,
+}
 
 impl RedisParser {
     /// Create a new RESP parser
     pub fn new() -> Self {
-        RedisParser
+        RedisParser {
+            protocol: Protocol::Resp2,
+        }
     }
 
-    /// Parse word from RESP
-    pub fn word(&self, buffer: &[u8], pos: usize) -> Option<(usize, BufSplit)> {
+    /// Switch the protocol version used when encoding replies
+    pub fn set_protocol(&mut self, protocol: Protocol) {
+        self.protocol = protocol;
+    }
+
+    /// Parse word from RESP, a line ended by `\r\n`
+    pub fn word(&self, buffer: &[u8], pos: usize) -> Result<Option<(usize, BufSplit)>, RESPError> {
         // We're at the edge of `buf`, so we can't find a word.
         if buffer.len() <= pos {
-            return None;
+            return Ok(None);
         }
         // Find the position of the b'\r'
-        memchr(b'\r', &buffer[pos..]).and_then(|end| {
-            if end + 1 < buffer.len() {
-                // pos + end == first index of b'\r' after `pos`
-                // pos + end + 2 == ..word\r\n<HERE> -- skip to after CLRF
-                Some((pos + end + 2, BufSplit(pos, pos + end)))
-            } else {
-                // Edge case: We received just enough bytes from the client
-                // to get the \r but not the \n
-                None
+        let Some(end) = memchr(b'\r', &buffer[pos..]) else {
+            // Stop buffering a line that will never be accepted
+            if buffer.len() - pos > INLINE_MAX_SIZE {
+                return Err(RESPError::LineTooLong);
             }
-        })
+            return Ok(None);
+        };
+        match buffer.get(pos + end + 1) {
+            // pos + end == first index of b'\r' after `pos`
+            // pos + end + 2 == ..word\r\n<HERE> -- skip to after CLRF
+            Some(b'\n') => Ok(Some((pos + end + 2, BufSplit(pos, pos + end)))),
+            Some(_) => Err(RESPError::MissingCrlf),
+            // Edge case: We received just enough bytes from the client
+            // to get the \r but not the \n
+            None => Ok(None),
+        }
     }
 
     /// Parse simple string from RESP
     pub fn simple_string(&self, buffer: &[u8], pos: usize) -> RedisResult {
         Ok(self
-            .word(buffer, pos)
-            .map(|(pos, word)| (pos, RedisBufSplit::String(word))))
+            .word(buffer, pos)?
+            .map(|(pos, word)| (pos, RedisBufSplit::SimpleString(word))))
     }
 
     /// Parse error from RESP
     pub fn error(&self, buffer: &[u8], pos: usize) -> RedisResult {
         Ok(self
-            .word(buffer, pos)
+            .word(buffer, pos)?
             .map(|(pos, word)| (pos, RedisBufSplit::Error(word))))
     }
 
     /// Parse ints from RESP
     pub fn int(&self, buffer: &[u8], pos: usize) -> Result<Option<(usize, i64)>, RESPError> {
-        match self.word(buffer, pos) {
+        match self.word(buffer, pos)? {
             Some((pos, word)) => {
                 // convert buffer to str
                 let s = str::from_utf8(word.as_slice(buffer))
@@ -109,10 +223,12 @@ impl RedisParser {
         match self.int(buffer, pos)? {
             // Null bulk string encountered
             Some((pos, -1)) => Ok(Some((pos, RedisBufSplit::NullBulkString))),
-            Some((pos, size)) if size >= 0 => {
+            Some((pos, size)) if (0..=BULK_MAX_SIZE).contains(&size) => {
                 let total_size = pos + size as usize;
                 if buffer.len() < total_size + 2 {
                     Ok(None)
+                } else if &buffer[total_size..total_size + 2] != b"\r\n" {
+                    Err(RESPError::MissingCrlf)
                 } else {
                     let bb = RedisBufSplit::String(BufSplit(pos, total_size));
                     Ok(Some((total_size + 2, bb)))
@@ -123,32 +239,156 @@ impl RedisParser {
         }
     }
 
-    /// Parse RESP array
-    pub fn array(&self, buffer: &[u8], pos: usize) -> RedisResult {
+    /// Parse RESP array nested `depth` aggregates deep
+    pub fn array(&self, buffer: &[u8], pos: usize, depth: usize) -> RedisResult {
         match self.int(buffer, pos)? {
             None => Ok(None),
             Some((pos, -1)) => Ok(Some((pos, RedisBufSplit::NullArray))),
-            Some((pos, num_elements)) if num_elements >= 0 => {
-                let mut values = Vec::with_capacity(num_elements as usize);
-                let mut curr_pos = pos;
-                for _ in 0..num_elements {
-                    match self.parse(buffer, curr_pos)? {
-                        Some((new_pos, value)) => {
-                            curr_pos = new_pos;
-                            values.push(value);
-                        }
-                        None => return Ok(None),
+            Some((pos, num_elements)) if (0..=AGGREGATE_MAX_LEN).contains(&num_elements) => {
+                Ok(self
+                    .elements(buffer, pos, num_elements as usize, depth)?
+                    .map(|(pos, values)| (pos, RedisBufSplit::Array(values))))
+            }
+            Some((_pos, bad_num_elements)) => Err(RESPError::BadArraySize(bad_num_elements)),
+        }
+    }
+
+    /// Parse `count` consecutive values of an aggregate nested `depth` deep
+    fn elements(
+        &self,
+        buffer: &[u8],
+        pos: usize,
+        count: usize,
+        depth: usize,
+    ) -> Result<Option<(usize, Vec<RedisBufSplit>)>, RESPError> {
+        if depth >= MAX_DEPTH {
+            return Err(RESPError::TooDeep);
+        }
+        // Don't trust the announced size for the allocation, it comes from the client
+        let mut values = Vec::with_capacity(count.min(1024));
+        let mut curr_pos = pos;
+        for _ in 0..count {
+            match self.value(buffer, curr_pos, depth + 1)? {
+                Some((new_pos, value)) => {
+                    curr_pos = new_pos;
+                    values.push(value);
+                }
+                None => return Ok(None),
+            }
+        }
+        Ok(Some((curr_pos, values)))
+    }
+
+    /// Parse an aggregate of `len` elements (RESP3 set and push)
+    fn sized_elements(
+        &self,
+        buffer: &[u8],
+        pos: usize,
+        depth: usize,
+        make: fn(Vec<RedisBufSplit>) -> RedisBufSplit,
+    ) -> RedisResult {
+        match self.int(buffer, pos)? {
+            None => Ok(None),
+            Some((pos, len)) if (0..=AGGREGATE_MAX_LEN).contains(&len) => Ok(self
+                .elements(buffer, pos, len as usize, depth)?
+                .map(|(pos, values)| (pos, make(values)))),
+            Some((_pos, bad_len)) => Err(RESPError::BadArraySize(bad_len)),
+        }
+    }
+
+    /// Parse an aggregate of `len` key/value pairs (RESP3 map and attribute)
+    fn pairs(
+        &self,
+        buffer: &[u8],
+        pos: usize,
+        depth: usize,
+        make: fn(Vec<(RedisBufSplit, RedisBufSplit)>) -> RedisBufSplit,
+    ) -> RedisResult {
+        match self.int(buffer, pos)? {
+            None => Ok(None),
+            Some((pos, len)) if (0..=AGGREGATE_MAX_LEN).contains(&len) => Ok(self
+                .elements(buffer, pos, 2 * len as usize, depth)?
+                .map(|(pos, values)| {
+                    let mut pairs = Vec::with_capacity(values.len() / 2);
+                    let mut values = values.into_iter();
+                    while let (Some(k), Some(v)) = (values.next(), values.next()) {
+                        pairs.push((k, v));
                     }
+                    (pos, make(pairs))
+                })),
+            Some((_pos, bad_len)) => Err(RESPError::BadArraySize(bad_len)),
+        }
+    }
+
+    /// Parse RESP3 null, its line must be empty
+    pub fn null(&self, buffer: &[u8], pos: usize) -> RedisResult {
+        match self.word(buffer, pos)? {
+            Some((pos, word)) if word.as_slice(buffer).is_empty() => {
+                Ok(Some((pos, RedisBufSplit::Null)))
+            }
+            Some(_) => Err(RESPError::BadNull),
+            None => Ok(None),
+        }
+    }
+
+    /// Parse RESP3 boolean
+    pub fn boolean(&self, buffer: &[u8], pos: usize) -> RedisResult {
+        match self.word(buffer, pos)? {
+            Some((pos, word)) => match word.as_slice(buffer) {
+                b"t" => Ok(Some((pos, RedisBufSplit::Boolean(true)))),
+                b"f" => Ok(Some((pos, RedisBufSplit::Boolean(false)))),
+                _ => Err(RESPError::BadBoolean),
+            },
+            None => Ok(None),
+        }
+    }
+
+    /// Parse RESP3 double, including `inf`, `-inf` and `nan`
+    pub fn double(&self, buffer: &[u8], pos: usize) -> RedisResult {
+        match self.word(buffer, pos)? {
+            Some((pos, word)) => {
+                let d = str::from_utf8(word.as_slice(buffer))
+                    .map_err(|_| RESPError::DoubleParseFailure)?
+                    .parse()
+                    .map_err(|_| RESPError::DoubleParseFailure)?;
+                Ok(Some((pos, RedisBufSplit::Double(d))))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Parse RESP3 big number, kept as its decimal digits
+    pub fn big_number(&self, buffer: &[u8], pos: usize) -> RedisResult {
+        Ok(self
+            .word(buffer, pos)?
+            .map(|(pos, word)| (pos, RedisBufSplit::BigNumber(word))))
+    }
+
+    /// Parse RESP3 verbatim string, a bulk string prefixed by `fmt:`
+    pub fn verbatim_string(&self, buffer: &[u8], pos: usize) -> RedisResult {
+        match self.bulk_string(buffer, pos)? {
+            Some((pos, RedisBufSplit::String(BufSplit(start, end)))) => {
+                if end - start < 4 || buffer[start + 3] != b':' {
+                    return Err(RESPError::BadVerbatimString);
                 }
-                Ok(Some((curr_pos, RedisBufSplit::Array(values))))
+                let format = BufSplit(start, start + 3);
+                let value = BufSplit(start + 4, end);
+                Ok(Some((pos, RedisBufSplit::VerbatimString(format, value))))
             }
-            Some((_pos, bad_num_elements)) => Err(RESPError::BadArraySize(bad_num_elements)),
+            Some(_) => Err(RESPError::BadVerbatimString),
+            None => Ok(None),
         }
     }
 
     /// Top level parse function
     pub fn parse(&self, buffer: &[u8], pos: usize) -> RedisResult {
-        if buffer.is_empty() {
+        self.value(buffer, pos, 0)
+    }
+
+    /// Parse a value nested `depth` aggregates deep
+    fn value(&self, buffer: &[u8], pos: usize, depth: usize) -> RedisResult {
+        // Wait for more data if the next value has not arrived yet
+        if buffer.len() <= pos {
             return Ok(None);
         }
 
@@ -157,15 +397,55 @@ impl RedisParser {
             b'-' => self.error(buffer, pos + 1),
             b'$' => self.bulk_string(buffer, pos + 1),
             b':' => self.resp_int(buffer, pos + 1),
-            b'*' => self.array(buffer, pos + 1),
+            b'*' => self.array(buffer, pos + 1, depth),
+            b'%' => self.pairs(buffer, pos + 1, depth, RedisBufSplit::Map),
+            b'~' => self.sized_elements(buffer, pos + 1, depth, RedisBufSplit::Set),
+            b',' => self.double(buffer, pos + 1),
+            b'#' => self.boolean(buffer, pos + 1),
+            b'_' => self.null(buffer, pos + 1),
+            b'(' => self.big_number(buffer, pos + 1),
+            b'=' => self.verbatim_string(buffer, pos + 1),
+            b'|' => self.pairs(buffer, pos + 1, depth, RedisBufSplit::Attribute),
+            b'>' => self.sized_elements(buffer, pos + 1, depth, RedisBufSplit::Push),
             _ => Err(RESPError::UnknownStartingByte),
         }
     }
+}
+
+impl Default for RedisParser {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
-    /// decode function that will be used at the top level
-    pub fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<RedisValueRef>, RESPError> {
-        if buffer.is_empty() {
-            return Ok(None);
+impl Decoder for RedisParser {
+    type Item = RedisValueRef;
+    type Error = RESPError;
+
+    /// Decode the next complete frame from the connection buffer
+    fn decode(&mut self, buffer: &mut BytesMut) -> Result<Option<RedisValueRef>, RESPError> {
+        loop {
+            if buffer.is_empty() {
+                return Ok(None);
+            }
+            if is_type_byte(buffer[0]) {
+                break;
+            }
+
+            // Anything that isn't RESP is a telnet style inline command
+            let Some(newline) = memchr(b'\n', buffer) else {
+                if buffer.len() > INLINE_MAX_SIZE {
+                    return Err(RESPError::InlineTooBig);
+                }
+                return Ok(None);
+            };
+            let line = buffer.split_to(newline + 1).freeze();
+            let args = split_inline_args(&line[..newline])?;
+            // Empty lines are skipped, like Redis does
+            if !args.is_empty() {
+                let args = args.into_iter().map(RedisValueRef::String).collect();
+                return Ok(Some(RedisValueRef::Array(args)));
+            }
         }
 
         match self.parse(buffer, 0)? {
@@ -173,15 +453,283 @@ impl RedisParser {
                 let data = buffer.split_to(pos);
                 Ok(Some(value.redis_value(&data.freeze())))
             }
-            None => todo!(),
+            // Incomplete frame, leave the buffer untouched until more bytes arrive
+            None => Ok(None),
         }
     }
 }
 
-impl Default for RedisParser {
-    fn default() -> Self {
-        Self::new()
+fn is_type_byte(byte: u8) -> bool {
+    matches!(
+        byte,
+        b'+' | b'-'
+            | b'$'
+            | b':'
+            | b'*'
+            | b'%'
+            | b'~'
+            | b','
+            | b'#'
+            | b'_'
+            | b'('
+            | b'='
+            | b'|'
+            | b'>'
+    )
+}
+
+/// Split an inline command line into arguments, following the quoting rules
+/// of redis-cli: `"..."` understands escapes such as `\n` and `\x41`, while
+/// `'...'` only understands `\'`. A closing quote must be followed by a space.
+pub fn split_inline_args(line: &[u8]) -> Result<Vec<Bytes>, RESPError> {
+    let mut args = Vec::new();
+    let mut pos = 0;
+    loop {
+        while pos < line.len() && line[pos].is_ascii_whitespace() {
+            pos += 1;
+        }
+        if pos == line.len() {
+            return Ok(args);
+        }
+
+        let mut current = Vec::new();
+        match line[pos] {
+            b'"' => {
+                pos += 1;
+                loop {
+                    match line.get(pos) {
+                        None => return Err(RESPError::UnbalancedQuotes),
+                        Some(b'\\')
+                            if pos + 3 < line.len()
+                                && line[pos + 1] == b'x'
+                                && line[pos + 2].is_ascii_hexdigit()
+                                && line[pos + 3].is_ascii_hexdigit() =>
+                        {
+                            let hex = str::from_utf8(&line[pos + 2..pos + 4]).unwrap();
+                            current.push(u8::from_str_radix(hex, 16).unwrap());
+                            pos += 4;
+                        }
+                        Some(b'\\') if pos + 1 < line.len() => {
+                            current.push(match line[pos + 1] {
+                                b'n' => b'\n',
+                                b'r' => b'\r',
+                                b't' => b'\t',
+                                b'b' => 0x08,
+                                b'a' => 0x07,
+                                c => c,
+                            });
+                            pos += 2;
+                        }
+                        Some(b'"') => {
+                            pos += 1;
+                            break;
+                        }
+                        Some(&c) => {
+                            current.push(c);
+                            pos += 1;
+                        }
+                    }
+                }
+            }
+            b'\'' => {
+                pos += 1;
+                loop {
+                    match line.get(pos) {
+                        None => return Err(RESPError::UnbalancedQuotes),
+                        Some(b'\\') if line.get(pos + 1) == Some(&b'\'') => {
+                            current.push(b'\'');
+                            pos += 2;
+                        }
+                        Some(b'\'') => {
+                            pos += 1;
+                            break;
+                        }
+                        Some(&c) => {
+                            current.push(c);
+                            pos += 1;
+                        }
+                    }
+                }
+            }
+            _ => {
+                while pos < line.len() && !line[pos].is_ascii_whitespace() {
+                    current.push(line[pos]);
+                    pos += 1;
+                }
+            }
+        }
+
+        // A closing quote must be followed by a space or the end of the line
+        if pos < line.len() && !line[pos].is_ascii_whitespace() {
+            return Err(RESPError::UnbalancedQuotes);
+        }
+        args.push(Bytes::from(current));
+    }
+}
+
+impl Encoder<RedisValueRef> for RedisParser {
+    type Error = io::Error;
+
+    /// Encode a value into the connection's write buffer
+    fn encode(&mut self, item: RedisValueRef, dst: &mut BytesMut) -> Result<(), io::Error> {
+        write_redis_value(&item, self.protocol, dst);
+        Ok(())
+    }
+}
+
+/// Serialize a RESP value into `dst`, the single path every reply goes through.
+///
+/// RESP3-only types are downgraded the way Redis does when the connection
+/// speaks RESP2.
+pub fn write_redis_value(item: &RedisValueRef, protocol: Protocol, dst: &mut BytesMut) {
+    let resp3 = protocol == Protocol::Resp3;
+    match item {
+        RedisValueRef::SimpleString(s) => write_line(b'+', s, dst),
+        RedisValueRef::Error(e) => write_line(b'-', e, dst),
+        RedisValueRef::String(s) => write_blob(b'$', s, dst),
+        RedisValueRef::Int(i) => write_header(b':', *i, dst),
+        RedisValueRef::Array(arr) => write_aggregate(b'*', arr, protocol, dst),
+        RedisValueRef::NullArray if resp3 => dst.put_slice(b"_\r\n"),
+        RedisValueRef::NullArray => write_header(b'*', -1, dst),
+        RedisValueRef::NullBulkString | RedisValueRef::Null if resp3 => dst.put_slice(b"_\r\n"),
+        RedisValueRef::NullBulkString | RedisValueRef::Null => write_header(b'$', -1, dst),
+        RedisValueRef::Map(pairs) => {
+            write_pairs(if resp3 { b'%' } else { b'*' }, pairs, protocol, dst)
+        }
+        RedisValueRef::Set(values) => {
+            write_aggregate(if resp3 { b'~' } else { b'*' }, values, protocol, dst)
+        }
+        RedisValueRef::Push(values) => {
+            write_aggregate(if resp3 { b'>' } else { b'*' }, values, protocol, dst)
+        }
+        RedisValueRef::Double(d) if resp3 => write_line(b',', format_double(*d).as_bytes(), dst),
+        RedisValueRef::Double(d) => write_blob(b'$', format_double(*d).as_bytes(), dst),
+        RedisValueRef::Boolean(b) if resp3 => dst.put_slice(if *b { b"#t\r\n" } else { b"#f\r\n" }),
+        RedisValueRef::Boolean(b) => write_header(b':', *b as i64, dst),
+        RedisValueRef::BigNumber(n) if resp3 => write_line(b'(', n, dst),
+        RedisValueRef::BigNumber(n) => write_blob(b'$', n, dst),
+        RedisValueRef::VerbatimString(format, s) if resp3 => {
+            write_header(b'=', (format.len() + 1 + s.len()) as i64, dst);
+            dst.put_slice(format);
+            dst.put_u8(b':');
+            dst.put_slice(s);
+            dst.put_slice(b"\r\n");
+        }
+        RedisValueRef::VerbatimString(_, s) => write_blob(b'$', s, dst),
+        RedisValueRef::Attribute(pairs) if resp3 => write_pairs(b'|', pairs, protocol, dst),
+        // RESP2 has no way to carry attributes, clients never see them
+        RedisValueRef::Attribute(_) => {}
+    }
+}
+
+/// Largest magnitude Redis formats as an integer, LLONG_MAX / 2
+const DOUBLE_INTEGER_LIMIT: f64 = (i64::MAX / 2) as f64;
+
+/// Format a double the way Redis replies with it (`d2string`): integral
+/// values as integers, anything else with the shortest digits that read back
+/// as the same double, laid out like `%.17g`
+pub fn format_double(d: f64) -> String {
+    if d.is_nan() {
+        return "nan".to_string();
+    }
+    if d.is_infinite() {
+        return if d > 0.0 { "inf" } else { "-inf" }.to_string();
+    }
+    if d.abs() <= DOUBLE_INTEGER_LIMIT && d.fract() == 0.0 {
+        // Keeps the sign of -0
+        return if d == 0.0 && d.is_sign_negative() {
+            "-0".to_string()
+        } else {
+            (d as i64).to_string()
+        };
     }
+
+    // Shortest round trip digits and the decimal exponent, as in `1.25e-7`
+    let scientific = format!("{:e}", d.abs());
+    let (mantissa, exponent) = scientific
+        .split_once('e')
+        .expect("{:e} always has an exponent");
+    let digits = mantissa.replace('.', "");
+    let exponent: i32 = exponent.parse().expect("{:e} exponent is an integer");
+
+    let mut out = String::with_capacity(digits.len() + 8);
+    if d < 0.0 {
+        out.push('-');
+    }
+    if (-4..17).contains(&exponent) {
+        if exponent < 0 {
+            out.push_str("0.");
+            out.push_str(&"0".repeat((-exponent - 1) as usize));
+            out.push_str(&digits);
+        } else {
+            let int_len = exponent as usize + 1;
+            if digits.len() <= int_len {
+                out.push_str(&digits);
+                out.push_str(&"0".repeat(int_len - digits.len()));
+            } else {
+                out.push_str(&digits[..int_len]);
+                out.push('.');
+                out.push_str(&digits[int_len..]);
+            }
+        }
+    } else {
+        out.push_str(&digits[..1]);
+        if digits.len() > 1 {
+            out.push('.');
+            out.push_str(&digits[1..]);
+        }
+        // Like printf, the exponent is signed and has at least two digits
+        let sign = if exponent < 0 { '-' } else { '+' };
+        write!(out, "e{}{:02}", sign, exponent.abs()).expect("writing to a String cannot fail");
+    }
+    out
+}
+
+/// Write a length-prefixed binary safe string
+fn write_blob(prefix: u8, s: &[u8], dst: &mut BytesMut) {
+    write_header(prefix, s.len() as i64, dst);
+    dst.put_slice(s);
+    dst.put_slice(b"\r\n");
+}
+
+fn write_aggregate(prefix: u8, values: &[RedisValueRef], protocol: Protocol, dst: &mut BytesMut) {
+    write_header(prefix, values.len() as i64, dst);
+    for value in values {
+        write_redis_value(value, protocol, dst);
+    }
+}
+
+/// Write key/value pairs, flattened into an array of twice the length for RESP2
+fn write_pairs(
+    prefix: u8,
+    pairs: &[(RedisValueRef, RedisValueRef)],
+    protocol: Protocol,
+    dst: &mut BytesMut,
+) {
+    let len = if prefix == b'*' {
+        2 * pairs.len()
+    } else {
+        pairs.len()
+    };
+    write_header(prefix, len as i64, dst);
+    for (k, v) in pairs {
+        write_redis_value(k, protocol, dst);
+        write_redis_value(v, protocol, dst);
+    }
+}
+
+/// Write a type byte followed by a line of raw bytes
+fn write_line(prefix: u8, line: &[u8], dst: &mut BytesMut) {
+    dst.reserve(line.len() + 3);
+    dst.put_u8(prefix);
+    dst.put_slice(line);
+    dst.put_slice(b"\r\n");
+}
+
+/// Write a type byte followed by a length or integer
+fn write_header(prefix: u8, n: i64, dst: &mut BytesMut) {
+    dst.put_u8(prefix);
+    write!(dst, "{}\r\n", n).expect("writing to BytesMut cannot fail");
 }
 
 impl BufSplit {
@@ -200,6 +748,7 @@ impl BufSplit {
 impl RedisBufSplit {
     pub fn redis_value(self, buffer: &Bytes) -> RedisValueRef {
         match self {
+            RedisBufSplit::SimpleString(bfs) => RedisValueRef::SimpleString(bfs.as_bytes(buffer)),
             RedisBufSplit::String(bfs) => RedisValueRef::String(bfs.as_bytes(buffer)),
             RedisBufSplit::Error(bfs) => RedisValueRef::Error(bfs.as_bytes(buffer)),
             RedisBufSplit::Int(i) => RedisValueRef::Int(i),
@@ -208,28 +757,341 @@ impl RedisBufSplit {
             }
             RedisBufSplit::NullArray => RedisValueRef::NullArray,
             RedisBufSplit::NullBulkString => RedisValueRef::NullBulkString,
+            RedisBufSplit::Map(pairs) => RedisValueRef::Map(pairs_value(pairs, buffer)),
+            RedisBufSplit::Set(values) => {
+                RedisValueRef::Set(values.into_iter().map(|v| v.redis_value(buffer)).collect())
+            }
+            RedisBufSplit::Double(d) => RedisValueRef::Double(d),
+            RedisBufSplit::Boolean(b) => RedisValueRef::Boolean(b),
+            RedisBufSplit::Null => RedisValueRef::Null,
+            RedisBufSplit::BigNumber(bfs) => RedisValueRef::BigNumber(bfs.as_bytes(buffer)),
+            RedisBufSplit::VerbatimString(format, bfs) => {
+                RedisValueRef::VerbatimString(format.as_bytes(buffer), bfs.as_bytes(buffer))
+            }
+            RedisBufSplit::Attribute(pairs) => RedisValueRef::Attribute(pairs_value(pairs, buffer)),
+            RedisBufSplit::Push(values) => {
+                RedisValueRef::Push(values.into_iter().map(|v| v.redis_value(buffer)).collect())
+            }
         }
     }
 }
 
-pub fn encode_bulk_string_from_array(input: Vec<&str>) -> String {
-    let mut res = String::new();
-    // Add details about the string
-    res.push_str(format!("*{}\r\n", input.len()).as_str());
+fn pairs_value(
+    pairs: Vec<(RedisBufSplit, RedisBufSplit)>,
+    buffer: &Bytes,
+) -> Vec<(RedisValueRef, RedisValueRef)> {
+    pairs
+        .into_iter()
+        .map(|(k, v)| (k.redis_value(buffer), v.redis_value(buffer)))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    for s in input {
-        let encoded = encode_bulk_string(s);
-        res.push_str(encoded.as_str());
+    fn bulk(s: &str) -> RedisValueRef {
+        RedisValueRef::bulk_string(s.to_string())
     }
-    res
-}
 
-pub fn encode_bulk_string(input: &str) -> String {
-    let result = format!("${}\r\n{}\r\n", input.len(), input);
+    fn encode(value: &RedisValueRef, protocol: Protocol) -> BytesMut {
+        let mut parser = RedisParser::new();
+        parser.set_protocol(protocol);
+        let mut buffer = BytesMut::new();
+        parser.encode(value.clone(), &mut buffer).unwrap();
+        buffer
+    }
 
-    result
-}
+    fn decode_all(buffer: &mut BytesMut) -> Vec<RedisValueRef> {
+        let mut parser = RedisParser::new();
+        let mut values = Vec::new();
+        while let Some(value) = parser.decode(buffer).unwrap() {
+            values.push(value);
+        }
+        values
+    }
+
+    fn round_trip(value: RedisValueRef, protocol: Protocol) {
+        let mut buffer = encode(&value, protocol);
+        assert_eq!(decode_all(&mut buffer), vec![value]);
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn round_trips_resp2_values() {
+        for value in [
+            RedisValueRef::ok(),
+            bulk("hello"),
+            RedisValueRef::bulk_string(Bytes::from_static(b"bin\r\n\0ary")),
+            bulk(""),
+            RedisValueRef::error("ERR something went wrong"),
+            RedisValueRef::Int(0),
+            RedisValueRef::Int(i64::MIN),
+            RedisValueRef::Int(i64::MAX),
+            RedisValueRef::NullArray,
+            RedisValueRef::NullBulkString,
+            RedisValueRef::Array(vec![]),
+            RedisValueRef::Array(vec![
+                bulk("a"),
+                RedisValueRef::Int(-3),
+                RedisValueRef::Array(vec![RedisValueRef::NullBulkString, bulk("b")]),
+                RedisValueRef::NullArray,
+            ]),
+        ] {
+            round_trip(value, Protocol::Resp2);
+        }
+    }
+
+    #[test]
+    fn round_trips_resp3_values() {
+        for value in [
+            RedisValueRef::Map(vec![
+                (bulk("key"), RedisValueRef::Int(1)),
+                (bulk("nested"), RedisValueRef::Array(vec![bulk("x")])),
+            ]),
+            RedisValueRef::Set(vec![bulk("a"), bulk("b")]),
+            RedisValueRef::Double(1.5),
+            RedisValueRef::Double(-0.25),
+            RedisValueRef::Double(f64::INFINITY),
+            RedisValueRef::Double(f64::NEG_INFINITY),
+            RedisValueRef::Boolean(true),
+            RedisValueRef::Boolean(false),
+            RedisValueRef::Null,
+            RedisValueRef::BigNumber(Bytes::from_static(
+                b"3492890328409238509324850943850943825024385",
+            )),
+            RedisValueRef::VerbatimString(
+                Bytes::from_static(b"txt"),
+                Bytes::from_static(b"Some string"),
+            ),
+            RedisValueRef::Attribute(vec![(bulk("ttl"), RedisValueRef::Int(3600))]),
+            RedisValueRef::Push(vec![bulk("message"), bulk("channel"), bulk("hi")]),
+        ] {
+            round_trip(value, Protocol::Resp3);
+        }
+
+        let mut buffer = encode(&RedisValueRef::Double(f64::NAN), Protocol::Resp3);
+        match decode_all(&mut buffer).as_slice() {
+            [RedisValueRef::Double(d)] => assert!(d.is_nan()),
+            other => panic!("unexpected {:?}", other),
+        }
+    }
+
+    #[test]
+    fn downgrades_resp3_values_for_resp2() {
+        let cases = [
+            (RedisValueRef::Null, &b"$-1\r\n"[..]),
+            (RedisValueRef::NullArray, b"*-1\r\n"),
+            (RedisValueRef::Boolean(true), b":1\r\n"),
+            (RedisValueRef::Double(2.5), b"$3\r\n2.5\r\n"),
+            (
+                RedisValueRef::Map(vec![(bulk("a"), RedisValueRef::Int(1))]),
+                b"*2\r\n$1\r\na\r\n:1\r\n",
+            ),
+            (RedisValueRef::Set(vec![bulk("a")]), b"*1\r\n$1\r\na\r\n"),
+            (
+                RedisValueRef::VerbatimString(
+                    Bytes::from_static(b"txt"),
+                    Bytes::from_static(b"hi"),
+                ),
+                b"$2\r\nhi\r\n",
+            ),
+            (RedisValueRef::Attribute(vec![(bulk("a"), bulk("b"))]), b""),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(
+                &encode(&value, Protocol::Resp2)[..],
+                expected,
+                "{:?}",
+                value
+            );
+        }
+        assert_eq!(
+            &encode(&RedisValueRef::NullArray, Protocol::Resp3)[..],
+            b"_\r\n"
+        );
+        assert_eq!(
+            &encode(&RedisValueRef::NullBulkString, Protocol::Resp3)[..],
+            b"_\r\n"
+        );
+    }
 
-pub fn encode_simple_string(input: &str) -> String {
-    format!("+{}\r\n", input)
+    #[test]
+    fn keeps_errors_on_one_line() {
+        let error = RedisValueRef::error("ERR unknown command 'foo\r\n+ok'");
+        assert_eq!(
+            &encode(&error, Protocol::Resp2)[..],
+            b"-ERR unknown command 'foo  +ok'\r\n"
+        );
+    }
+
+    #[test]
+    fn formats_doubles_like_redis() {
+        let cases = [
+            (0.0, "0"),
+            (-0.0, "-0"),
+            (3.0, "3"),
+            (-42.0, "-42"),
+            (1.5, "1.5"),
+            (0.1, "0.1"),
+            (-2.5e-3, "-0.0025"),
+            (1e-4, "0.0001"),
+            (1e-5, "1e-05"),
+            (1.2345e-7, "1.2345e-07"),
+            (123456.789, "123456.789"),
+            (1e16 + 0.5, "10000000000000000"),
+            (4611686018427387904.0, "4611686018427387904"),
+            (9223372036854775808.0, "9.223372036854776e+18"),
+            (1e20, "1e+20"),
+            (1e21, "1e+21"),
+            (-1.5e300, "-1.5e+300"),
+            (5e-324, "5e-324"),
+            (f64::INFINITY, "inf"),
+            (f64::NEG_INFINITY, "-inf"),
+            (f64::NAN, "nan"),
+        ];
+        for (value, expected) in cases {
+            assert_eq!(format_double(value), expected, "{:e}", value);
+        }
+    }
+
+    #[test]
+    fn waits_for_partial_frames() {
+        let frame = b"*2\r\n$4\r\nECHO\r\n$11\r\nhello world\r\n";
+        let mut parser = RedisParser::new();
+        let mut buffer = BytesMut::new();
+        for (i, byte) in frame.iter().enumerate() {
+            buffer.put_u8(*byte);
+            let decoded = parser.decode(&mut buffer).unwrap();
+            if i + 1 < frame.len() {
+                assert_eq!(decoded, None, "decoded after {} bytes", i + 1);
+                assert_eq!(buffer.len(), i + 1);
+            } else {
+                assert_eq!(
+                    decoded,
+                    Some(RedisValueRef::Array(vec![
+                        bulk("ECHO"),
+                        bulk("hello world")
+                    ]))
+                );
+            }
+        }
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn decodes_pipelined_frames_in_order() {
+        let mut buffer = BytesMut::from(
+            &b"*1\r\n$4\r\nPING\r\n*2\r\n$3\r\nGET\r\n$1\r\nk\r\nPING\r\n*3\r\n$3\r\nSET\r\n$1\r\nk"[..],
+        );
+        let values = decode_all(&mut buffer);
+        assert_eq!(
+            values,
+            vec![
+                RedisValueRef::Array(vec![bulk("PING")]),
+                RedisValueRef::Array(vec![bulk("GET"), bulk("k")]),
+                RedisValueRef::Array(vec![bulk("PING")]),
+            ]
+        );
+        // The incomplete SET stays buffered until the rest arrives
+        assert_eq!(&buffer[..], b"*3\r\n$3\r\nSET\r\n$1\r\nk");
+        buffer.extend_from_slice(b"\r\n$1\r\nv\r\n");
+        assert_eq!(
+            decode_all(&mut buffer),
+            vec![RedisValueRef::Array(vec![
+                bulk("SET"),
+                bulk("k"),
+                bulk("v")
+            ])]
+        );
+    }
+
+    #[test]
+    fn decodes_inline_commands() {
+        let mut buffer = BytesMut::from(&b"\r\nSET key \"a \\x41\\n\" 'it\\'s'\r\nPING\n"[..]);
+        assert_eq!(
+            decode_all(&mut buffer),
+            vec![
+                RedisValueRef::Array(vec![bulk("SET"), bulk("key"), bulk("a A\n"), bulk("it's")]),
+                RedisValueRef::Array(vec![bulk("PING")]),
+            ]
+        );
+
+        let mut buffer = BytesMut::from(&b"SET \"unbalanced\r\n"[..]);
+        assert!(matches!(
+            RedisParser::new().decode(&mut buffer),
+            Err(RESPError::UnbalancedQuotes)
+        ));
+    }
+
+    fn decode_error(input: &[u8]) -> RESPError {
+        let mut buffer = BytesMut::from(input);
+        match RedisParser::new().decode(&mut buffer) {
+            Err(e) => e,
+            Ok(value) => panic!("decoded {:?} from {:?}", value, input),
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        assert!(matches!(
+            decode_error(b"$4\r\nPINGxx"),
+            RESPError::MissingCrlf
+        ));
+        assert!(matches!(
+            decode_error(b"$999999999999\r\n"),
+            RESPError::BadBulkStringSize(_)
+        ));
+        assert!(matches!(
+            decode_error(b"$-2\r\n"),
+            RESPError::BadBulkStringSize(_)
+        ));
+        assert!(matches!(
+            decode_error(b"*2000000\r\n"),
+            RESPError::BadArraySize(_)
+        ));
+        assert!(matches!(
+            decode_error(b"*x\r\n"),
+            RESPError::IntParseFailure
+        ));
+        assert!(matches!(decode_error(b"#x\r\n"), RESPError::BadBoolean));
+        assert!(matches!(decode_error(b"+OK\rX"), RESPError::MissingCrlf));
+        assert!(matches!(
+            decode_error(b"*1\r\n:12\r3\r\n"),
+            RESPError::MissingCrlf
+        ));
+    }
+
+    #[test]
+    fn bounds_unterminated_lines() {
+        let mut parser = RedisParser::new();
+        for start in [&b"+"[..], b"-", b":", b",", b"(", b"*"] {
+            // A line may still end once more bytes arrive
+            let mut buffer = BytesMut::from(start);
+            buffer.extend_from_slice(&b"1".repeat(INLINE_MAX_SIZE));
+            assert_eq!(parser.decode(&mut buffer).unwrap(), None);
+            // But not once it is longer than any line accepted
+            buffer.extend_from_slice(b"1");
+            assert!(matches!(
+                parser.decode(&mut buffer),
+                Err(RESPError::LineTooLong)
+            ));
+        }
+    }
+
+    #[test]
+    fn rejects_deep_nesting() {
+        let mut frame = b"*1\r\n".repeat(200_000);
+        frame.extend_from_slice(b"$4\r\nPING\r\n");
+        assert!(matches!(decode_error(&frame), RESPError::TooDeep));
+
+        // Moderate nesting, as in replies, is still fine
+        let mut buffer = BytesMut::from(&b"*1\r\n*1\r\n*1\r\n:1\r\n"[..]);
+        assert_eq!(
+            decode_all(&mut buffer),
+            vec![RedisValueRef::Array(vec![RedisValueRef::Array(vec![
+                RedisValueRef::Array(vec![RedisValueRef::Int(1)])
+            ])])]
+        );
+    }
 }
@@ -1,48 +1,55 @@
-use std::{
-    io::{Read, Write},
-    net::TcpStream,
-};
+use bytes::Bytes;
+use futures::{SinkExt, StreamExt};
+use tokio::net::TcpStream;
+use tokio_util::codec::Framed;
 
-use crate::encode_bulk_string_from_array;
+use crate::{RedisParser, RedisValueRef};
 
 pub async fn run_replica(host: &String, port: &String) {
-    let mut stream = TcpStream::connect(format!("{}:{}", &host, &port)).unwrap();
-    let buffer = [0; 1024];
+    let stream = TcpStream::connect(format!("{}:{}", &host, &port))
+        .await
+        .unwrap();
+    let mut stream = Framed::new(stream, RedisParser::new());
 
     // Connect replica to the master server
-    handle_handshake(&mut stream, buffer).await;
+    handle_handshake(&mut stream).await;
 }
 
-async fn handle_handshake(stream: &mut TcpStream, mut buffer: [u8; 1024]) {
+async fn handle_handshake(stream: &mut Framed<TcpStream, RedisParser>) {
     // Send ping response
-    let ping_response = encode_bulk_string_from_array(vec!["ping"]);
     stream
-        .write_all(ping_response.as_bytes())
+        .send(command(&["ping"]))
+        .await
         .expect("failed to write to stream on replica");
-    stream.flush().unwrap();
-    let _res = stream.read(&mut buffer).unwrap();
+    let _res = stream.next().await;
 
     // Send first REPLCONF response
-    let repl_conf_1 = encode_bulk_string_from_array(vec!["REPLCONF", "listening-port", "6380"]);
     stream
-        .write_all(repl_conf_1.as_bytes())
+        .send(command(&["REPLCONF", "listening-port", "6380"]))
+        .await
         .expect("failed to write first REPLCONF response");
-    let _res = stream.read(&mut buffer).unwrap();
-    stream.flush().unwrap();
+    let _res = stream.next().await;
 
     // Send second REPLCONF response
-    let repl_conf_2 = encode_bulk_string_from_array(vec!["REPLCONF", "capa", "psync2"]);
     stream
-        .write_all(repl_conf_2.as_bytes())
+        .send(command(&["REPLCONF", "capa", "psync2"]))
+        .await
         .expect("failed to write second REPLCONF response");
-    stream.flush().unwrap();
-    let _res = stream.read(&mut buffer).unwrap();
+    let _res = stream.next().await;
 
     // Send PSYNC command
-    let psync = encode_bulk_string_from_array(vec!["PSYNC", "?", "-1"]);
     stream
-        .write_all(psync.as_bytes())
+        .send(command(&["PSYNC", "?", "-1"]))
+        .await
         .expect("failed to send psync response");
-    stream.flush().unwrap();
-    let _res = stream.read(&mut buffer).unwrap();
+    let _res = stream.next().await;
+}
+
+/// Build a command as an array of bulk strings
+fn command(args: &[&str]) -> RedisValueRef {
+    RedisValueRef::Array(
+        args.iter()
+            .map(|arg| RedisValueRef::String(Bytes::copy_from_slice(arg.as_bytes())))
+            .collect(),
+    )
 }
@@ -0,0 +1,109 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+use rand::{seq::index, Rng};
+
+use crate::ScanIndex;
+
+/// Members of a set in a vector with their positions in a hash index, for
+/// O(1) random picks, plus the members ordered by scan hash so an SSCAN call
+/// only walks the members it returns
+#[derive(Clone, Debug, Default)]
+pub struct Set {
+    members: Vec<Bytes>,
+    positions: HashMap<Bytes, usize>,
+    scan_index: ScanIndex,
+}
+
+impl Set {
+    pub fn new() -> Set {
+        Set::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.members.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.members.is_empty()
+    }
+
+    pub fn contains(&self, member: &[u8]) -> bool {
+        self.positions.contains_key(member)
+    }
+
+    /// Add a member, false if it was already there
+    pub fn insert(&mut self, member: Bytes) -> bool {
+        if self.positions.contains_key(&member) {
+            return false;
+        }
+        self.positions.insert(member.clone(), self.members.len());
+        self.members.push(member.clone());
+        self.scan_index.insert(member);
+        true
+    }
+
+    /// Remove a member, false if it was not there
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        let Some(pos) = self.positions.remove(member) else {
+            return false;
+        };
+        let member = self.members.swap_remove(pos);
+        // The last member moved into the hole
+        if let Some(moved) = self.members.get(pos) {
+            self.positions.insert(moved.clone(), pos);
+        }
+        self.scan_index.remove(member);
+        true
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Bytes> {
+        self.members.iter()
+    }
+
+    /// A random member, None if the set is empty
+    pub fn random(&self, rng: &mut impl Rng) -> Option<&Bytes> {
+        if self.members.is_empty() {
+            return None;
+        }
+        Some(&self.members[rng.gen_range(0..self.members.len())])
+    }
+
+    /// Up to `count` distinct random members
+    pub fn random_distinct(&self, rng: &mut impl Rng, count: usize) -> Vec<&Bytes> {
+        index::sample(rng, self.members.len(), count.min(self.members.len()))
+            .into_iter()
+            .map(|i| &self.members[i])
+            .collect()
+    }
+
+    /// Remove and return a random member, None if the set is empty
+    pub fn pop_random(&mut self, rng: &mut impl Rng) -> Option<Bytes> {
+        let member = self.random(rng)?.clone();
+        self.remove(&member);
+        Some(member)
+    }
+
+    /// Walk the members from an SSCAN cursor, see `ScanIndex::scan`
+    pub fn scan(&self, cursor: u64, count: usize) -> (u64, Vec<&Bytes>) {
+        self.scan_index.scan(cursor, count, |_| true)
+    }
+}
+
+impl PartialEq for Set {
+    /// Sets are equal when they hold the same members, in whatever order
+    fn eq(&self, other: &Self) -> bool {
+        self.positions.len() == other.positions.len()
+            && self.members.iter().all(|member| other.contains(member))
+    }
+}
+
+impl FromIterator<Bytes> for Set {
+    fn from_iter<I: IntoIterator<Item = Bytes>>(members: I) -> Set {
+        let mut set = Set::new();
+        for member in members {
+            set.insert(member);
+        }
+        set
+    }
+}
@@ -0,0 +1,423 @@
+use std::collections::HashSet;
+
+use crate::{
+    arg_bytes, arg_int, check_arity, parse_cursor, scan_reply, CommandError, CommandResult,
+    Database, RedisValueRef, ScanOptions, Set, SetObject, StoredValue,
+};
+use bytes::Bytes;
+
+/// SADD key member [member ...]
+pub fn handle_sadd(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("sadd", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let mut members = Vec::with_capacity(args.len() - 1);
+    for member in &args[1..] {
+        members.push(arg_bytes(member)?.clone());
+    }
+
+    let set = set_or_create(db, key)?;
+    let added = members
+        .into_iter()
+        .filter(|member| set.insert(member.clone()))
+        .count();
+    if added > 0 {
+        db.signal_modified(key);
+    }
+    Ok(RedisValueRef::Int(added as i64))
+}
+
+/// SREM key member [member ...]
+pub fn handle_srem(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("srem", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let Some(set) = set_mut(db, key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let mut removed = 0;
+    for member in &args[1..] {
+        if set.remove(arg_bytes(member)?) {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(RedisValueRef::Int(removed))
+}
+
+/// SMEMBERS key, a set reply in RESP3
+pub fn handle_smembers(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("smembers", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let members = match set(db, key)? {
+        Some(set) => set.iter().cloned().map(RedisValueRef::String).collect(),
+        None => vec![],
+    };
+    Ok(RedisValueRef::Set(members))
+}
+
+/// SISMEMBER key member
+pub fn handle_sismember(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("sismember", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let member = arg_bytes(&args[1])?;
+    let found = set(db, key)?.is_some_and(|set| set.contains(member));
+    Ok(RedisValueRef::Int(found as i64))
+}
+
+/// SCARD key
+pub fn handle_scard(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("scard", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let len = set(db, key)?.map_or(0, |set| set.len());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// SPOP key [count]
+pub fn handle_spop(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("spop", args, 1, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let count = match args.get(1) {
+        Some(count) => {
+            let count = arg_int(count)?;
+            if count < 0 {
+                return Err(CommandError::NotPositive);
+            }
+            Some(count as usize)
+        }
+        None => None,
+    };
+
+    let Some(set) = set_mut(db, key)? else {
+        return Ok(match count {
+            Some(_) => RedisValueRef::Set(vec![]),
+            None => RedisValueRef::NullBulkString,
+        });
+    };
+    let mut rng = rand::thread_rng();
+    let popped: Vec<Bytes> = (0..count.unwrap_or(1).min(set.len()))
+        .filter_map(|_| set.pop_random(&mut rng))
+        .collect();
+    if !popped.is_empty() {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+
+    let mut popped = popped.into_iter().map(RedisValueRef::String);
+    match count {
+        Some(_) => Ok(RedisValueRef::Set(popped.collect())),
+        None => Ok(popped.next().unwrap_or(RedisValueRef::NullBulkString)),
+    }
+}
+
+/// SRANDMEMBER key [count]. A positive count returns distinct members, a
+/// negative one may return the same member several times.
+pub fn handle_srandmember(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("srandmember", args, 1, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let count = args.get(1).map(arg_int).transpose()?;
+
+    let Some(set) = set(db, key)? else {
+        return Ok(match count {
+            Some(_) => RedisValueRef::Array(vec![]),
+            None => RedisValueRef::NullBulkString,
+        });
+    };
+    let mut rng = rand::thread_rng();
+    let members = match count {
+        None => {
+            let member = set.random(&mut rng).cloned();
+            return Ok(member.map_or(RedisValueRef::NullBulkString, RedisValueRef::String));
+        }
+        Some(count) if count >= 0 => set.random_distinct(&mut rng, count as usize),
+        Some(count) => (0..count.unsigned_abs())
+            .filter_map(|_| set.random(&mut rng))
+            .collect(),
+    };
+    Ok(RedisValueRef::Array(
+        members
+            .into_iter()
+            .cloned()
+            .map(RedisValueRef::String)
+            .collect(),
+    ))
+}
+
+/// SINTER, SUNION and SDIFF key [key ...]
+pub fn handle_setop(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 1, None)?;
+    let result = combine(name, args, db)?;
+    Ok(RedisValueRef::Set(
+        result.into_iter().map(RedisValueRef::String).collect(),
+    ))
+}
+
+/// SINTERSTORE, SUNIONSTORE and SDIFFSTORE destination key [key ...],
+/// returns the size of the stored set
+pub fn handle_setop_store(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 2, None)?;
+    let destination = arg_bytes(&args[0])?;
+    let result = combine(name, &args[1..], db)?;
+
+    let len = result.len();
+    // An empty result deletes the destination, like every empty aggregate
+    if result.is_empty() {
+        db.remove(destination);
+    } else {
+        db.insert(
+            destination.clone(),
+            SetObject::new(StoredValue::Set(result.into_iter().collect()), None),
+        );
+    }
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// SSCAN key cursor [MATCH pattern] [COUNT count]
+pub fn handle_sscan(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("sscan", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let cursor = parse_cursor(&args[1])?;
+    let options = ScanOptions::parse(&args[2..], false)?;
+
+    let Some(set) = set(db, key)? else {
+        return Ok(scan_reply(0, vec![]));
+    };
+    let (next, members) = set.scan(cursor, options.count);
+    let members = members
+        .into_iter()
+        .filter(|member| options.matches(member))
+        .map(|member| RedisValueRef::String(member.clone()))
+        .collect();
+    Ok(scan_reply(next, members))
+}
+
+/// Intersect, union or difference the sets at `keys`, depending on whether
+/// `name` starts with SINTER, SUNION or SDIFF. Missing keys are empty sets.
+fn combine(
+    name: &str,
+    keys: &[RedisValueRef],
+    db: &mut Database,
+) -> Result<HashSet<Bytes>, CommandError> {
+    let keys = keys.iter().map(arg_bytes).collect::<Result<Vec<_>, _>>()?;
+    // Check every key up front so a later WRONGTYPE fails the whole command
+    for key in &keys {
+        set(db, key)?;
+    }
+    // Borrow the sets, only members of the result are cloned
+    let db = &*db;
+    let sets: Vec<Option<&Set>> = keys
+        .iter()
+        .map(|key| match db.peek(key) {
+            Some(SetObject {
+                value: StoredValue::Set(set),
+                ..
+            }) => Some(set),
+            _ => None,
+        })
+        .collect();
+
+    if name.starts_with("sinter") {
+        // A missing key is an empty set, which empties the intersection
+        let Some(mut sets) = sets.into_iter().collect::<Option<Vec<_>>>() else {
+            return Ok(HashSet::new());
+        };
+        // Walk the smallest set and probe the others
+        sets.sort_by_key(|set| set.len());
+        let (smallest, others) = sets.split_first().expect("at least one key");
+        Ok(smallest
+            .iter()
+            .filter(|member| others.iter().all(|set| set.contains(member)))
+            .cloned()
+            .collect())
+    } else if name.starts_with("sunion") {
+        let mut union = HashSet::new();
+        for set in sets.into_iter().flatten() {
+            union.extend(set.iter().cloned());
+        }
+        Ok(union)
+    } else {
+        let (first, others) = sets.split_first().expect("at least one key");
+        let Some(first) = first else {
+            return Ok(HashSet::new());
+        };
+        Ok(first
+            .iter()
+            .filter(|member| !others.iter().flatten().any(|set| set.contains(member)))
+            .cloned()
+            .collect())
+    }
+}
+
+/// Get the set held at a key, failing if the key holds another type
+pub fn set<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a Set>, CommandError> {
+    match db.get(key) {
+        Some(SetObject {
+            value: StoredValue::Set(set),
+            ..
+        }) => Ok(Some(set)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the set held at a key for modification. Callers must report changes
+/// with `Database::signal_modified`, and call `Database::remove_if_empty` if
+/// they may empty the set.
+pub fn set_mut<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a mut Set>, CommandError> {
+    match db.get_mut(key) {
+        Some(SetObject {
+            value: StoredValue::Set(set),
+            ..
+        }) => Ok(Some(set)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the set held at a key for modification, creating an empty one if the
+/// key is missing. Callers must report changes with `Database::signal_modified`.
+fn set_or_create<'a>(db: &'a mut Database, key: &Bytes) -> Result<&'a mut Set, CommandError> {
+    match &mut db
+        .get_or_insert_with(key, || StoredValue::Set(Set::new()))
+        .value
+    {
+        StoredValue::Set(set) => Ok(set),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args;
+
+    #[test]
+    fn sscan_cursor_survives_writes() {
+        let mut db = Database::new();
+        for i in 0..50 {
+            handle_sadd(&args(&format!("s member:{}", i)), &mut db).unwrap();
+        }
+
+        // Members present for the whole iteration are returned exactly once,
+        // however many members come and go in between calls
+        let mut seen = Vec::new();
+        let mut cursor = "0".to_string();
+        for round in 0.. {
+            let reply = handle_sscan(&args(&format!("s {} COUNT 3", cursor)), &mut db).unwrap();
+            let RedisValueRef::Array(reply) = reply else {
+                panic!("unexpected {:?}", reply);
+            };
+            let [RedisValueRef::String(next), RedisValueRef::Array(members)] = &reply[..] else {
+                panic!("unexpected {:?}", reply);
+            };
+            seen.extend(
+                members
+                    .iter()
+                    .map(|member| arg_bytes(member).unwrap().clone()),
+            );
+            handle_sadd(&args(&format!("s new:{}", round)), &mut db).unwrap();
+            handle_srem(
+                &args(&format!("s new:{} member:{}", round - 1, round + 40)),
+                &mut db,
+            )
+            .unwrap();
+            cursor = String::from_utf8(next.to_vec()).unwrap();
+            if cursor == "0" {
+                break;
+            }
+        }
+        let mut expected = (0..40)
+            .map(|i| Bytes::from(format!("member:{}", i)))
+            .collect::<Vec<_>>();
+        expected.sort();
+        // Members added or removed meanwhile may or may not be returned
+        seen.retain(|member| expected.contains(member));
+        seen.sort();
+        assert_eq!(seen, expected);
+    }
+
+    fn members(reply: CommandResult) -> Vec<Bytes> {
+        match reply.unwrap() {
+            RedisValueRef::Array(members) | RedisValueRef::Set(members) => members
+                .iter()
+                .map(|member| arg_bytes(member).unwrap().clone())
+                .collect(),
+            reply => panic!("unexpected {:?}", reply),
+        }
+    }
+
+    #[test]
+    fn spop_and_srandmember() {
+        let mut db = Database::new();
+        handle_sadd(&args("s a b c d e"), &mut db).unwrap();
+        let all = ["a", "b", "c", "d", "e"].map(Bytes::from);
+
+        let mut distinct = members(handle_srandmember(&args("s 3"), &mut db));
+        distinct.sort();
+        distinct.dedup();
+        assert_eq!(distinct.len(), 3);
+        assert_eq!(members(handle_srandmember(&args("s 10"), &mut db)).len(), 5);
+        let repeated = members(handle_srandmember(&args("s -20"), &mut db));
+        assert_eq!(repeated.len(), 20);
+        assert!(repeated.iter().all(|member| all.contains(member)));
+
+        let Ok(RedisValueRef::String(popped)) = handle_spop(&args("s"), &mut db) else {
+            panic!("SPOP replies a member");
+        };
+        assert_eq!(
+            handle_sismember(
+                &args(&format!("s {}", String::from_utf8_lossy(&popped))),
+                &mut db
+            ),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert_eq!(members(handle_spop(&args("s 2"), &mut db)).len(), 2);
+        assert_eq!(handle_scard(&args("s"), &mut db), Ok(RedisValueRef::Int(2)));
+        // Popping everything removes the key
+        assert_eq!(members(handle_spop(&args("s 5"), &mut db)).len(), 2);
+        assert!(!db.exists(b"s"));
+        assert_eq!(
+            handle_spop(&args("s"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+        assert_eq!(
+            handle_srandmember(&args("s"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+        assert_eq!(
+            handle_spop(&args("s -1"), &mut db),
+            Err(CommandError::NotPositive)
+        );
+    }
+
+    #[test]
+    fn removals_keep_positions_consistent() {
+        let mut set = (0..20)
+            .map(|i| Bytes::from(format!("m{}", i)))
+            .collect::<Set>();
+        for i in (0..20).step_by(3) {
+            assert!(set.remove(format!("m{}", i).as_bytes()));
+        }
+        assert!(!set.remove(b"m0"));
+        assert_eq!(set.len(), 13);
+        for i in 0..20 {
+            assert_eq!(set.contains(format!("m{}", i).as_bytes()), i % 3 != 0);
+        }
+
+        let mut rng = rand::thread_rng();
+        while let Some(member) = set.pop_random(&mut rng) {
+            assert!(!set.contains(&member));
+        }
+        assert!(set.is_empty());
+        assert_eq!(set.scan(0, 100), (0, vec![]));
+    }
+}
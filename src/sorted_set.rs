@@ -0,0 +1,596 @@
+use std::{cmp::Ordering, collections::HashMap};
+
+use bytes::Bytes;
+use rand::Rng;
+
+/// Levels a skiplist node can have, enough for 2^64 elements at p = 1/4
+const MAX_LEVEL: usize = 32;
+/// Chance of a node reaching the next level, as in Redis
+const LEVEL_PROBABILITY: f64 = 0.25;
+/// Arena slot of the header node, which holds no element
+const HEADER: usize = 0;
+
+/// Members ordered by score and then lexicographically, backed by a skiplist
+/// with spans for O(log n) rank lookups plus a hash index for O(1) scores.
+/// Nodes live in an arena and link to each other by index.
+#[derive(Clone, Debug)]
+pub struct SortedSet {
+    nodes: Vec<Node>,
+    // Arena slots of deleted nodes, reused by later inserts
+    free: Vec<usize>,
+    level: usize,
+    tail: Option<usize>,
+    scores: HashMap<Bytes, f64>,
+}
+
+#[derive(Clone, Debug)]
+struct Node {
+    member: Bytes,
+    score: f64,
+    backward: Option<usize>,
+    levels: Vec<Link>,
+}
+
+/// A forward pointer and the number of level 0 nodes it skips over
+#[derive(Clone, Copy, Debug, Default)]
+struct Link {
+    forward: Option<usize>,
+    span: usize,
+}
+
+/// One end of a score range
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ScoreBound {
+    Inclusive(f64),
+    Exclusive(f64),
+}
+
+impl ScoreBound {
+    /// Whether a score passes this bound used as the minimum
+    pub fn satisfies_min(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(min) => score >= min,
+            ScoreBound::Exclusive(min) => score > min,
+        }
+    }
+
+    /// Whether a score passes this bound used as the maximum
+    pub fn satisfies_max(&self, score: f64) -> bool {
+        match *self {
+            ScoreBound::Inclusive(max) => score <= max,
+            ScoreBound::Exclusive(max) => score < max,
+        }
+    }
+}
+
+/// One end of a lexicographic range
+#[derive(Clone, Debug, PartialEq)]
+pub enum LexBound {
+    Inclusive(Bytes),
+    Exclusive(Bytes),
+    NegativeInfinity,
+    PositiveInfinity,
+}
+
+impl LexBound {
+    /// Whether a member passes this bound used as the minimum
+    pub fn satisfies_min(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(min) => member >= min.as_ref(),
+            LexBound::Exclusive(min) => member > min.as_ref(),
+            LexBound::NegativeInfinity => true,
+            LexBound::PositiveInfinity => false,
+        }
+    }
+
+    /// Whether a member passes this bound used as the maximum
+    pub fn satisfies_max(&self, member: &[u8]) -> bool {
+        match self {
+            LexBound::Inclusive(max) => member <= max.as_ref(),
+            LexBound::Exclusive(max) => member < max.as_ref(),
+            LexBound::NegativeInfinity => false,
+            LexBound::PositiveInfinity => true,
+        }
+    }
+}
+
+impl SortedSet {
+    pub fn new() -> SortedSet {
+        let header = Node {
+            member: Bytes::new(),
+            score: 0.0,
+            backward: None,
+            levels: vec![Link::default(); MAX_LEVEL],
+        };
+        SortedSet {
+            nodes: vec![header],
+            free: Vec::new(),
+            level: 1,
+            tail: None,
+            scores: HashMap::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.scores.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.scores.is_empty()
+    }
+
+    pub fn score(&self, member: &[u8]) -> Option<f64> {
+        self.scores.get(member).copied()
+    }
+
+    /// Add a member or change its score, returns whether it was added
+    pub fn insert(&mut self, member: Bytes, score: f64) -> bool {
+        match self.scores.get(&member).copied() {
+            Some(old) if old == score => false,
+            Some(old) => {
+                // Out of the index while relinking, so `len` counts the
+                // linked nodes when `link` sets spans for new levels
+                self.scores.remove(&member);
+                self.unlink(old, &member);
+                self.link(member.clone(), score);
+                self.scores.insert(member, score);
+                false
+            }
+            None => {
+                self.link(member.clone(), score);
+                self.scores.insert(member, score);
+                true
+            }
+        }
+    }
+
+    /// Remove a member, returns whether it was present
+    pub fn remove(&mut self, member: &[u8]) -> bool {
+        match self.scores.remove(member) {
+            Some(score) => {
+                self.unlink(score, member);
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Zero based position of a member in ascending order
+    pub fn rank(&self, member: &[u8]) -> Option<usize> {
+        let score = self.score(member)?;
+        let mut rank = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if self.compare(next, score, member) == Ordering::Greater {
+                    break;
+                }
+                rank += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if x != HEADER && self.nodes[x].member == member {
+                return Some(rank - 1);
+            }
+        }
+        None
+    }
+
+    /// Members and scores in ascending order from zero based `start` to
+    /// `end`, both inclusive and already clamped to the set
+    pub fn range_by_rank(&self, start: usize, end: usize) -> Vec<(Bytes, f64)> {
+        let len = end + 1 - start;
+        let mut elements = Vec::with_capacity(len);
+        let mut node = self.by_rank(start);
+        while let Some(x) = node {
+            if elements.len() == len {
+                break;
+            }
+            elements.push(self.entry(x));
+            node = self.nodes[x].levels[0].forward;
+        }
+        elements
+    }
+
+    /// Members with scores between `min` and `max`, skipping `offset` of
+    /// them and returning at most `limit`. `rev` walks from the top.
+    pub fn range_by_score(
+        &self,
+        min: ScoreBound,
+        max: ScoreBound,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Bytes, f64)> {
+        let start = match rev {
+            false => self.first_in_score_range(min),
+            true => self.last_in_score_range(max),
+        };
+        self.collect_range(start, rev, offset, limit, |node| {
+            min.satisfies_min(node.score) && max.satisfies_max(node.score)
+        })
+    }
+
+    /// Members between `min` and `max` lexicographically, assuming they all
+    /// share a score as Redis does
+    pub fn range_by_lex(
+        &self,
+        min: &LexBound,
+        max: &LexBound,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+    ) -> Vec<(Bytes, f64)> {
+        let start = match rev {
+            false => self.seek(|node| !min.satisfies_min(&node.member)),
+            true => self.seek_last(|node| max.satisfies_max(&node.member)),
+        };
+        self.collect_range(start, rev, offset, limit, |node| {
+            min.satisfies_min(&node.member) && max.satisfies_max(&node.member)
+        })
+    }
+
+    /// Number of members with scores between `min` and `max`, computed from
+    /// the ranks of both ends rather than by walking the range
+    pub fn count_in_score_range(&self, min: ScoreBound, max: ScoreBound) -> usize {
+        let (Some(first), Some(last)) = (
+            self.first_in_score_range(min),
+            self.last_in_score_range(max),
+        ) else {
+            return 0;
+        };
+        let first = self.node_rank(first);
+        let last = self.node_rank(last);
+        if last < first {
+            return 0;
+        }
+        last - first + 1
+    }
+
+    /// Remove and return the lowest or highest scoring member
+    pub fn pop(&mut self, highest: bool) -> Option<(Bytes, f64)> {
+        let x = match highest {
+            false => self.nodes[HEADER].levels[0].forward,
+            true => self.tail,
+        }?;
+        let (member, score) = self.entry(x);
+        self.remove(&member);
+        Some((member, score))
+    }
+
+    /// Every member and score, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&Bytes, f64)> {
+        self.scores.iter().map(|(member, score)| (member, *score))
+    }
+
+    fn entry(&self, x: usize) -> (Bytes, f64) {
+        (self.nodes[x].member.clone(), self.nodes[x].score)
+    }
+
+    /// Order of a node relative to a score and member
+    fn compare(&self, x: usize, score: f64, member: &[u8]) -> Ordering {
+        let node = &self.nodes[x];
+        node.score
+            .partial_cmp(&score)
+            .unwrap_or(Ordering::Equal)
+            .then_with(|| node.member.as_ref().cmp(member))
+    }
+
+    /// Walk from `start` collecting nodes while `in_range` holds
+    fn collect_range(
+        &self,
+        start: Option<usize>,
+        rev: bool,
+        offset: usize,
+        limit: Option<usize>,
+        in_range: impl Fn(&Node) -> bool,
+    ) -> Vec<(Bytes, f64)> {
+        let mut elements = Vec::new();
+        let mut skipped = 0;
+        let mut node = start;
+        while let Some(x) = node {
+            if limit.is_some_and(|limit| elements.len() >= limit) || !in_range(&self.nodes[x]) {
+                break;
+            }
+            if skipped < offset {
+                skipped += 1;
+            } else {
+                elements.push(self.entry(x));
+            }
+            node = match rev {
+                false => self.nodes[x].levels[0].forward,
+                true => self.nodes[x].backward,
+            };
+        }
+        elements
+    }
+
+    fn first_in_score_range(&self, min: ScoreBound) -> Option<usize> {
+        self.seek(|node| !min.satisfies_min(node.score))
+    }
+
+    fn last_in_score_range(&self, max: ScoreBound) -> Option<usize> {
+        self.seek_last(|node| max.satisfies_max(node.score))
+    }
+
+    /// First node for which `before` is false, `before` must hold for a
+    /// prefix of the list
+    fn seek(&self, before: impl Fn(&Node) -> bool) -> Option<usize> {
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if !before(&self.nodes[next]) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        self.nodes[x].levels[0].forward
+    }
+
+    /// Last node for which `within` is true, `within` must hold for a prefix
+    /// of the list
+    fn seek_last(&self, within: impl Fn(&Node) -> bool) -> Option<usize> {
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if !within(&self.nodes[next]) {
+                    break;
+                }
+                x = next;
+            }
+        }
+        (x != HEADER).then_some(x)
+    }
+
+    /// Node at a zero based rank
+    fn by_rank(&self, rank: usize) -> Option<usize> {
+        // Spans count from one, the header sits at rank zero
+        let rank = rank + 1;
+        let mut traversed = 0;
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if traversed + self.nodes[x].levels[i].span > rank {
+                    break;
+                }
+                traversed += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            if traversed == rank {
+                return Some(x);
+            }
+        }
+        None
+    }
+
+    fn node_rank(&self, x: usize) -> usize {
+        self.rank(&self.nodes[x].member)
+            .expect("linked nodes are indexed")
+    }
+
+    fn random_level() -> usize {
+        let mut rng = rand::thread_rng();
+        let mut level = 1;
+        while level < MAX_LEVEL && rng.gen_bool(LEVEL_PROBABILITY) {
+            level += 1;
+        }
+        level
+    }
+
+    /// Link a new node into the skiplist, the member must not be linked nor
+    /// counted by `len`
+    fn link(&mut self, member: Bytes, score: f64) {
+        let mut update = [HEADER; MAX_LEVEL];
+        let mut rank = [0; MAX_LEVEL];
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            rank[i] = if i == self.level - 1 { 0 } else { rank[i + 1] };
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if self.compare(next, score, &member) != Ordering::Less {
+                    break;
+                }
+                rank[i] += self.nodes[x].levels[i].span;
+                x = next;
+            }
+            update[i] = x;
+        }
+
+        let level = Self::random_level();
+        if level > self.level {
+            for i in self.level..level {
+                rank[i] = 0;
+                update[i] = HEADER;
+                self.nodes[HEADER].levels[i].span = self.len();
+            }
+            self.level = level;
+        }
+
+        let node = Node {
+            member,
+            score,
+            backward: (update[0] != HEADER).then_some(update[0]),
+            levels: vec![Link::default(); level],
+        };
+        let x = match self.free.pop() {
+            Some(slot) => {
+                self.nodes[slot] = node;
+                slot
+            }
+            None => {
+                self.nodes.push(node);
+                self.nodes.len() - 1
+            }
+        };
+
+        for i in 0..level {
+            let previous = self.nodes[update[i]].levels[i];
+            self.nodes[x].levels[i] = Link {
+                forward: previous.forward,
+                span: previous.span - (rank[0] - rank[i]),
+            };
+            self.nodes[update[i]].levels[i] = Link {
+                forward: Some(x),
+                span: rank[0] - rank[i] + 1,
+            };
+        }
+        // Levels above the new node now skip over one more node
+        for (i, &previous) in update.iter().enumerate().take(self.level).skip(level) {
+            self.nodes[previous].levels[i].span += 1;
+        }
+
+        match self.nodes[x].levels[0].forward {
+            Some(next) => self.nodes[next].backward = Some(x),
+            None => self.tail = Some(x),
+        }
+    }
+
+    /// Unlink the node holding a member with the given score
+    fn unlink(&mut self, score: f64, member: &[u8]) {
+        let mut update = [HEADER; MAX_LEVEL];
+        let mut x = HEADER;
+        for i in (0..self.level).rev() {
+            while let Some(next) = self.nodes[x].levels[i].forward {
+                if self.compare(next, score, member) != Ordering::Less {
+                    break;
+                }
+                x = next;
+            }
+            update[i] = x;
+        }
+        let Some(x) = self.nodes[x].levels[0].forward else {
+            return;
+        };
+        if self.compare(x, score, member) != Ordering::Equal {
+            return;
+        }
+
+        for (i, &previous) in update.iter().enumerate().take(self.level) {
+            if self.nodes[previous].levels[i].forward == Some(x) {
+                let removed = self.nodes[x].levels[i];
+                let link = &mut self.nodes[previous].levels[i];
+                link.span += removed.span;
+                link.span -= 1;
+                link.forward = removed.forward;
+            } else {
+                self.nodes[previous].levels[i].span -= 1;
+            }
+        }
+        match self.nodes[x].levels[0].forward {
+            Some(next) => self.nodes[next].backward = self.nodes[x].backward,
+            None => self.tail = self.nodes[x].backward,
+        }
+        while self.level > 1 && self.nodes[HEADER].levels[self.level - 1].forward.is_none() {
+            self.level -= 1;
+        }
+
+        // Release the member and keep the slot for the next insert
+        self.nodes[x].member = Bytes::new();
+        self.nodes[x].levels.clear();
+        self.free.push(x);
+    }
+}
+
+impl Default for SortedSet {
+    fn default() -> Self {
+        SortedSet::new()
+    }
+}
+
+impl PartialEq for SortedSet {
+    /// Sets are equal when they hold the same members and scores, whatever
+    /// the shape of their skiplists
+    fn eq(&self, other: &Self) -> bool {
+        self.scores == other.scores
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::{rngs::StdRng, SeedableRng};
+
+    /// Check the links of every level against the level 0 order: a span
+    /// counts the nodes up to the next one, or to the end of the list
+    fn check_invariants(zset: &SortedSet) {
+        let mut order = vec![HEADER];
+        let mut node = zset.nodes[HEADER].levels[0].forward;
+        while let Some(x) = node {
+            let previous = *order.last().unwrap();
+            assert_eq!(
+                zset.nodes[x].backward,
+                (previous != HEADER).then_some(previous)
+            );
+            if previous != HEADER {
+                let (score, member) = (zset.nodes[x].score, &zset.nodes[x].member);
+                assert_eq!(zset.compare(previous, score, member), Ordering::Less);
+            }
+            order.push(x);
+            node = zset.nodes[x].levels[0].forward;
+        }
+        assert_eq!(order.len() - 1, zset.len());
+        assert_eq!(zset.tail, (order.len() > 1).then(|| *order.last().unwrap()));
+        let position = |x: usize| order.iter().position(|&node| node == x).unwrap();
+
+        for i in 0..zset.level {
+            let mut x = HEADER;
+            loop {
+                let link = zset.nodes[x].levels[i];
+                match link.forward {
+                    Some(next) => {
+                        assert_eq!(link.span, position(next) - position(x), "level {}", i);
+                        x = next;
+                    }
+                    None => {
+                        assert_eq!(link.span, zset.len() - position(x), "level {}", i);
+                        break;
+                    }
+                }
+            }
+        }
+        for (rank, &x) in order[1..].iter().enumerate() {
+            assert_eq!(zset.rank(&zset.nodes[x].member), Some(rank));
+            assert_eq!(zset.by_rank(rank), Some(x));
+        }
+    }
+
+    #[test]
+    fn spans_and_ranks_survive_mixed_writes() {
+        let mut rng = StdRng::seed_from_u64(20);
+        let mut zset = SortedSet::new();
+        for _ in 0..2000 {
+            let member = Bytes::from(format!("m{}", rng.gen_range(0..60)));
+            match rng.gen_range(0..3) {
+                0 => {
+                    zset.remove(&member);
+                }
+                _ => {
+                    zset.insert(member, rng.gen_range(0..20) as f64);
+                }
+            }
+            check_invariants(&zset);
+        }
+    }
+
+    #[test]
+    fn updates_move_members() {
+        let mut zset = SortedSet::new();
+        for (member, score) in [("a", 1.0), ("b", 2.0), ("c", 3.0)] {
+            assert!(zset.insert(Bytes::from(member), score));
+        }
+        assert!(!zset.insert(Bytes::from("a"), 4.0));
+        assert!(!zset.insert(Bytes::from("c"), 3.0));
+        assert_eq!(zset.rank(b"a"), Some(2));
+        assert_eq!(zset.rank(b"b"), Some(0));
+        assert_eq!(
+            zset.range_by_rank(0, 2),
+            vec![
+                (Bytes::from("b"), 2.0),
+                (Bytes::from("c"), 3.0),
+                (Bytes::from("a"), 4.0)
+            ]
+        );
+        assert_eq!(zset.pop(true), Some((Bytes::from("a"), 4.0)));
+        assert_eq!(zset.len(), 2);
+        check_invariants(&zset);
+    }
+}
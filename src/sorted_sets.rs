@@ -0,0 +1,522 @@
+use std::collections::HashMap;
+
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, arg_float, arg_int, check_arity, clamp_range, is_keyword, parse_float, CommandError,
+    CommandResult, Database, LexBound, RedisValueRef, ScoreBound, SetObject, SortedSet,
+    StoredValue,
+};
+
+/// ZADD key [NX | XX] [GT | LT] [CH] [INCR] score member [score member ...]
+pub fn handle_zadd(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zadd", args, 3, None)?;
+    let key = arg_bytes(&args[0])?;
+
+    let (mut nx, mut xx, mut gt, mut lt, mut ch, mut incr) =
+        (false, false, false, false, false, false);
+    let mut next = 1;
+    for option in &args[1..] {
+        if is_keyword(option, "nx") {
+            nx = true;
+        } else if is_keyword(option, "xx") {
+            xx = true;
+        } else if is_keyword(option, "gt") {
+            gt = true;
+        } else if is_keyword(option, "lt") {
+            lt = true;
+        } else if is_keyword(option, "ch") {
+            ch = true;
+        } else if is_keyword(option, "incr") {
+            incr = true;
+        } else {
+            break;
+        }
+        next += 1;
+    }
+    let pairs = &args[next..];
+    if pairs.is_empty() || pairs.len() % 2 == 1 {
+        return Err(CommandError::Syntax);
+    }
+    if nx && xx {
+        return Err(CommandError::XxNxConflict);
+    }
+    if (nx && (gt || lt)) || (gt && lt) {
+        return Err(CommandError::GtLtNxConflict);
+    }
+    if incr && pairs.len() > 2 {
+        return Err(CommandError::IncrSinglePair);
+    }
+    let mut elements = Vec::with_capacity(pairs.len() / 2);
+    for pair in pairs.chunks(2) {
+        elements.push((arg_float(&pair[0])?, arg_bytes(&pair[1])?.clone()));
+    }
+
+    // XX never creates the key
+    if xx && zset(db, key)?.is_none() {
+        if incr {
+            return Ok(RedisValueRef::NullBulkString);
+        }
+        return Ok(RedisValueRef::Int(0));
+    }
+    let zset = zset_or_create(db, key)?;
+    let (mut added, mut changed) = (0, 0);
+    let mut incr_reply = RedisValueRef::NullBulkString;
+    for (score, member) in elements {
+        let current = zset.score(&member);
+        let new_score = match current {
+            None if xx => continue,
+            None => score,
+            Some(_) if nx => continue,
+            Some(current) => {
+                let new_score = if incr { current + score } else { score };
+                if new_score.is_nan() {
+                    return Err(CommandError::ScoreNaN);
+                }
+                if (gt && new_score <= current) || (lt && new_score >= current) {
+                    continue;
+                }
+                new_score
+            }
+        };
+        match current {
+            None => added += 1,
+            Some(current) if current != new_score => changed += 1,
+            Some(_) => {}
+        }
+        zset.insert(member, new_score);
+        incr_reply = RedisValueRef::Double(new_score);
+    }
+    if added + changed > 0 {
+        db.signal_modified(key);
+    }
+
+    if incr {
+        return Ok(incr_reply);
+    }
+    Ok(RedisValueRef::Int(if ch { added + changed } else { added }))
+}
+
+/// ZRANGE key start stop [BYSCORE | BYLEX] [REV] [LIMIT offset count]
+/// [WITHSCORES]
+pub fn handle_zrange(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zrange", args, 3, None)?;
+    let key = arg_bytes(&args[0])?;
+
+    let (mut by_score, mut by_lex, mut rev, mut with_scores) = (false, false, false, false);
+    let mut limit = None;
+    let mut options = args[3..].iter();
+    while let Some(option) = options.next() {
+        if is_keyword(option, "byscore") {
+            by_score = true;
+        } else if is_keyword(option, "bylex") {
+            by_lex = true;
+        } else if is_keyword(option, "rev") {
+            rev = true;
+        } else if is_keyword(option, "withscores") {
+            with_scores = true;
+        } else if is_keyword(option, "limit") {
+            match (options.next(), options.next()) {
+                (Some(offset), Some(count)) => limit = Some((arg_int(offset)?, arg_int(count)?)),
+                _ => return Err(CommandError::Syntax),
+            }
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    if by_score && by_lex {
+        return Err(CommandError::Syntax);
+    }
+    if limit.is_some() && !by_score && !by_lex {
+        return Err(CommandError::LimitWithoutRange);
+    }
+    if with_scores && by_lex {
+        return Err(CommandError::WithScoresByLex);
+    }
+
+    // Score and lex ranges are given from the start of the walk, so REV
+    // swaps them
+    let (min, max) = match rev && (by_score || by_lex) {
+        true => (&args[2], &args[1]),
+        false => (&args[1], &args[2]),
+    };
+    // A negative offset gives nothing and a negative count means no limit
+    let (offset, count) = match limit {
+        Some((offset, _)) if offset < 0 => return Ok(RedisValueRef::Array(vec![])),
+        Some((offset, count)) => (offset as usize, (count >= 0).then_some(count as usize)),
+        None => (0, None),
+    };
+
+    let elements = if by_score {
+        let (min, max) = (parse_score_bound(min)?, parse_score_bound(max)?);
+        match zset(db, key)? {
+            Some(zset) => zset.range_by_score(min, max, rev, offset, count),
+            None => vec![],
+        }
+    } else if by_lex {
+        let (min, max) = (parse_lex_bound(min)?, parse_lex_bound(max)?);
+        match zset(db, key)? {
+            Some(zset) => zset.range_by_lex(&min, &max, rev, offset, count),
+            None => vec![],
+        }
+    } else {
+        let (start, end) = (arg_int(min)?, arg_int(max)?);
+        match zset(db, key)? {
+            Some(zset) => match clamp_range(start, end, zset.len()) {
+                // Ranks count from the top with REV
+                Some((start, end)) if rev => {
+                    let last = zset.len() - 1;
+                    let mut elements = zset.range_by_rank(last - end, last - start);
+                    elements.reverse();
+                    elements
+                }
+                Some((start, end)) => zset.range_by_rank(start, end),
+                None => vec![],
+            },
+            None => vec![],
+        }
+    };
+    Ok(elements_reply(elements, with_scores))
+}
+
+/// ZRANK and ZREVRANK key member [WITHSCORE]
+pub fn handle_zrank(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 2, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let member = arg_bytes(&args[1])?;
+    let with_score = match args.get(2) {
+        Some(option) if is_keyword(option, "withscore") => true,
+        Some(_) => return Err(CommandError::Syntax),
+        None => false,
+    };
+
+    let found = zset(db, key)?.and_then(|zset| {
+        let rank = zset.rank(member)?;
+        let rank = match name {
+            "zrevrank" => zset.len() - 1 - rank,
+            _ => rank,
+        };
+        Some((rank, zset.score(member)?))
+    });
+    Ok(match found {
+        Some((rank, score)) if with_score => RedisValueRef::Array(vec![
+            RedisValueRef::Int(rank as i64),
+            RedisValueRef::Double(score),
+        ]),
+        Some((rank, _)) => RedisValueRef::Int(rank as i64),
+        None if with_score => RedisValueRef::NullArray,
+        None => RedisValueRef::NullBulkString,
+    })
+}
+
+/// ZSCORE key member
+pub fn handle_zscore(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zscore", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let member = arg_bytes(&args[1])?;
+    match zset(db, key)?.and_then(|zset| zset.score(member)) {
+        Some(score) => Ok(RedisValueRef::Double(score)),
+        None => Ok(RedisValueRef::NullBulkString),
+    }
+}
+
+/// ZCARD key
+pub fn handle_zcard(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zcard", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let len = zset(db, key)?.map_or(0, |zset| zset.len());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// ZREM key member [member ...]
+pub fn handle_zrem(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zrem", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let Some(zset) = zset_mut(db, key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let mut removed = 0;
+    for member in &args[1..] {
+        if zset.remove(arg_bytes(member)?) {
+            removed += 1;
+        }
+    }
+    if removed > 0 {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(RedisValueRef::Int(removed))
+}
+
+/// ZINCRBY key increment member
+pub fn handle_zincrby(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zincrby", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let increment = arg_float(&args[1])?;
+    let member = arg_bytes(&args[2])?;
+
+    let zset = zset_or_create(db, key)?;
+    let score = zset.score(member).unwrap_or(0.0) + increment;
+    if score.is_nan() {
+        db.remove_if_empty(key);
+        return Err(CommandError::ScoreNaN);
+    }
+    zset.insert(member.clone(), score);
+    db.signal_modified(key);
+    Ok(RedisValueRef::Double(score))
+}
+
+/// ZCOUNT key min max
+pub fn handle_zcount(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("zcount", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let min = parse_score_bound(&args[1])?;
+    let max = parse_score_bound(&args[2])?;
+    let count = zset(db, key)?.map_or(0, |zset| zset.count_in_score_range(min, max));
+    Ok(RedisValueRef::Int(count as i64))
+}
+
+/// ZPOPMIN and ZPOPMAX key [count]
+pub fn handle_zpop(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 1, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let count = match args.get(1) {
+        Some(count) => match arg_int(count)? {
+            count if count < 0 => return Err(CommandError::NotPositive),
+            count => count as usize,
+        },
+        None => 1,
+    };
+
+    let Some(zset) = zset_mut(db, key)? else {
+        return Ok(RedisValueRef::Array(vec![]));
+    };
+    let mut popped = Vec::with_capacity(count.min(zset.len()));
+    while popped.len() < count {
+        match zset.pop(name == "zpopmax") {
+            Some(element) => popped.push(element),
+            None => break,
+        }
+    }
+    if !popped.is_empty() {
+        db.signal_modified(key);
+        db.remove_if_empty(key);
+    }
+    Ok(elements_reply(popped, true))
+}
+
+/// ZUNIONSTORE and ZINTERSTORE destination numkeys key [key ...]
+/// [WEIGHTS weight [weight ...]] [AGGREGATE SUM | MIN | MAX]. Plain sets
+/// count as sorted sets with every score 1.
+pub fn handle_zsetop_store(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 3, None)?;
+    let destination = arg_bytes(&args[0])?;
+    let numkeys = arg_int(&args[1])?;
+    if numkeys < 1 {
+        return Err(CommandError::NoInputKeys(name));
+    }
+    let numkeys = numkeys as usize;
+    if numkeys > args.len() - 2 {
+        return Err(CommandError::Syntax);
+    }
+    let keys = &args[2..2 + numkeys];
+
+    let mut weights = vec![1.0; numkeys];
+    let mut aggregate = Aggregate::Sum;
+    let mut options = args[2 + numkeys..].iter();
+    while let Some(option) = options.next() {
+        if is_keyword(option, "weights") {
+            for weight in weights.iter_mut() {
+                let arg = options.next().ok_or(CommandError::Syntax)?;
+                *weight = parse_float(arg_bytes(arg)?).ok_or(CommandError::WeightNotFloat)?;
+            }
+        } else if is_keyword(option, "aggregate") {
+            let arg = options.next().ok_or(CommandError::Syntax)?;
+            aggregate = if is_keyword(arg, "sum") {
+                Aggregate::Sum
+            } else if is_keyword(arg, "min") {
+                Aggregate::Min
+            } else if is_keyword(arg, "max") {
+                Aggregate::Max
+            } else {
+                return Err(CommandError::Syntax);
+            };
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+
+    let mut sources = Vec::with_capacity(numkeys);
+    for (key, weight) in keys.iter().zip(weights) {
+        let elements = match db.get(arg_bytes(key)?) {
+            Some(SetObject {
+                value: StoredValue::SortedSet(zset),
+                ..
+            }) => zset
+                .iter()
+                .map(|(member, score)| (member.clone(), weighted(score, weight)))
+                .collect(),
+            Some(SetObject {
+                value: StoredValue::Set(set),
+                ..
+            }) => set
+                .iter()
+                .map(|member| (member.clone(), weighted(1.0, weight)))
+                .collect(),
+            Some(_) => return Err(CommandError::WrongType),
+            None => HashMap::new(),
+        };
+        sources.push(elements);
+    }
+
+    let mut sources = sources.into_iter();
+    let first = sources.next().unwrap_or_default();
+    let result = if name == "zinterstore" {
+        sources.fold(first, |acc, source| {
+            acc.into_iter()
+                .filter_map(|(member, score)| {
+                    let other = *source.get(&member)?;
+                    Some((member, aggregate.apply(score, other)))
+                })
+                .collect()
+        })
+    } else {
+        sources.fold(first, |mut acc, source| {
+            for (member, score) in source {
+                acc.entry(member)
+                    .and_modify(|current| *current = aggregate.apply(*current, score))
+                    .or_insert(score);
+            }
+            acc
+        })
+    };
+
+    let len = result.len();
+    if result.is_empty() {
+        db.remove(destination);
+    } else {
+        let mut zset = SortedSet::new();
+        for (member, score) in result {
+            zset.insert(member, score);
+        }
+        db.insert(
+            destination.clone(),
+            SetObject::new(StoredValue::SortedSet(zset), None),
+        );
+    }
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// How ZUNIONSTORE and ZINTERSTORE combine the scores of a member
+#[derive(Clone, Copy)]
+enum Aggregate {
+    Sum,
+    Min,
+    Max,
+}
+
+impl Aggregate {
+    fn apply(self, a: f64, b: f64) -> f64 {
+        match self {
+            // inf + -inf counts as zero, as in Redis
+            Aggregate::Sum => weighted(a + b, 1.0),
+            Aggregate::Min => a.min(b),
+            Aggregate::Max => a.max(b),
+        }
+    }
+}
+
+/// Scale a score, treating the NaN from 0 * inf as zero
+fn weighted(score: f64, weight: f64) -> f64 {
+    match score * weight {
+        score if score.is_nan() => 0.0,
+        score => score,
+    }
+}
+
+/// Flat `member [score] ...` reply shared by the range and pop commands
+pub fn elements_reply(elements: Vec<(Bytes, f64)>, with_scores: bool) -> RedisValueRef {
+    let mut reply = Vec::with_capacity(elements.len() * if with_scores { 2 } else { 1 });
+    for (member, score) in elements {
+        reply.push(RedisValueRef::String(member));
+        if with_scores {
+            reply.push(RedisValueRef::Double(score));
+        }
+    }
+    RedisValueRef::Array(reply)
+}
+
+/// Parse a score range end such as `1.5`, `(1.5` or `-inf`
+pub fn parse_score_bound(arg: &RedisValueRef) -> Result<ScoreBound, CommandError> {
+    let bytes = arg_bytes(arg)?;
+    let bound = match bytes.strip_prefix(b"(") {
+        Some(score) => parse_float(score).map(ScoreBound::Exclusive),
+        None => parse_float(bytes).map(ScoreBound::Inclusive),
+    };
+    bound.ok_or(CommandError::InvalidScoreRange)
+}
+
+/// Parse a lex range end: `[member`, `(member`, `-` or `+`
+fn parse_lex_bound(arg: &RedisValueRef) -> Result<LexBound, CommandError> {
+    let bytes = arg_bytes(arg)?;
+    match bytes.first() {
+        Some(b'-') if bytes.len() == 1 => Ok(LexBound::NegativeInfinity),
+        Some(b'+') if bytes.len() == 1 => Ok(LexBound::PositiveInfinity),
+        Some(b'[') => Ok(LexBound::Inclusive(bytes.slice(1..))),
+        Some(b'(') => Ok(LexBound::Exclusive(bytes.slice(1..))),
+        _ => Err(CommandError::InvalidLexRange),
+    }
+}
+
+/// Get the sorted set held at a key, failing if the key holds another type
+pub fn zset<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a SortedSet>, CommandError> {
+    match db.get(key) {
+        Some(SetObject {
+            value: StoredValue::SortedSet(zset),
+            ..
+        }) => Ok(Some(zset)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the sorted set held at a key for modification. Callers must report
+/// changes with `Database::signal_modified`, and call
+/// `Database::remove_if_empty` if they may empty the set.
+pub fn zset_mut<'a>(
+    db: &'a mut Database,
+    key: &[u8],
+) -> Result<Option<&'a mut SortedSet>, CommandError> {
+    match db.get_mut(key) {
+        Some(SetObject {
+            value: StoredValue::SortedSet(zset),
+            ..
+        }) => Ok(Some(zset)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the sorted set held at a key for modification, creating an empty one
+/// if the key is missing. Callers must report changes with
+/// `Database::signal_modified`.
+pub fn zset_or_create<'a>(
+    db: &'a mut Database,
+    key: &Bytes,
+) -> Result<&'a mut SortedSet, CommandError> {
+    match &mut db
+        .get_or_insert_with(key, || StoredValue::SortedSet(SortedSet::new()))
+        .value
+    {
+        StoredValue::SortedSet(zset) => Ok(zset),
+        _ => Err(CommandError::WrongType),
+    }
+}
@@ -0,0 +1,1435 @@
+use std::{
+    future::Future,
+    ops::Bound,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use bytes::Bytes;
+
+use crate::{
+    arg_bytes, arg_int, check_arity, current_time_millis, is_keyword, wait_until_served,
+    BlockedOperation, CommandError, CommandResult, Consumer, ConsumerGroup, Database, PendingEntry,
+    RedisValueRef, SetObject, StoredValue, Stream, StreamId,
+};
+
+/// XAUTOCLAIM's default COUNT
+const AUTOCLAIM_COUNT: i64 = 100;
+
+/// XADD key [NOMKSTREAM] [MAXLEN | MINID [= | ~] threshold [LIMIT count]]
+/// * | id field value [field value ...]
+pub fn handle_xadd(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xadd", args, 4, None)?;
+    let key = arg_bytes(&args[0])?;
+
+    let mut no_create = false;
+    let mut trim = None;
+    let mut next = 1;
+    while let Some(option) = args.get(next) {
+        if is_keyword(option, "nomkstream") {
+            no_create = true;
+            next += 1;
+        } else if is_keyword(option, "maxlen") || is_keyword(option, "minid") {
+            let (parsed, used) = Trim::parse(&args[next..])?;
+            trim = Some(parsed);
+            next += used;
+        } else {
+            break;
+        }
+    }
+    let id = args.get(next).ok_or(CommandError::WrongArity("xadd"))?;
+    let fields = &args[next + 1..];
+    if fields.is_empty() || fields.len() % 2 == 1 {
+        return Err(CommandError::WrongArity("xadd"));
+    }
+    let id = parse_add_id(id)?;
+    let mut pairs = Vec::with_capacity(fields.len() / 2);
+    for pair in fields.chunks(2) {
+        pairs.push((arg_bytes(&pair[0])?.clone(), arg_bytes(&pair[1])?.clone()));
+    }
+
+    if stream(db, key)?.is_none() && no_create {
+        return Ok(RedisValueRef::NullBulkString);
+    }
+    let stream = stream_or_create(db, key)?;
+    let id = next_id(stream, id)?;
+    stream.entries.insert(id, pairs);
+    stream.last_id = id;
+    stream.entries_added += 1;
+    if let Some(trim) = trim {
+        trim.apply(stream);
+    }
+    db.signal_modified(key);
+    db.mark_ready(key);
+    Ok(RedisValueRef::bulk_string(id.to_string()))
+}
+
+/// XRANGE key start end [COUNT count] and XREVRANGE key end start [COUNT count]
+pub fn handle_xrange(
+    name: &'static str,
+    args: &[RedisValueRef],
+    db: &mut Database,
+) -> CommandResult {
+    check_arity(name, args, 3, Some(5))?;
+    let key = arg_bytes(&args[0])?;
+    let rev = name == "xrevrange";
+    let (start, end) = match rev {
+        true => (&args[2], &args[1]),
+        false => (&args[1], &args[2]),
+    };
+    let start = parse_range_start(start)?;
+    let end = parse_range_end(end)?;
+    let count = match &args[3..] {
+        [] => usize::MAX,
+        [option, count] if is_keyword(option, "count") => arg_int(count)?.max(0) as usize,
+        _ => return Err(CommandError::Syntax),
+    };
+
+    let (Some(start), Some(end)) = (start, end) else {
+        return Ok(RedisValueRef::Array(vec![]));
+    };
+    let Some(stream) = stream(db, key)? else {
+        return Ok(RedisValueRef::Array(vec![]));
+    };
+    if start > end {
+        return Ok(RedisValueRef::Array(vec![]));
+    }
+    let range = stream.entries.range(start..=end);
+    let entries: Vec<_> = match rev {
+        true => range.rev().take(count).map(entry_reply).collect(),
+        false => range.take(count).map(entry_reply).collect(),
+    };
+    Ok(RedisValueRef::Array(entries))
+}
+
+/// XLEN key
+pub fn handle_xlen(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xlen", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let len = stream(db, key)?.map_or(0, |stream| stream.entries.len());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// XDEL key id [id ...]
+pub fn handle_xdel(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xdel", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let mut ids = Vec::with_capacity(args.len() - 1);
+    for id in &args[1..] {
+        ids.push(parse_id(id, Some(0))?);
+    }
+
+    let Some(stream) = stream_mut(db, key)? else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let mut deleted = 0;
+    for id in ids {
+        if stream.entries.remove(&id).is_some() {
+            stream.max_deleted_id = stream.max_deleted_id.max(id);
+            deleted += 1;
+        }
+    }
+    if deleted > 0 {
+        db.signal_modified(key);
+    }
+    Ok(RedisValueRef::Int(deleted))
+}
+
+/// XREAD [COUNT count] [BLOCK milliseconds] STREAMS key [key ...] id [id ...]
+pub async fn handle_xread(
+    args: &[RedisValueRef],
+    client_id: u64,
+    store: &Arc<Mutex<Database>>,
+    closed: impl Future<Output = ()>,
+) -> CommandResult {
+    check_arity("xread", args, 3, None)?;
+    let options = ReadOptions::parse("xread", args)?;
+
+    let receiver = {
+        let mut db = store.lock().unwrap();
+        let (replies, streams) = read(&options, &mut db)?;
+        if !replies.is_empty() {
+            return Ok(RedisValueRef::Array(replies));
+        }
+        let Some(timeout) = options.block else {
+            return Ok(RedisValueRef::NullArray);
+        };
+        let keys = streams.iter().map(|(key, _)| key.clone()).collect();
+        let operation = BlockedOperation::Read {
+            streams,
+            count: options.count,
+        };
+        (db.block(client_id, keys, operation), timeout)
+    };
+    let (receiver, timeout) = receiver;
+    wait_until_served(client_id, store, receiver, timeout, closed).await
+}
+
+/// XREAD run inside a transaction, where BLOCK never waits
+pub fn try_xread(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xread", args, 3, None)?;
+    let options = ReadOptions::parse("xread", args)?;
+    match read(&options, db)? {
+        (replies, _) if replies.is_empty() => Ok(RedisValueRef::NullArray),
+        (replies, _) => Ok(RedisValueRef::Array(replies)),
+    }
+}
+
+/// Replies for the streams XREAD found entries in, and the ID each stream
+/// would be waited on after
+type ReadReplies = (Vec<RedisValueRef>, Vec<(Bytes, StreamId)>);
+
+/// Read the entries XREAD asks for, with `$` resolved to the current last ID
+fn read(options: &ReadOptions, db: &mut Database) -> Result<ReadReplies, CommandError> {
+    let mut streams = Vec::with_capacity(options.streams.len());
+    let mut replies = Vec::new();
+    for (key, id) in &options.streams {
+        let stream = stream(db, key)?;
+        // `$` reads only entries added after the call
+        let after = if id.as_ref() == b"$" {
+            stream.map_or(StreamId::MIN, |stream| stream.last_id)
+        } else {
+            parse_id(&RedisValueRef::String(id.clone()), Some(0))?
+        };
+        if let Some(stream) = stream {
+            let entries = entries_after(stream, after, options.count);
+            if !entries.is_empty() {
+                replies.push(stream_reply(key, entries));
+            }
+        }
+        streams.push((key.clone(), after));
+    }
+    Ok((replies, streams))
+}
+
+/// XREADGROUP GROUP group consumer [COUNT count] [BLOCK milliseconds]
+/// [NOACK] STREAMS key [key ...] id [id ...]. The ID `>` reads entries never
+/// delivered to the group, any other ID re-reads the consumer's pending
+/// entries after it.
+pub async fn handle_xreadgroup(
+    args: &[RedisValueRef],
+    client_id: u64,
+    store: &Arc<Mutex<Database>>,
+    closed: impl Future<Output = ()>,
+) -> CommandResult {
+    check_arity("xreadgroup", args, 6, None)?;
+    let options = ReadOptions::parse("xreadgroup", args)?;
+    let (group, consumer) = options.group.clone().ok_or(CommandError::Syntax)?;
+
+    let receiver = {
+        let mut db = store.lock().unwrap();
+        let (replies, history) = read_group(&options, &group, &consumer, &mut db)?;
+        if !replies.is_empty() {
+            return Ok(RedisValueRef::Array(replies));
+        }
+        // Only reads of new entries wait for them
+        let (Some(timeout), false) = (options.block, history) else {
+            return Ok(RedisValueRef::NullArray);
+        };
+        let keys = options.streams.iter().map(|(key, _)| key.clone()).collect();
+        let operation = BlockedOperation::ReadGroup {
+            group,
+            consumer,
+            count: options.count,
+            noack: options.noack,
+        };
+        (db.block(client_id, keys, operation), timeout)
+    };
+    let (receiver, timeout) = receiver;
+    wait_until_served(client_id, store, receiver, timeout, closed).await
+}
+
+/// XREADGROUP run inside a transaction, where BLOCK never waits
+pub fn try_xreadgroup(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xreadgroup", args, 6, None)?;
+    let options = ReadOptions::parse("xreadgroup", args)?;
+    let (group, consumer) = options.group.clone().ok_or(CommandError::Syntax)?;
+    match read_group(&options, &group, &consumer, db)? {
+        (replies, _) if replies.is_empty() => Ok(RedisValueRef::NullArray),
+        (replies, _) => Ok(RedisValueRef::Array(replies)),
+    }
+}
+
+/// Read the entries XREADGROUP asks for on behalf of a consumer. Also returns
+/// whether any stream re-read pending entries rather than new ones.
+fn read_group(
+    options: &ReadOptions,
+    group: &Bytes,
+    consumer: &Bytes,
+    db: &mut Database,
+) -> Result<(Vec<RedisValueRef>, bool), CommandError> {
+    // Check every stream, group and ID before delivering anything, so an
+    // error cannot leave entries pending that the client never saw
+    let mut reads = Vec::with_capacity(options.streams.len());
+    for (key, id) in &options.streams {
+        let no_group = || {
+            CommandError::NoGroupForRead(
+                String::from_utf8_lossy(key).into_owned(),
+                String::from_utf8_lossy(group).into_owned(),
+            )
+        };
+        let stream = stream(db, key)?.ok_or_else(no_group)?;
+        if !stream.groups.contains_key(group) {
+            return Err(no_group());
+        }
+        // None reads new entries, otherwise pending ones after the ID
+        let after = match id.as_ref() {
+            b">" => None,
+            _ => Some(parse_id(&RedisValueRef::String(id.clone()), Some(0))?),
+        };
+        reads.push((key, after));
+    }
+
+    let now = current_time_millis();
+    let mut replies = Vec::new();
+    let mut history = false;
+    for (key, after) in reads {
+        let stream = stream_mut(db, key)?.expect("stream checked");
+        let Some(after) = after else {
+            let entries = deliver_new(stream, group, consumer, options.count, options.noack, now);
+            if !entries.is_empty() {
+                db.signal_modified(key);
+                replies.push(stream_reply(key, entries));
+            }
+            continue;
+        };
+        history = true;
+        let entries = pending_history(stream, group, consumer, after, options.count, now);
+        if !entries.is_empty() {
+            db.signal_modified(key);
+        }
+        replies.push(RedisValueRef::Array(vec![
+            RedisValueRef::String(key.clone()),
+            RedisValueRef::Array(entries),
+        ]));
+    }
+    Ok((replies, history))
+}
+
+/// Serve a client blocked in XREAD or XREADGROUP on a stream that was
+/// written to, None when there is nothing new for it yet
+pub fn serve_stream_waiter(
+    db: &mut Database,
+    key: &Bytes,
+    operation: &BlockedOperation,
+) -> Option<CommandResult> {
+    match operation {
+        BlockedOperation::Read { streams, count } => {
+            let (_, after) = streams.iter().find(|(stream, _)| stream == key)?;
+            let stream = stream(db, key).ok()??;
+            let entries = entries_after(stream, *after, *count);
+            if entries.is_empty() {
+                return None;
+            }
+            Some(Ok(RedisValueRef::Array(vec![stream_reply(key, entries)])))
+        }
+        BlockedOperation::ReadGroup {
+            group,
+            consumer,
+            count,
+            noack,
+        } => {
+            let no_group = CommandError::NoGroupForRead(
+                String::from_utf8_lossy(key).into_owned(),
+                String::from_utf8_lossy(group).into_owned(),
+            );
+            let Some(stream) = stream_mut(db, key).ok().flatten() else {
+                return Some(Err(no_group));
+            };
+            if !stream.groups.contains_key(group) {
+                return Some(Err(no_group));
+            }
+            let now = current_time_millis();
+            let entries = deliver_new(stream, group, consumer, *count, *noack, now);
+            if entries.is_empty() {
+                return None;
+            }
+            db.signal_modified(key);
+            Some(Ok(RedisValueRef::Array(vec![stream_reply(key, entries)])))
+        }
+        _ => unreachable!("stream waiters only read"),
+    }
+}
+
+/// XGROUP CREATE key group id | $ [MKSTREAM] and XGROUP DESTROY key group
+pub fn handle_xgroup(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xgroup", args, 1, None)?;
+    let subcommand = &args[0];
+    if is_keyword(subcommand, "create") {
+        check_arity("xgroup|create", args, 4, Some(5))?;
+        let key = arg_bytes(&args[1])?;
+        let group = arg_bytes(&args[2])?;
+        let create = match args.get(4) {
+            Some(option) if is_keyword(option, "mkstream") => true,
+            Some(_) => return Err(CommandError::Syntax),
+            None => false,
+        };
+        let id = arg_bytes(&args[3])?;
+        let id = match id.as_ref() {
+            b"$" => None,
+            _ => Some(parse_id(&args[3], Some(0))?),
+        };
+
+        if stream(db, key)?.is_none() && !create {
+            return Err(CommandError::GroupKeyMissing);
+        }
+        let stream = stream_or_create(db, key)?;
+        if stream.groups.contains_key(group) {
+            return Err(CommandError::BusyGroup);
+        }
+        let group_state = ConsumerGroup {
+            last_delivered: id.unwrap_or(stream.last_id),
+            ..Default::default()
+        };
+        stream.groups.insert(group.clone(), group_state);
+        db.signal_modified(key);
+        Ok(RedisValueRef::ok())
+    } else if is_keyword(subcommand, "destroy") {
+        check_arity("xgroup|destroy", args, 3, Some(3))?;
+        let key = arg_bytes(&args[1])?;
+        let group = arg_bytes(&args[2])?;
+        let Some(stream) = stream_mut(db, key)? else {
+            return Err(CommandError::GroupKeyMissing);
+        };
+        let destroyed = stream.groups.remove(group).is_some();
+        if destroyed {
+            db.signal_modified(key);
+        }
+        // Consumers blocked on the group get an error
+        db.mark_ready(key);
+        Ok(RedisValueRef::Int(destroyed as i64))
+    } else {
+        Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(arg_bytes(subcommand)?).into_owned(),
+            "XGROUP",
+        ))
+    }
+}
+
+/// XACK key group id [id ...]
+pub fn handle_xack(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xack", args, 3, None)?;
+    let key = arg_bytes(&args[0])?;
+    let group = arg_bytes(&args[1])?;
+    let mut ids = Vec::with_capacity(args.len() - 2);
+    for id in &args[2..] {
+        ids.push(parse_id(id, Some(0))?);
+    }
+
+    let Some(group) = stream_mut(db, key)?.and_then(|stream| stream.groups.get_mut(group)) else {
+        return Ok(RedisValueRef::Int(0));
+    };
+    let acked = ids
+        .iter()
+        .filter(|id| group.pending.remove(id).is_some())
+        .count();
+    if acked > 0 {
+        db.signal_modified(key);
+    }
+    Ok(RedisValueRef::Int(acked as i64))
+}
+
+/// XPENDING key group [[IDLE min-idle-time] start end count [consumer]]
+pub fn handle_xpending(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xpending", args, 2, None)?;
+    let key = arg_bytes(&args[0])?;
+    let group_name = arg_bytes(&args[1])?;
+
+    // Parse the extended form before looking anything up
+    let mut extended = None;
+    if args.len() > 2 {
+        let mut rest = &args[2..];
+        let mut min_idle = 0;
+        if is_keyword(&rest[0], "idle") {
+            let idle = rest.get(1).ok_or(CommandError::Syntax)?;
+            min_idle = arg_int(idle)?.max(0) as u64;
+            rest = &rest[2..];
+        }
+        let (start, end, count, consumer) = match rest {
+            [start, end, count] => (start, end, count, None),
+            [start, end, count, consumer] => (start, end, count, Some(arg_bytes(consumer)?)),
+            _ => return Err(CommandError::Syntax),
+        };
+        let start = parse_range_start(start)?;
+        let end = parse_range_end(end)?;
+        let count = arg_int(count)?.max(0) as usize;
+        extended = Some((min_idle, start, end, count, consumer));
+    }
+
+    let group = group(db, key, group_name)?;
+    let Some((min_idle, start, end, count, consumer)) = extended else {
+        let (Some((first, _)), Some((last, _))) = (
+            group.pending.first_key_value(),
+            group.pending.last_key_value(),
+        ) else {
+            return Ok(RedisValueRef::Array(vec![
+                RedisValueRef::Int(0),
+                RedisValueRef::NullBulkString,
+                RedisValueRef::NullBulkString,
+                RedisValueRef::NullArray,
+            ]));
+        };
+        let mut per_consumer = std::collections::BTreeMap::<&Bytes, usize>::new();
+        for entry in group.pending.values() {
+            *per_consumer.entry(&entry.consumer).or_default() += 1;
+        }
+        let consumers = per_consumer
+            .into_iter()
+            .map(|(consumer, count)| {
+                RedisValueRef::Array(vec![
+                    RedisValueRef::String(consumer.clone()),
+                    RedisValueRef::bulk_string(count.to_string()),
+                ])
+            })
+            .collect();
+        return Ok(RedisValueRef::Array(vec![
+            RedisValueRef::Int(group.pending.len() as i64),
+            RedisValueRef::bulk_string(first.to_string()),
+            RedisValueRef::bulk_string(last.to_string()),
+            RedisValueRef::Array(consumers),
+        ]));
+    };
+
+    let (Some(start), Some(end)) = (start, end) else {
+        return Ok(RedisValueRef::Array(vec![]));
+    };
+    if start > end {
+        return Ok(RedisValueRef::Array(vec![]));
+    }
+    let now = current_time_millis();
+    let entries = group
+        .pending
+        .range(start..=end)
+        .filter(|(_, entry)| match consumer {
+            Some(consumer) => entry.consumer == consumer,
+            None => true,
+        })
+        .filter(|(_, entry)| now.saturating_sub(entry.delivered_at) >= min_idle)
+        .take(count)
+        .map(|(id, entry)| {
+            RedisValueRef::Array(vec![
+                RedisValueRef::bulk_string(id.to_string()),
+                RedisValueRef::String(entry.consumer.clone()),
+                RedisValueRef::Int(now.saturating_sub(entry.delivered_at) as i64),
+                RedisValueRef::Int(entry.delivery_count as i64),
+            ])
+        })
+        .collect();
+    Ok(RedisValueRef::Array(entries))
+}
+
+/// XCLAIM key group consumer min-idle-time id [id ...] [IDLE ms]
+/// [TIME unix-time-milliseconds] [RETRYCOUNT count] [FORCE] [JUSTID]
+/// [LASTID lastid]
+pub fn handle_xclaim(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xclaim", args, 5, None)?;
+    let key = arg_bytes(&args[0])?;
+    let group_name = arg_bytes(&args[1])?;
+    let consumer = arg_bytes(&args[2])?;
+    let min_idle = arg_int(&args[3])?.max(0) as u64;
+
+    // IDs run until the first argument that is not one
+    let mut ids = Vec::new();
+    let mut next = 4;
+    while let Some(arg) = args.get(next) {
+        match parse_id(arg, Some(0)) {
+            Ok(id) => ids.push(id),
+            Err(_) => break,
+        }
+        next += 1;
+    }
+    if ids.is_empty() {
+        return Err(CommandError::InvalidStreamId);
+    }
+    let now = current_time_millis();
+    let mut delivered_at = now;
+    let mut retry_count = None;
+    let (mut force, mut just_id) = (false, false);
+    let mut last_id = None;
+    let mut options = args[next..].iter();
+    while let Some(option) = options.next() {
+        if is_keyword(option, "force") {
+            force = true;
+        } else if is_keyword(option, "justid") {
+            just_id = true;
+        } else {
+            let value = options.next().ok_or(CommandError::Syntax)?;
+            if is_keyword(option, "idle") {
+                delivered_at = now.saturating_sub(arg_int(value)?.max(0) as u64);
+            } else if is_keyword(option, "time") {
+                delivered_at = arg_int(value)?.max(0) as u64;
+            } else if is_keyword(option, "retrycount") {
+                retry_count = Some(arg_int(value)?.max(0) as u64);
+            } else if is_keyword(option, "lastid") {
+                last_id = Some(parse_id(value, Some(0))?);
+            } else {
+                return Err(CommandError::Syntax);
+            }
+        }
+    }
+
+    let stream = group_stream(db, key, group_name)?;
+    let group = stream.groups.get_mut(group_name).expect("group checked");
+    let before = (group.last_delivered, group.pending.len());
+    if let Some(last_id) = last_id {
+        group.last_delivered = group.last_delivered.max(last_id);
+    }
+    let mut claimed = Vec::new();
+    for id in ids {
+        let exists = stream.entries.contains_key(&id);
+        let entry = match group.pending.get_mut(&id) {
+            Some(entry) => {
+                if !exists {
+                    // The entry was deleted, drop it from the PEL
+                    group.pending.remove(&id);
+                    continue;
+                }
+                if now.saturating_sub(entry.delivered_at) < min_idle {
+                    continue;
+                }
+                entry
+            }
+            None if force && exists => group.pending.entry(id).or_insert(PendingEntry {
+                consumer: consumer.clone(),
+                delivered_at,
+                delivery_count: 0,
+            }),
+            None => continue,
+        };
+        entry.consumer = consumer.clone();
+        entry.delivered_at = delivered_at;
+        match retry_count {
+            Some(count) => entry.delivery_count = count,
+            None if !just_id => entry.delivery_count += 1,
+            None => {}
+        }
+        claimed.push(id);
+    }
+    touch_consumer(group, consumer, now, !claimed.is_empty());
+    let modified = !claimed.is_empty() || before != (group.last_delivered, group.pending.len());
+
+    let reply = claimed_reply(stream, &claimed, just_id);
+    if modified {
+        db.signal_modified(key);
+    }
+    Ok(RedisValueRef::Array(reply))
+}
+
+/// XAUTOCLAIM key group consumer min-idle-time start [COUNT count] [JUSTID],
+/// replies with the cursor to continue from, the claimed entries and the IDs
+/// of pending entries that no longer exist
+pub fn handle_xautoclaim(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xautoclaim", args, 5, Some(8))?;
+    let key = arg_bytes(&args[0])?;
+    let group_name = arg_bytes(&args[1])?;
+    let consumer = arg_bytes(&args[2])?;
+    let min_idle = arg_int(&args[3])?.max(0) as u64;
+    let start = parse_range_start(&args[4])?;
+    let mut count = AUTOCLAIM_COUNT;
+    let mut just_id = false;
+    let mut options = args[5..].iter();
+    while let Some(option) = options.next() {
+        if is_keyword(option, "justid") {
+            just_id = true;
+        } else if is_keyword(option, "count") {
+            let value = options.next().ok_or(CommandError::Syntax)?;
+            count = arg_int(value)?;
+            if count < 1 {
+                return Err(CommandError::NotPositive);
+            }
+        } else {
+            return Err(CommandError::Syntax);
+        }
+    }
+    let count = count as usize;
+
+    let stream = group_stream(db, key, group_name)?;
+    let group = stream.groups.get_mut(group_name).expect("group checked");
+    let now = current_time_millis();
+    let mut claimed = Vec::new();
+    let mut deleted = Vec::new();
+    let mut cursor = start;
+    while let Some(from) = cursor {
+        let Some(id) = group.pending.range(from..).next().map(|(id, _)| *id) else {
+            cursor = None;
+            break;
+        };
+        if claimed.len() == count {
+            break;
+        }
+        cursor = id.next();
+        if !stream.entries.contains_key(&id) {
+            group.pending.remove(&id);
+            deleted.push(RedisValueRef::bulk_string(id.to_string()));
+            continue;
+        }
+        let entry = group.pending.get_mut(&id).expect("pending id");
+        if now.saturating_sub(entry.delivered_at) < min_idle {
+            continue;
+        }
+        entry.consumer = consumer.clone();
+        entry.delivered_at = now;
+        if !just_id {
+            entry.delivery_count += 1;
+        }
+        claimed.push(id);
+    }
+    touch_consumer(group, consumer, now, !claimed.is_empty());
+
+    let claimed_entries = claimed_reply(stream, &claimed, just_id);
+    if !claimed.is_empty() || !deleted.is_empty() {
+        db.signal_modified(key);
+    }
+    Ok(RedisValueRef::Array(vec![
+        RedisValueRef::bulk_string(cursor.unwrap_or(StreamId::MIN).to_string()),
+        RedisValueRef::Array(claimed_entries),
+        RedisValueRef::Array(deleted),
+    ]))
+}
+
+/// XINFO STREAM key, XINFO GROUPS key and XINFO CONSUMERS key group
+pub fn handle_xinfo(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("xinfo", args, 1, None)?;
+    let subcommand = &args[0];
+    let now = current_time_millis();
+    if is_keyword(subcommand, "stream") {
+        check_arity("xinfo|stream", args, 2, Some(2))?;
+        let key = arg_bytes(&args[1])?;
+        let stream = stream(db, key)?.ok_or(CommandError::NoSuchKey)?;
+        let first = stream.entries.first_key_value();
+        let last = stream.entries.last_key_value();
+        Ok(RedisValueRef::Map(vec![
+            field("length", RedisValueRef::Int(stream.entries.len() as i64)),
+            field("last-generated-id", id_reply(stream.last_id)),
+            field("max-deleted-entry-id", id_reply(stream.max_deleted_id)),
+            field(
+                "entries-added",
+                RedisValueRef::Int(stream.entries_added as i64),
+            ),
+            field(
+                "recorded-first-entry-id",
+                id_reply(first.map_or(StreamId::MIN, |(id, _)| *id)),
+            ),
+            field("groups", RedisValueRef::Int(stream.groups.len() as i64)),
+            field(
+                "first-entry",
+                first.map_or(RedisValueRef::NullBulkString, entry_reply),
+            ),
+            field(
+                "last-entry",
+                last.map_or(RedisValueRef::NullBulkString, entry_reply),
+            ),
+        ]))
+    } else if is_keyword(subcommand, "groups") {
+        check_arity("xinfo|groups", args, 2, Some(2))?;
+        let key = arg_bytes(&args[1])?;
+        let stream = stream(db, key)?.ok_or(CommandError::NoSuchKey)?;
+        let groups = stream
+            .groups
+            .iter()
+            .map(|(name, group)| {
+                RedisValueRef::Map(vec![
+                    field("name", RedisValueRef::String(name.clone())),
+                    field(
+                        "consumers",
+                        RedisValueRef::Int(group.consumers.len() as i64),
+                    ),
+                    field("pending", RedisValueRef::Int(group.pending.len() as i64)),
+                    field("last-delivered-id", id_reply(group.last_delivered)),
+                ])
+            })
+            .collect();
+        Ok(RedisValueRef::Array(groups))
+    } else if is_keyword(subcommand, "consumers") {
+        check_arity("xinfo|consumers", args, 3, Some(3))?;
+        let key = arg_bytes(&args[1])?;
+        let group_name = arg_bytes(&args[2])?;
+        let group = group(db, key, group_name)?;
+        let consumers = group
+            .consumers
+            .iter()
+            .map(|(name, consumer)| {
+                let pending = group
+                    .pending
+                    .values()
+                    .filter(|entry| entry.consumer == name)
+                    .count();
+                let inactive = consumer
+                    .active_at
+                    .map_or(-1, |active_at| now.saturating_sub(active_at) as i64);
+                RedisValueRef::Map(vec![
+                    field("name", RedisValueRef::String(name.clone())),
+                    field("pending", RedisValueRef::Int(pending as i64)),
+                    field(
+                        "idle",
+                        RedisValueRef::Int(now.saturating_sub(consumer.seen_at) as i64),
+                    ),
+                    field("inactive", RedisValueRef::Int(inactive)),
+                ])
+            })
+            .collect();
+        Ok(RedisValueRef::Array(consumers))
+    } else {
+        Err(CommandError::UnknownSubcommand(
+            String::from_utf8_lossy(arg_bytes(subcommand)?).into_owned(),
+            "XINFO",
+        ))
+    }
+}
+
+/// Options shared by XREAD and XREADGROUP
+struct ReadOptions {
+    count: Option<usize>,
+    /// Set when BLOCK was given, holding the timeout where None waits forever
+    block: Option<Option<Duration>>,
+    noack: bool,
+    group: Option<(Bytes, Bytes)>,
+    /// Each key with its unparsed ID, which may be `$` or `>`
+    streams: Vec<(Bytes, Bytes)>,
+}
+
+impl ReadOptions {
+    fn parse(name: &'static str, args: &[RedisValueRef]) -> Result<ReadOptions, CommandError> {
+        let mut options = ReadOptions {
+            count: None,
+            block: None,
+            noack: false,
+            group: None,
+            streams: Vec::new(),
+        };
+        let group_allowed = name == "xreadgroup";
+        let mut next = 0;
+        while let Some(option) = args.get(next) {
+            if is_keyword(option, "streams") {
+                let rest = &args[next + 1..];
+                if rest.is_empty() || rest.len() % 2 == 1 {
+                    return Err(CommandError::UnbalancedStreams(name));
+                }
+                let (keys, ids) = rest.split_at(rest.len() / 2);
+                for (key, id) in keys.iter().zip(ids) {
+                    options
+                        .streams
+                        .push((arg_bytes(key)?.clone(), arg_bytes(id)?.clone()));
+                }
+                return match (group_allowed, &options.group) {
+                    (true, None) => Err(CommandError::Syntax),
+                    _ => Ok(options),
+                };
+            } else if is_keyword(option, "count") {
+                let count = args.get(next + 1).ok_or(CommandError::Syntax)?;
+                // Zero or less means no limit
+                options.count = Some(arg_int(count)?)
+                    .filter(|count| *count > 0)
+                    .map(|c| c as usize);
+                next += 2;
+            } else if is_keyword(option, "block") {
+                let timeout = args.get(next + 1).ok_or(CommandError::Syntax)?;
+                let millis = arg_int(timeout).map_err(|_| CommandError::TimeoutNotInteger)?;
+                if millis < 0 {
+                    return Err(CommandError::NegativeTimeout);
+                }
+                options.block = Some((millis > 0).then(|| Duration::from_millis(millis as u64)));
+                next += 2;
+            } else if group_allowed && is_keyword(option, "noack") {
+                options.noack = true;
+                next += 1;
+            } else if group_allowed && is_keyword(option, "group") {
+                match (args.get(next + 1), args.get(next + 2)) {
+                    (Some(group), Some(consumer)) => {
+                        options.group =
+                            Some((arg_bytes(group)?.clone(), arg_bytes(consumer)?.clone()));
+                    }
+                    _ => return Err(CommandError::Syntax),
+                }
+                next += 3;
+            } else {
+                return Err(CommandError::Syntax);
+            }
+        }
+        Err(CommandError::Syntax)
+    }
+}
+
+/// XADD trimming, MAXLEN or MINID
+struct Trim {
+    max_len: Option<usize>,
+    min_id: Option<StreamId>,
+    /// Most entries to evict, only allowed with `~`
+    limit: Option<usize>,
+}
+
+impl Trim {
+    /// Parse `MAXLEN | MINID [= | ~] threshold [LIMIT count]`, returning the
+    /// number of arguments used
+    fn parse(args: &[RedisValueRef]) -> Result<(Trim, usize), CommandError> {
+        let by_len = is_keyword(&args[0], "maxlen");
+        let mut next = 1;
+        let approximate = match args.get(next) {
+            Some(RedisValueRef::String(op)) if op.as_ref() == b"~" => true,
+            Some(RedisValueRef::String(op)) if op.as_ref() == b"=" => false,
+            _ => {
+                next -= 1;
+                false
+            }
+        };
+        next += 1;
+        let threshold = args.get(next).ok_or(CommandError::Syntax)?;
+        next += 1;
+        let mut trim = Trim {
+            max_len: None,
+            min_id: None,
+            limit: None,
+        };
+        if by_len {
+            let max_len = arg_int(threshold)?;
+            if max_len < 0 {
+                return Err(CommandError::NegativeMaxLen);
+            }
+            trim.max_len = Some(max_len as usize);
+        } else {
+            trim.min_id = Some(parse_id(threshold, Some(0))?);
+        }
+        if args.get(next).is_some_and(|arg| is_keyword(arg, "limit")) {
+            let limit = args.get(next + 1).ok_or(CommandError::Syntax)?;
+            let limit = arg_int(limit)?;
+            if limit < 0 {
+                return Err(CommandError::NotPositive);
+            }
+            if !approximate {
+                return Err(CommandError::LimitWithoutApprox);
+            }
+            // LIMIT 0 means no limit
+            trim.limit = (limit > 0).then_some(limit as usize);
+            next += 2;
+        }
+        Ok((trim, next))
+    }
+
+    /// Evict the oldest entries. Approximate trimming is done exactly, which
+    /// Redis allows for.
+    fn apply(&self, stream: &mut Stream) {
+        let mut evicted = 0;
+        while let Some((&oldest, _)) = stream.entries.first_key_value() {
+            if self.limit.is_some_and(|limit| evicted >= limit) {
+                break;
+            }
+            let evict = match (self.max_len, self.min_id) {
+                (Some(max_len), _) => stream.entries.len() > max_len,
+                (_, Some(min_id)) => oldest < min_id,
+                _ => false,
+            };
+            if !evict {
+                break;
+            }
+            stream.entries.remove(&oldest);
+            evicted += 1;
+        }
+    }
+}
+
+/// The ID given to XADD
+enum AddId {
+    Auto,
+    /// `ms-*`, the sequence number is generated
+    AutoSeq(u64),
+    Explicit(StreamId),
+}
+
+fn parse_add_id(arg: &RedisValueRef) -> Result<AddId, CommandError> {
+    let bytes = arg_bytes(arg)?;
+    if bytes.as_ref() == b"*" {
+        return Ok(AddId::Auto);
+    }
+    if let Some(ms) = bytes.strip_suffix(b"-*") {
+        return std::str::from_utf8(ms)
+            .ok()
+            .and_then(|ms| ms.parse().ok())
+            .map(AddId::AutoSeq)
+            .ok_or(CommandError::InvalidStreamId);
+    }
+    let id = parse_id(arg, Some(0))?;
+    if id == StreamId::MIN {
+        return Err(CommandError::StreamIdZero);
+    }
+    Ok(AddId::Explicit(id))
+}
+
+/// Work out the ID of a new entry, which must be above every ID so far
+fn next_id(stream: &Stream, id: AddId) -> Result<StreamId, CommandError> {
+    let last = stream.last_id;
+    let id = match id {
+        AddId::Auto => {
+            let ms = current_time_millis();
+            if ms > last.ms {
+                StreamId::new(ms, 0)
+            } else {
+                // The clock went backwards or several entries share a millisecond
+                last.next().ok_or(CommandError::StreamIdTooSmall)?
+            }
+        }
+        AddId::AutoSeq(ms) if ms == last.ms => {
+            let seq = last
+                .seq
+                .checked_add(1)
+                .ok_or(CommandError::StreamIdTooSmall)?;
+            StreamId::new(ms, seq)
+        }
+        // 0-0 is never a valid ID
+        AddId::AutoSeq(0) => StreamId::new(0, 1),
+        AddId::AutoSeq(ms) => StreamId::new(ms, 0),
+        AddId::Explicit(id) => id,
+    };
+    if id <= last {
+        return Err(CommandError::StreamIdTooSmall);
+    }
+    Ok(id)
+}
+
+/// Parse `ms-seq`, or a bare `ms` whose sequence number is `missing_seq`
+fn parse_id(arg: &RedisValueRef, missing_seq: Option<u64>) -> Result<StreamId, CommandError> {
+    let id = std::str::from_utf8(arg_bytes(arg)?).map_err(|_| CommandError::InvalidStreamId)?;
+    let (ms, seq) = match id.split_once('-') {
+        Some((ms, seq)) => (ms.parse().ok(), seq.parse().ok()),
+        None => (id.parse().ok(), missing_seq),
+    };
+    match (ms, seq) {
+        (Some(ms), Some(seq)) => Ok(StreamId::new(ms, seq)),
+        _ => Err(CommandError::InvalidStreamId),
+    }
+}
+
+/// Parse the start of an ID range: `-`, an ID, or an exclusive `(id`. None
+/// when nothing can follow an exclusive start.
+fn parse_range_start(arg: &RedisValueRef) -> Result<Option<StreamId>, CommandError> {
+    let bytes = arg_bytes(arg)?;
+    match bytes.as_ref() {
+        b"-" => Ok(Some(StreamId::MIN)),
+        b"+" => Ok(Some(StreamId::MAX)),
+        [b'(', ..] => {
+            let id = parse_id(&RedisValueRef::String(bytes.slice(1..)), Some(0))?;
+            Ok(id.next())
+        }
+        _ => Ok(Some(parse_id(arg, Some(0))?)),
+    }
+}
+
+/// Parse the end of an ID range: `+`, an ID where a bare `ms` covers the
+/// whole millisecond, or an exclusive `(id`
+fn parse_range_end(arg: &RedisValueRef) -> Result<Option<StreamId>, CommandError> {
+    let bytes = arg_bytes(arg)?;
+    match bytes.as_ref() {
+        b"-" => Ok(Some(StreamId::MIN)),
+        b"+" => Ok(Some(StreamId::MAX)),
+        [b'(', ..] => {
+            let id = parse_id(&RedisValueRef::String(bytes.slice(1..)), Some(u64::MAX))?;
+            Ok(id.prev())
+        }
+        _ => Ok(Some(parse_id(arg, Some(u64::MAX))?)),
+    }
+}
+
+/// Entries after an ID, at most `count` of them
+fn entries_after(stream: &Stream, after: StreamId, count: Option<usize>) -> Vec<RedisValueRef> {
+    stream
+        .entries
+        .range((Bound::Excluded(after), Bound::Unbounded))
+        .take(count.unwrap_or(usize::MAX))
+        .map(entry_reply)
+        .collect()
+}
+
+/// Hand the entries a group has not seen yet to a consumer, adding them to
+/// the pending entries list unless `noack` is set
+fn deliver_new(
+    stream: &mut Stream,
+    group_name: &Bytes,
+    consumer: &Bytes,
+    count: Option<usize>,
+    noack: bool,
+    now: u64,
+) -> Vec<RedisValueRef> {
+    let group = stream.groups.get_mut(group_name).expect("group checked");
+    let ids: Vec<StreamId> = stream
+        .entries
+        .range((Bound::Excluded(group.last_delivered), Bound::Unbounded))
+        .take(count.unwrap_or(usize::MAX))
+        .map(|(id, _)| *id)
+        .collect();
+    if let Some(last) = ids.last() {
+        group.last_delivered = *last;
+    }
+    if !noack {
+        for id in &ids {
+            let entry = PendingEntry {
+                consumer: consumer.clone(),
+                delivered_at: now,
+                delivery_count: 1,
+            };
+            group.pending.insert(*id, entry);
+        }
+    }
+    touch_consumer(group, consumer, now, !ids.is_empty());
+    ids.iter()
+        .map(|id| entry_reply((id, &stream.entries[id])))
+        .collect()
+}
+
+/// A consumer's pending entries after an ID, with deleted entries reported as
+/// an ID and a null
+fn pending_history(
+    stream: &mut Stream,
+    group_name: &Bytes,
+    consumer: &Bytes,
+    after: StreamId,
+    count: Option<usize>,
+    now: u64,
+) -> Vec<RedisValueRef> {
+    let group = stream.groups.get_mut(group_name).expect("group checked");
+    touch_consumer(group, consumer, now, false);
+    group
+        .pending
+        .range_mut((Bound::Excluded(after), Bound::Unbounded))
+        .filter(|(_, entry)| entry.consumer == consumer)
+        .take(count.unwrap_or(usize::MAX))
+        .map(|(id, entry)| match stream.entries.get(id) {
+            Some(fields) => {
+                // Like Redis, reading an entry again counts as a delivery
+                entry.delivered_at = now;
+                entry.delivery_count += 1;
+                entry_reply((id, fields))
+            }
+            None => RedisValueRef::Array(vec![
+                RedisValueRef::bulk_string(id.to_string()),
+                RedisValueRef::NullArray,
+            ]),
+        })
+        .collect()
+}
+
+/// Record that a consumer tried to read, creating it if needed
+fn touch_consumer(group: &mut ConsumerGroup, consumer: &Bytes, now: u64, active: bool) {
+    let state = group.consumers.entry(consumer.clone()).or_insert(Consumer {
+        seen_at: now,
+        active_at: None,
+    });
+    state.seen_at = now;
+    if active {
+        state.active_at = Some(now);
+    }
+}
+
+/// Claimed entries, or just their IDs
+fn claimed_reply(stream: &Stream, claimed: &[StreamId], just_id: bool) -> Vec<RedisValueRef> {
+    claimed
+        .iter()
+        .map(|id| match just_id {
+            true => RedisValueRef::bulk_string(id.to_string()),
+            false => entry_reply((id, &stream.entries[id])),
+        })
+        .collect()
+}
+
+/// `[key, [entry ...]]` as replied by XREAD
+fn stream_reply(key: &Bytes, entries: Vec<RedisValueRef>) -> RedisValueRef {
+    RedisValueRef::Array(vec![
+        RedisValueRef::String(key.clone()),
+        RedisValueRef::Array(entries),
+    ])
+}
+
+/// `[id, [field, value, ...]]`
+fn entry_reply((id, fields): (&StreamId, &Vec<(Bytes, Bytes)>)) -> RedisValueRef {
+    let mut values = Vec::with_capacity(fields.len() * 2);
+    for (field, value) in fields {
+        values.push(RedisValueRef::String(field.clone()));
+        values.push(RedisValueRef::String(value.clone()));
+    }
+    RedisValueRef::Array(vec![
+        RedisValueRef::bulk_string(id.to_string()),
+        RedisValueRef::Array(values),
+    ])
+}
+
+fn id_reply(id: StreamId) -> RedisValueRef {
+    RedisValueRef::bulk_string(id.to_string())
+}
+
+fn field(name: &str, value: RedisValueRef) -> (RedisValueRef, RedisValueRef) {
+    (RedisValueRef::bulk_string(name.to_string()), value)
+}
+
+/// The stream holding a group, failing with NOGROUP if either is missing
+fn group_stream<'a>(
+    db: &'a mut Database,
+    key: &Bytes,
+    group: &Bytes,
+) -> Result<&'a mut Stream, CommandError> {
+    let no_group = || {
+        CommandError::NoGroup(
+            String::from_utf8_lossy(key).into_owned(),
+            String::from_utf8_lossy(group).into_owned(),
+        )
+    };
+    let stream = stream_mut(db, key)?.ok_or_else(no_group)?;
+    if !stream.groups.contains_key(group) {
+        return Err(no_group());
+    }
+    Ok(stream)
+}
+
+/// A consumer group, failing with NOGROUP if it or its stream is missing
+fn group<'a>(
+    db: &'a mut Database,
+    key: &Bytes,
+    name: &Bytes,
+) -> Result<&'a ConsumerGroup, CommandError> {
+    let no_group = || {
+        CommandError::NoGroup(
+            String::from_utf8_lossy(key).into_owned(),
+            String::from_utf8_lossy(name).into_owned(),
+        )
+    };
+    stream(db, key)?
+        .and_then(|stream| stream.groups.get(name))
+        .ok_or_else(no_group)
+}
+
+/// Get the stream held at a key, failing if the key holds another type
+pub fn stream<'a>(db: &'a mut Database, key: &[u8]) -> Result<Option<&'a Stream>, CommandError> {
+    match db.get(key) {
+        Some(SetObject {
+            value: StoredValue::Stream(stream),
+            ..
+        }) => Ok(Some(stream)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the stream held at a key for modification. Callers must report
+/// changes with `Database::signal_modified`.
+pub fn stream_mut<'a>(
+    db: &'a mut Database,
+    key: &[u8],
+) -> Result<Option<&'a mut Stream>, CommandError> {
+    match db.get_mut(key) {
+        Some(SetObject {
+            value: StoredValue::Stream(stream),
+            ..
+        }) => Ok(Some(stream)),
+        Some(_) => Err(CommandError::WrongType),
+        None => Ok(None),
+    }
+}
+
+/// Get the stream held at a key for modification, creating an empty one if
+/// the key is missing. Callers must report changes with
+/// `Database::signal_modified`.
+fn stream_or_create<'a>(db: &'a mut Database, key: &Bytes) -> Result<&'a mut Stream, CommandError> {
+    match &mut db
+        .get_or_insert_with(key, || StoredValue::Stream(Stream::default()))
+        .value
+    {
+        StoredValue::Stream(stream) => Ok(stream),
+        _ => Err(CommandError::WrongType),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args;
+
+    fn run(
+        handler: fn(&[RedisValueRef], &mut Database) -> CommandResult,
+        line: &str,
+        db: &mut Database,
+    ) -> RedisValueRef {
+        handler(&args(line), db).unwrap()
+    }
+
+    fn text(value: &RedisValueRef) -> String {
+        String::from_utf8_lossy(arg_bytes(value).unwrap()).into_owned()
+    }
+
+    /// The IDs per stream in an XREADGROUP reply, deleted entries included
+    fn read_ids(reply: RedisValueRef) -> Vec<(String, Vec<String>)> {
+        let RedisValueRef::Array(streams) = reply else {
+            return vec![];
+        };
+        streams
+            .iter()
+            .map(|stream| {
+                let RedisValueRef::Array(stream) = stream else {
+                    panic!("unexpected {:?}", stream);
+                };
+                let RedisValueRef::Array(entries) = &stream[1] else {
+                    panic!("unexpected {:?}", stream);
+                };
+                let ids = entries
+                    .iter()
+                    .map(|entry| match entry {
+                        RedisValueRef::Array(entry) => text(&entry[0]),
+                        _ => panic!("unexpected {:?}", entry),
+                    })
+                    .collect();
+                (text(&stream[0]), ids)
+            })
+            .collect()
+    }
+
+    /// ID, consumer and delivery count of each entry XPENDING lists
+    fn pending(db: &mut Database, line: &str) -> Vec<(String, String, i64)> {
+        let RedisValueRef::Array(entries) = run(handle_xpending, line, db) else {
+            panic!("XPENDING replies an array");
+        };
+        entries
+            .iter()
+            .map(|entry| match entry {
+                RedisValueRef::Array(entry) => match &entry[..] {
+                    [id, consumer, _, RedisValueRef::Int(count)] => {
+                        (text(id), text(consumer), *count)
+                    }
+                    _ => panic!("unexpected {:?}", entry),
+                },
+                _ => panic!("unexpected {:?}", entry),
+            })
+            .collect()
+    }
+
+    fn owned(entries: &[(&str, &str, i64)]) -> Vec<(String, String, i64)> {
+        entries
+            .iter()
+            .map(|(id, consumer, count)| (id.to_string(), consumer.to_string(), *count))
+            .collect()
+    }
+
+    #[test]
+    fn read_group_checks_every_stream_first() {
+        let mut db = Database::new();
+        run(handle_xadd, "a 1-1 f v", &mut db);
+        run(handle_xadd, "b 1-1 f v", &mut db);
+        run(handle_xgroup, "CREATE a g 0", &mut db);
+
+        let no_group = |key: &str| CommandError::NoGroupForRead(key.to_string(), "g".to_string());
+        for (line, error) in [
+            ("GROUP g alice STREAMS a b > >", no_group("b")),
+            ("GROUP g alice STREAMS a missing > >", no_group("missing")),
+        ] {
+            assert_eq!(try_xreadgroup(&args(line), &mut db), Err(error));
+        }
+        run(handle_xgroup, "CREATE b g 0", &mut db);
+        assert_eq!(
+            try_xreadgroup(&args("GROUP g alice STREAMS a b > bad"), &mut db),
+            Err(CommandError::InvalidStreamId)
+        );
+
+        // Nothing was delivered by the failed calls
+        assert_eq!(pending(&mut db, "a g - + 10"), vec![]);
+        let reply = try_xreadgroup(&args("GROUP g alice STREAMS a b > >"), &mut db).unwrap();
+        assert_eq!(
+            read_ids(reply),
+            vec![
+                ("a".to_string(), vec!["1-1".to_string()]),
+                ("b".to_string(), vec!["1-1".to_string()]),
+            ]
+        );
+    }
+
+    #[test]
+    fn pending_entries_follow_reads_acks_and_claims() {
+        let mut db = Database::new();
+        for id in ["1-1", "2-1", "3-1"] {
+            run(handle_xadd, &format!("s {} f v", id), &mut db);
+        }
+        run(handle_xgroup, "CREATE s g 0", &mut db);
+
+        let read = |db: &mut Database, line: &str| {
+            read_ids(try_xreadgroup(&args(&format!("GROUP g {}", line)), db).unwrap())
+        };
+        let ids = |ids: &[&str]| {
+            vec![(
+                "s".to_string(),
+                ids.iter().map(|id| id.to_string()).collect(),
+            )]
+        };
+        assert_eq!(
+            read(&mut db, "alice COUNT 2 STREAMS s >"),
+            ids(&["1-1", "2-1"])
+        );
+        assert_eq!(read(&mut db, "bob STREAMS s >"), ids(&["3-1"]));
+        assert_eq!(
+            try_xreadgroup(&args("GROUP g bob STREAMS s >"), &mut db),
+            Ok(RedisValueRef::NullArray)
+        );
+        assert_eq!(
+            run(handle_xpending, "s g", &mut db),
+            RedisValueRef::Array(vec![
+                RedisValueRef::Int(3),
+                RedisValueRef::bulk_string("1-1"),
+                RedisValueRef::bulk_string("3-1"),
+                RedisValueRef::Array(vec![
+                    RedisValueRef::Array(vec![
+                        RedisValueRef::bulk_string("alice"),
+                        RedisValueRef::bulk_string("2"),
+                    ]),
+                    RedisValueRef::Array(vec![
+                        RedisValueRef::bulk_string("bob"),
+                        RedisValueRef::bulk_string("1"),
+                    ]),
+                ]),
+            ])
+        );
+
+        // Reading the history again counts as another delivery
+        assert_eq!(read(&mut db, "alice STREAMS s 0"), ids(&["1-1", "2-1"]));
+        assert_eq!(
+            pending(&mut db, "s g - + 10 alice"),
+            owned(&[("1-1", "alice", 2), ("2-1", "alice", 2)])
+        );
+
+        assert_eq!(
+            run(handle_xack, "s g 1-1 9-9", &mut db),
+            RedisValueRef::Int(1)
+        );
+        assert_eq!(run(handle_xack, "s g 1-1", &mut db), RedisValueRef::Int(0));
+        assert_eq!(read(&mut db, "alice STREAMS s 0"), ids(&["2-1"]));
+
+        // Entries idle for less than the minimum stay with their consumer
+        let RedisValueRef::Array(claimed) = run(handle_xclaim, "s g bob 3600000 2-1", &mut db)
+        else {
+            panic!("XCLAIM replies an array");
+        };
+        assert!(claimed.is_empty());
+        assert_eq!(
+            run(handle_xclaim, "s g bob 0 2-1 JUSTID", &mut db),
+            RedisValueRef::Array(vec![RedisValueRef::bulk_string("2-1")])
+        );
+        assert_eq!(
+            pending(&mut db, "s g - + 10"),
+            owned(&[("2-1", "bob", 3), ("3-1", "bob", 1)])
+        );
+
+        // Deleted entries read back as an ID and a null, and claiming one
+        // drops it from the PEL
+        run(handle_xdel, "s 3-1", &mut db);
+        let reply = try_xreadgroup(&args("GROUP g bob STREAMS s 2-1"), &mut db).unwrap();
+        assert_eq!(
+            reply,
+            RedisValueRef::Array(vec![RedisValueRef::Array(vec![
+                RedisValueRef::bulk_string("s"),
+                RedisValueRef::Array(vec![RedisValueRef::Array(vec![
+                    RedisValueRef::bulk_string("3-1"),
+                    RedisValueRef::NullArray,
+                ])]),
+            ])])
+        );
+        run(handle_xclaim, "s g alice 0 3-1", &mut db);
+        assert_eq!(pending(&mut db, "s g - + 10"), owned(&[("2-1", "bob", 3)]));
+
+        // NOACK deliveries never enter the PEL
+        run(handle_xadd, "s 4-1 f v", &mut db);
+        assert_eq!(read(&mut db, "carol NOACK STREAMS s >"), ids(&["4-1"]));
+        assert_eq!(pending(&mut db, "s g - + 10").len(), 1);
+    }
+}
@@ -0,0 +1,564 @@
+use bytes::{Bytes, BytesMut};
+
+use crate::{
+    arg_bytes, arg_float, arg_int, check_arity, current_time_millis, is_keyword, parse_float,
+    parse_int, CommandError, CommandResult, Database, LongDouble, RedisValueRef, SetExpiry,
+    SetObject, StoredValue,
+};
+
+/// Largest string a command may build, Redis' default proto-max-bulk-len
+pub const MAX_STRING_SIZE: usize = 512 * 1024 * 1024;
+
+/// INCR, DECR, INCRBY and DECRBY
+pub fn handle_incr(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    let by_amount = name.ends_with("by");
+    check_arity(
+        name,
+        args,
+        1 + by_amount as usize,
+        Some(1 + by_amount as usize),
+    )?;
+    let key = arg_bytes(&args[0])?;
+    let amount = if by_amount { arg_int(&args[1])? } else { 1 };
+    let delta = if name.starts_with("decr") {
+        amount.checked_neg().ok_or(CommandError::Overflow)?
+    } else {
+        amount
+    };
+
+    let current = match db.get_string(key)? {
+        Some(value) => parse_int(value).ok_or(CommandError::NotInteger)?,
+        None => 0,
+    };
+    let value = current.checked_add(delta).ok_or(CommandError::Overflow)?;
+    store_preserving_ttl(db, key, Bytes::from(value.to_string()));
+    Ok(RedisValueRef::Int(value))
+}
+
+/// INCRBYFLOAT key increment
+pub fn handle_incrbyfloat(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("incrbyfloat", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let increment = arg_float(&args[1])?;
+
+    let current = match db.get_string(key)? {
+        Some(value) => parse_float(value).ok_or(CommandError::NotFloat)?,
+        None => 0.0,
+    };
+    if !(current + increment).is_finite() {
+        return Err(CommandError::NanOrInfinity);
+    }
+    // Redis adds in long double precision and prints 17 fixed decimals with
+    // trailing zeros trimmed, so 0.1 + 0.2 is 0.3 and there is no exponent
+    let parse = |bytes: &[u8]| LongDouble::parse(bytes).ok_or(CommandError::NotFloat);
+    let current = match db.get_string(key)? {
+        Some(value) => parse(value)?,
+        None => LongDouble::ZERO,
+    };
+    let value = current + parse(arg_bytes(&args[1])?)?;
+    let value = Bytes::from(value.to_human_string());
+    store_preserving_ttl(db, key, value.clone());
+    Ok(RedisValueRef::String(value))
+}
+
+/// APPEND key value, returns the new length
+pub fn handle_append(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("append", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?;
+    let suffix = arg_bytes(&args[1])?;
+
+    let current = db.get_string(key)?.cloned().unwrap_or_default();
+    if current.len() + suffix.len() > MAX_STRING_SIZE {
+        return Err(CommandError::StringTooLong);
+    }
+    let mut value = BytesMut::with_capacity(current.len() + suffix.len());
+    value.extend_from_slice(&current);
+    value.extend_from_slice(suffix);
+    let len = value.len();
+    store_preserving_ttl(db, key, value.freeze());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// STRLEN key
+pub fn handle_strlen(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("strlen", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    let len = db.get_string(key)?.map_or(0, |value| value.len());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// GETRANGE key start end, both inclusive and negative from the end
+pub fn handle_getrange(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("getrange", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let start = arg_int(&args[1])?;
+    let end = arg_int(&args[2])?;
+
+    let value = db.get_string(key)?.cloned().unwrap_or_default();
+    match clamp_range(start, end, value.len()) {
+        Some((start, end)) => Ok(RedisValueRef::String(value.slice(start..=end))),
+        None => Ok(RedisValueRef::String(Bytes::new())),
+    }
+}
+
+/// SETRANGE key offset value, zero padding the string if needed
+pub fn handle_setrange(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("setrange", args, 3, Some(3))?;
+    let key = arg_bytes(&args[0])?;
+    let offset = arg_int(&args[1])?;
+    let patch = arg_bytes(&args[2])?;
+    if offset < 0 {
+        return Err(CommandError::OffsetOutOfRange);
+    }
+    let offset = offset as usize;
+
+    let current = db.get_string(key)?.cloned();
+    // Nothing to write never creates the key
+    if patch.is_empty() {
+        return Ok(RedisValueRef::Int(current.map_or(0, |v| v.len()) as i64));
+    }
+    if offset + patch.len() > MAX_STRING_SIZE {
+        return Err(CommandError::StringTooLong);
+    }
+
+    let current = current.unwrap_or_default();
+    let mut value = BytesMut::from(current.as_ref());
+    if value.len() < offset + patch.len() {
+        value.resize(offset + patch.len(), 0);
+    }
+    value[offset..offset + patch.len()].copy_from_slice(patch);
+    let len = value.len();
+    store_preserving_ttl(db, key, value.freeze());
+    Ok(RedisValueRef::Int(len as i64))
+}
+
+/// MGET key [key ...]
+pub fn handle_mget(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("mget", args, 1, None)?;
+    let mut values = Vec::with_capacity(args.len());
+    for key in args {
+        // Keys holding another type read as missing rather than failing
+        values.push(match db.get_string(arg_bytes(key)?) {
+            Ok(Some(value)) => RedisValueRef::String(value.clone()),
+            _ => RedisValueRef::NullBulkString,
+        });
+    }
+    Ok(RedisValueRef::Array(values))
+}
+
+/// MSET and MSETNX key value [key value ...]. The caller holds the database
+/// lock for the whole command, so every pair is applied atomically.
+pub fn handle_mset(name: &'static str, args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity(name, args, 2, None)?;
+    if args.len() % 2 == 1 {
+        return Err(CommandError::WrongArity(name));
+    }
+    let mut pairs = Vec::with_capacity(args.len() / 2);
+    for pair in args.chunks(2) {
+        pairs.push((arg_bytes(&pair[0])?.clone(), arg_bytes(&pair[1])?.clone()));
+    }
+
+    if name == "msetnx" {
+        for (key, _) in &pairs {
+            if db.exists(key) {
+                return Ok(RedisValueRef::Int(0));
+            }
+        }
+    }
+    for (key, value) in pairs {
+        db.set(key, value, SetExpiry::Persist);
+    }
+    match name {
+        "msetnx" => Ok(RedisValueRef::Int(1)),
+        _ => Ok(RedisValueRef::ok()),
+    }
+}
+
+/// GETSET key value, the value replaces the old one along with its TTL
+pub fn handle_getset(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("getset", args, 2, Some(2))?;
+    let key = arg_bytes(&args[0])?.clone();
+    let value = arg_bytes(&args[1])?.clone();
+    let old = db.get_string(&key)?.cloned();
+    db.set(key, value, SetExpiry::Persist);
+    match old {
+        Some(old) => Ok(RedisValueRef::String(old)),
+        None => Ok(RedisValueRef::NullBulkString),
+    }
+}
+
+/// GETDEL key
+pub fn handle_getdel(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("getdel", args, 1, Some(1))?;
+    let key = arg_bytes(&args[0])?;
+    match db.get_string(key)?.cloned() {
+        Some(value) => {
+            db.remove(key);
+            Ok(RedisValueRef::String(value))
+        }
+        None => Ok(RedisValueRef::NullBulkString),
+    }
+}
+
+/// GETEX key [EX seconds | PX milliseconds | EXAT unix-time-seconds |
+/// PXAT unix-time-milliseconds | PERSIST]
+pub fn handle_getex(args: &[RedisValueRef], db: &mut Database) -> CommandResult {
+    check_arity("getex", args, 1, None)?;
+    let key = arg_bytes(&args[0])?;
+
+    // None leaves the TTL alone, Some(None) removes it
+    let expiry = match &args[1..] {
+        [] => None,
+        [option] if is_keyword(option, "persist") => Some(None),
+        [option, amount] => {
+            let unit = ["ex", "px", "exat", "pxat"]
+                .into_iter()
+                .find(|unit| is_keyword(option, unit))
+                .ok_or(CommandError::Syntax)?;
+            Some(Some(expiry_millis("getex", unit, arg_int(amount)?)?))
+        }
+        _ => return Err(CommandError::Syntax),
+    };
+
+    let Some(value) = db.get_string(key)?.cloned() else {
+        return Ok(RedisValueRef::NullBulkString);
+    };
+    match expiry {
+        Some(Some(expires_at)) if expires_at <= current_time_millis() => {
+            db.remove(key);
+        }
+        Some(expires_at) => {
+            db.set_expiry(key, expires_at);
+        }
+        None => {}
+    }
+    Ok(RedisValueRef::String(value))
+}
+
+/// Turn an EX, PX, EXAT or PXAT option into the unix time in milliseconds a
+/// key expires at. `name` is the command reported in errors.
+pub fn expiry_millis(name: &'static str, unit: &str, amount: i64) -> Result<u64, CommandError> {
+    let invalid = CommandError::InvalidExpireTime(name);
+    if amount <= 0 {
+        return Err(invalid);
+    }
+    let millis = match unit {
+        "ex" | "exat" => amount.checked_mul(1000).ok_or(invalid.clone())?,
+        _ => amount,
+    } as u64;
+
+    match unit {
+        "ex" | "px" => current_time_millis().checked_add(millis).ok_or(invalid),
+        // A time in the past gives a key that is already expired
+        _ => Ok(millis),
+    }
+}
+
+/// Convert Redis style inclusive indexes, which may count from the end, into
+/// a valid inclusive range over `len` elements
+pub fn clamp_range(start: i64, end: i64, len: usize) -> Option<(usize, usize)> {
+    let len = len as i64;
+    let start = if start < 0 {
+        (len + start).max(0)
+    } else {
+        start
+    };
+    let end = if end < 0 { len + end } else { end.min(len - 1) };
+    if start > end || start >= len || end < 0 {
+        return None;
+    }
+    Some((start as usize, end as usize))
+}
+
+/// Replace the value of a key without touching its TTL, creating it if needed
+pub fn store_preserving_ttl(db: &mut Database, key: &Bytes, value: Bytes) {
+    match db.get_mut(key) {
+        Some(object) => {
+            object.value = StoredValue::String(value.into());
+            db.signal_modified(key);
+        }
+        None => {
+            db.insert(
+                key.clone(),
+                SetObject::new(StoredValue::String(value.into()), None),
+            );
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::args;
+
+    fn bulk(value: &'static str) -> CommandResult {
+        Ok(RedisValueRef::bulk_string(value))
+    }
+
+    fn set(db: &mut Database, key: &'static str, value: &'static str) {
+        db.set(Bytes::from(key), Bytes::from(value), SetExpiry::Persist);
+    }
+
+    #[test]
+    fn incr_family() {
+        let mut db = Database::new();
+        assert_eq!(
+            handle_incr("incr", &args("n"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(
+            handle_incr("incrby", &args("n 10"), &mut db),
+            Ok(RedisValueRef::Int(11))
+        );
+        assert_eq!(
+            handle_incr("decrby", &args("n 20"), &mut db),
+            Ok(RedisValueRef::Int(-9))
+        );
+        assert_eq!(
+            handle_incr("decr", &args("n"), &mut db),
+            Ok(RedisValueRef::Int(-10))
+        );
+
+        set(&mut db, "max", "9223372036854775807");
+        assert_eq!(
+            handle_incr("incr", &args("max"), &mut db),
+            Err(CommandError::Overflow)
+        );
+        assert_eq!(
+            handle_incr("decrby", &args("n -9223372036854775808"), &mut db),
+            Err(CommandError::Overflow)
+        );
+        for value in ["1.5", " 1", "01", "abc"] {
+            set(&mut db, "s", value);
+            assert_eq!(
+                handle_incr("incr", &args("s"), &mut db),
+                Err(CommandError::NotInteger)
+            );
+        }
+    }
+
+    #[test]
+    fn incrbyfloat_keeps_the_ttl() {
+        let mut db = Database::new();
+        assert_eq!(handle_incrbyfloat(&args("f 10.5"), &mut db), bulk("10.5"));
+        db.set_expiry(b"f", Some(current_time_millis() + 60_000));
+        assert_eq!(handle_incrbyfloat(&args("f 0.1"), &mut db), bulk("10.6"));
+        assert_eq!(handle_incrbyfloat(&args("f -5.6"), &mut db), bulk("5"));
+        set(&mut db, "k", "0.1");
+        assert_eq!(handle_incrbyfloat(&args("k 0.2"), &mut db), bulk("0.3"));
+        assert!(db.get(b"f").unwrap().expires_at.is_some());
+        // Large values never switch to exponent notation
+        assert_eq!(
+            handle_incrbyfloat(&args("big 5e20"), &mut db),
+            bulk("500000000000000000000")
+        );
+
+        assert_eq!(
+            handle_incrbyfloat(&args("f inf"), &mut db),
+            Err(CommandError::NanOrInfinity)
+        );
+        set(&mut db, "s", "abc");
+        assert_eq!(
+            handle_incrbyfloat(&args("s 1"), &mut db),
+            Err(CommandError::NotFloat)
+        );
+    }
+
+    #[test]
+    fn append_getrange_and_setrange() {
+        let mut db = Database::new();
+        assert_eq!(
+            handle_append(&args("k Hello"), &mut db),
+            Ok(RedisValueRef::Int(5))
+        );
+        assert_eq!(
+            handle_append(&args("k World"), &mut db),
+            Ok(RedisValueRef::Int(10))
+        );
+        assert_eq!(
+            handle_strlen(&args("k"), &mut db),
+            Ok(RedisValueRef::Int(10))
+        );
+        assert_eq!(handle_getrange(&args("k 0 4"), &mut db), bulk("Hello"));
+        assert_eq!(handle_getrange(&args("k -5 -1"), &mut db), bulk("World"));
+        assert_eq!(handle_getrange(&args("k 5 100"), &mut db), bulk("World"));
+        assert_eq!(handle_getrange(&args("k 6 2"), &mut db), bulk(""));
+        assert_eq!(handle_getrange(&args("missing 0 -1"), &mut db), bulk(""));
+
+        assert_eq!(
+            handle_setrange(&args("k 5 ,"), &mut db),
+            Ok(RedisValueRef::Int(10))
+        );
+        assert_eq!(db.get_string(b"k").unwrap().unwrap(), "Hello,orld");
+        assert_eq!(
+            handle_setrange(&args("pad 3 x"), &mut db),
+            Ok(RedisValueRef::Int(4))
+        );
+        assert_eq!(db.get_string(b"pad").unwrap().unwrap(), &b"\0\0\0x"[..]);
+        // An empty patch never creates the key
+        assert_eq!(
+            handle_setrange(
+                &[
+                    RedisValueRef::bulk_string("none"),
+                    RedisValueRef::bulk_string("9"),
+                    RedisValueRef::bulk_string("")
+                ],
+                &mut db
+            ),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert!(!db.exists(b"none"));
+        assert_eq!(
+            handle_setrange(&args("k -1 x"), &mut db),
+            Err(CommandError::OffsetOutOfRange)
+        );
+    }
+
+    #[test]
+    fn strings_are_capped_at_512mb() {
+        let mut db = Database::new();
+        let too_far = format!("k {} x", MAX_STRING_SIZE);
+        assert_eq!(
+            handle_setrange(&args(&too_far), &mut db),
+            Err(CommandError::StringTooLong)
+        );
+        assert!(!db.exists(b"k"));
+
+        // Zeroed memory is only mapped once written, so this stays cheap
+        let largest = Bytes::from(vec![0; MAX_STRING_SIZE]);
+        db.set(Bytes::from("k"), largest, SetExpiry::Persist);
+        assert_eq!(
+            handle_append(&args("k x"), &mut db),
+            Err(CommandError::StringTooLong)
+        );
+        let past_the_end = format!("k {} xy", MAX_STRING_SIZE - 1);
+        assert_eq!(
+            handle_setrange(&args(&past_the_end), &mut db),
+            Err(CommandError::StringTooLong)
+        );
+        assert_eq!(
+            handle_strlen(&args("k"), &mut db),
+            Ok(RedisValueRef::Int(MAX_STRING_SIZE as i64))
+        );
+    }
+
+    #[test]
+    fn mget_and_mset() {
+        let mut db = Database::new();
+        assert_eq!(
+            handle_mset("mset", &args("a 1 b 2"), &mut db),
+            Ok(RedisValueRef::ok())
+        );
+        db.insert(
+            Bytes::from("list"),
+            SetObject::new(StoredValue::List(Default::default()), None),
+        );
+        assert_eq!(
+            handle_mget(&args("a missing list b"), &mut db),
+            Ok(RedisValueRef::Array(vec![
+                RedisValueRef::bulk_string("1"),
+                RedisValueRef::NullBulkString,
+                RedisValueRef::NullBulkString,
+                RedisValueRef::bulk_string("2"),
+            ]))
+        );
+        assert_eq!(
+            handle_mset("mset", &args("a 1 b"), &mut db),
+            Err(CommandError::WrongArity("mset"))
+        );
+
+        // MSETNX sets nothing if any key exists
+        assert_eq!(
+            handle_mset("msetnx", &args("c 3 a 9"), &mut db),
+            Ok(RedisValueRef::Int(0))
+        );
+        assert!(!db.exists(b"c"));
+        assert_eq!(handle_getset(&args("a 1"), &mut db), bulk("1"));
+        assert_eq!(
+            handle_mset("msetnx", &args("c 3 d 4"), &mut db),
+            Ok(RedisValueRef::Int(1))
+        );
+        assert_eq!(handle_getdel(&args("d"), &mut db), bulk("4"));
+    }
+
+    #[test]
+    fn getset_and_getdel() {
+        let mut db = Database::new();
+        assert_eq!(
+            handle_getset(&args("k v1"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+        db.set_expiry(b"k", Some(current_time_millis() + 60_000));
+        assert_eq!(handle_getset(&args("k v2"), &mut db), bulk("v1"));
+        // The new value does not keep the old TTL
+        assert_eq!(db.get(b"k").unwrap().expires_at, None);
+
+        assert_eq!(handle_getdel(&args("k"), &mut db), bulk("v2"));
+        assert!(!db.exists(b"k"));
+        assert_eq!(
+            handle_getdel(&args("k"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+
+        db.insert(
+            Bytes::from("list"),
+            SetObject::new(StoredValue::List(Default::default()), None),
+        );
+        assert_eq!(
+            handle_getset(&args("list v"), &mut db),
+            Err(CommandError::WrongType)
+        );
+        assert_eq!(
+            handle_getdel(&args("list"), &mut db),
+            Err(CommandError::WrongType)
+        );
+        assert!(db.exists(b"list"));
+    }
+
+    #[test]
+    fn getex_options() {
+        let mut db = Database::new();
+        set(&mut db, "k", "v");
+        assert_eq!(handle_getex(&args("k"), &mut db), bulk("v"));
+        assert_eq!(db.get(b"k").unwrap().expires_at, None);
+
+        let now = current_time_millis();
+        assert_eq!(handle_getex(&args("k EX 100"), &mut db), bulk("v"));
+        let expires_at = db.get(b"k").unwrap().expires_at.unwrap();
+        assert!(expires_at >= now + 100_000 && expires_at <= now + 101_000);
+        assert_eq!(
+            handle_getex(&args("k pxat 99999999999999"), &mut db),
+            bulk("v")
+        );
+        assert_eq!(db.get(b"k").unwrap().expires_at, Some(99999999999999));
+        assert_eq!(handle_getex(&args("k PERSIST"), &mut db), bulk("v"));
+        assert_eq!(db.get(b"k").unwrap().expires_at, None);
+
+        // A time in the past deletes the key after replying
+        assert_eq!(handle_getex(&args("k EXAT 1"), &mut db), bulk("v"));
+        assert!(!db.exists(b"k"));
+        assert_eq!(
+            handle_getex(&args("k EX 10"), &mut db),
+            Ok(RedisValueRef::NullBulkString)
+        );
+
+        set(&mut db, "k", "v");
+        for bad in ["k EX", "k EX 10 PX 10", "k KEEPTTL", "k PERSIST 1"] {
+            assert_eq!(
+                handle_getex(&args(bad), &mut db),
+                Err(CommandError::Syntax),
+                "{}",
+                bad
+            );
+        }
+        assert_eq!(
+            handle_getex(&args("k EX 0"), &mut db),
+            Err(CommandError::InvalidExpireTime("getex"))
+        );
+        assert_eq!(
+            handle_getex(&args("k PX abc"), &mut db),
+            Err(CommandError::NotInteger)
+        );
+        assert_eq!(db.get(b"k").unwrap().expires_at, None);
+    }
+}
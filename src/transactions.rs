@@ -0,0 +1,79 @@
+use crate::{arg_bytes, check_arity, Client, CommandError, CommandResult, Database, RedisValueRef};
+
+/// Commands queued by a client between MULTI and EXEC
+#[derive(Debug, Default)]
+pub struct Transaction {
+    pub commands: Vec<(String, Vec<RedisValueRef>)>,
+    /// A command was rejected while queueing, so EXEC must fail
+    pub failed: bool,
+}
+
+/// MULTI
+pub fn handle_multi(args: &[RedisValueRef], client: &mut Client) -> CommandResult {
+    check_arity("multi", args, 0, Some(0))?;
+    if client.transaction.is_some() {
+        return Err(CommandError::MultiNested);
+    }
+    client.transaction = Some(Transaction::default());
+    Ok(RedisValueRef::ok())
+}
+
+/// DISCARD
+pub fn handle_discard(
+    args: &[RedisValueRef],
+    client: &mut Client,
+    db: &mut Database,
+) -> CommandResult {
+    check_arity("discard", args, 0, Some(0))?;
+    if client.transaction.take().is_none() {
+        return Err(CommandError::DiscardWithoutMulti);
+    }
+    unwatch_all(client, db);
+    Ok(RedisValueRef::ok())
+}
+
+/// WATCH key [key ...]
+pub fn handle_watch(
+    args: &[RedisValueRef],
+    client: &mut Client,
+    db: &mut Database,
+) -> CommandResult {
+    check_arity("watch", args, 1, None)?;
+    if client.transaction.is_some() {
+        return Err(CommandError::WatchInMulti);
+    }
+    for key in args {
+        let key = arg_bytes(key)?;
+        if client.watched.contains(key) {
+            continue;
+        }
+        // Reclaim a key that already expired, or it would count as
+        // modified when it is reclaimed later
+        db.expire_if_needed(key);
+        db.watch(client.id, key.clone());
+        client.watched.push(key.clone());
+    }
+    Ok(RedisValueRef::ok())
+}
+
+/// UNWATCH
+pub fn handle_unwatch(
+    args: &[RedisValueRef],
+    client: &mut Client,
+    db: &mut Database,
+) -> CommandResult {
+    check_arity("unwatch", args, 0, Some(0))?;
+    unwatch_all(client, db);
+    Ok(RedisValueRef::ok())
+}
+
+/// Forget every key a client watches, returns whether any of them was
+/// modified since it was watched. A watched key that expired in the meantime
+/// counts as modified.
+pub fn unwatch_all(client: &mut Client, db: &mut Database) -> bool {
+    for key in &client.watched {
+        db.expire_if_needed(key);
+    }
+    let keys = std::mem::take(&mut client.watched);
+    db.unwatch(client.id, &keys)
+}
@@ -1,24 +1,150 @@
-use std::{io::Write, net::TcpStream};
+use bytes::Bytes;
+use futures::SinkExt;
+use std::{
+    io, str,
+    time::{SystemTime, UNIX_EPOCH},
+};
+use tokio::{io::AsyncWriteExt, net::TcpStream};
+use tokio_util::codec::Framed;
 
-use crate::encode_bulk_string;
+use crate::{CommandError, RedisParser, RedisValueRef};
 
-pub fn write_response(response: &[u8], mut stream: &TcpStream) {
-    stream
-        .write_all(response)
-        .expect("failed to write to stream");
+/// Most bytes buffered for a client parked in a blocking command, Redis'
+/// default client-query-buffer-limit
+const QUERY_BUFFER_LIMIT: usize = 1024 * 1024 * 1024;
+
+pub async fn write_response(
+    response: RedisValueRef,
+    stream: &mut Framed<TcpStream, RedisParser>,
+) -> io::Result<()> {
+    stream.send(response).await
+}
+
+/// Write bytes that are not a RESP frame (e.g. an RDB payload) straight to the socket
+pub async fn write_raw(
+    response: &[u8],
+    stream: &mut Framed<TcpStream, RedisParser>,
+) -> io::Result<()> {
+    // Anything still buffered by the codec must go out first to keep ordering
+    stream.flush().await?;
+    stream.get_mut().write_all(response).await
+}
+
+/// Resolve once the peer closes the connection, while a command waits.
+/// Bytes that arrive first are kept in the codec's read buffer, so commands
+/// pipelined behind the waiting one still run afterwards. Reading stops once
+/// the buffer passes the query buffer limit, and `check_query_buffer` then
+/// closes the connection.
+pub async fn connection_closed(stream: &mut Framed<TcpStream, RedisParser>) {
+    let mut chunk = [0; 4096];
+    loop {
+        if stream.get_ref().readable().await.is_err() {
+            return;
+        }
+        match stream.get_ref().try_read(&mut chunk) {
+            Ok(0) => return,
+            Ok(n) => {
+                let buffer = stream.read_buffer_mut();
+                buffer.extend_from_slice(&chunk[..n]);
+                if buffer.len() > QUERY_BUFFER_LIMIT {
+                    return;
+                }
+            }
+            Err(e) if e.kind() == io::ErrorKind::WouldBlock => {}
+            Err(_) => return,
+        }
+    }
+}
+
+/// Like Redis, close the connection of a client that buffered more than the
+/// query buffer limit while it waited, without running any of it
+pub fn check_query_buffer(stream: &Framed<TcpStream, RedisParser>) -> io::Result<()> {
+    if stream.read_buffer().len() > QUERY_BUFFER_LIMIT {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "client reached the query buffer limit",
+        ));
+    }
+    Ok(())
+}
+
+/// Check the number of arguments a command was called with, excluding its name
+pub fn check_arity(
+    name: &'static str,
+    args: &[RedisValueRef],
+    min: usize,
+    max: Option<usize>,
+) -> Result<(), CommandError> {
+    if args.len() < min || max.is_some_and(|max| args.len() > max) {
+        return Err(CommandError::WrongArity(name));
+    }
+    Ok(())
+}
+
+/// Get the raw bytes of a command argument
+pub fn arg_bytes(arg: &RedisValueRef) -> Result<&Bytes, CommandError> {
+    match arg {
+        RedisValueRef::String(b) => Ok(b),
+        _ => Err(CommandError::InvalidRequest),
+    }
+}
+
+/// Get a command argument as UTF-8 text
+pub fn arg_str(arg: &RedisValueRef) -> Result<&str, CommandError> {
+    str::from_utf8(arg_bytes(arg)?).map_err(|_| CommandError::Syntax)
+}
+
+/// Parse a command argument as a signed 64 bit integer
+pub fn arg_int(arg: &RedisValueRef) -> Result<i64, CommandError> {
+    parse_int(arg_bytes(arg)?).ok_or(CommandError::NotInteger)
+}
+
+/// Parse a command argument as a double, rejecting NaN
+pub fn arg_float(arg: &RedisValueRef) -> Result<f64, CommandError> {
+    parse_float(arg_bytes(arg)?).ok_or(CommandError::NotFloat)
+}
+
+/// Parse an integer the strict way Redis does: no sign other than a leading
+/// `-`, no leading zeros and no surrounding spaces
+pub fn parse_int(bytes: &[u8]) -> Option<i64> {
+    let digits = bytes.strip_prefix(b"-").unwrap_or(bytes);
+    match digits {
+        [] => None,
+        [b'0'] if digits.len() != bytes.len() => None,
+        [b'0', _, ..] => None,
+        _ if !digits.iter().all(u8::is_ascii_digit) => None,
+        _ => str::from_utf8(bytes).ok()?.parse().ok(),
+    }
+}
+
+/// Parse a double, accepting `inf` and `-inf` but not NaN or spaces
+pub fn parse_float(bytes: &[u8]) -> Option<f64> {
+    let s = str::from_utf8(bytes).ok()?;
+    if s.starts_with(|c: char| c.is_ascii_whitespace())
+        || s.ends_with(|c: char| c.is_ascii_whitespace())
+    {
+        return None;
+    }
+    s.parse::<f64>().ok().filter(|f| !f.is_nan())
 }
 
-pub fn return_null(stream: &mut TcpStream) {
-    let response = b"$-1\r\n";
-    write_response(response, stream);
+/// Whether an argument matches a keyword, ignoring case
+pub fn is_keyword(arg: &RedisValueRef, keyword: &str) -> bool {
+    matches!(arg, RedisValueRef::String(b) if b.eq_ignore_ascii_case(keyword.as_bytes()))
 }
 
-pub fn return_ok(stream: &mut TcpStream) {
-    let response = b"+OK\r\n";
-    write_response(response, stream);
+/// Split a command line on whitespace into bulk string arguments
+#[cfg(test)]
+pub fn args(line: &str) -> Vec<RedisValueRef> {
+    line.split_whitespace()
+        .map(|arg| RedisValueRef::bulk_string(arg.to_string()))
+        .collect()
 }
 
-pub fn return_bulk_string(value: String, stream: &mut TcpStream) {
-    let response = encode_bulk_string(&value);
-    write_response(response.as_bytes(), stream)
+/// Current unix time in milliseconds
+pub fn current_time_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
 }